@@ -0,0 +1,176 @@
+# English messages, also the fallback for messages missing from other catalogs.
+#
+# Command help defaults to the texts in the command specs in src/commands.rs,
+# catalogs may override them with help-<command>, help-<command>-<argument>
+# and help-<command>--<flag>.
+
+language-name = English
+help-title = *[Payload dumper bot written in rust](https://github.com/kmiit/payload_dump_bot-rs)\.*
+help-usage = Usage:
+
+## Argument parsing
+
+usage = Usage: { $usage }
+arg-unknown-option = Unknown option --{ $name }
+arg-no-value = --{ $name } takes no value
+arg-needs-value = --{ $name } needs a { $value }
+arg-unexpected = Unexpected argument { $arg }
+arg-missing = Missing { $name }
+arg-dangling-escape = Dangling \ at the end
+arg-unclosed-quote = Unclosed { $quote } quote
+invalid-job-id = Invalid job id { $id }
+
+## Access control
+
+denied-user = Sorry, this bot is private and you are not allowed to use it.
+denied-command = Sorry, /{ $command } is disabled in this chat.
+denied-admin = This command is for admins only.
+
+## Jobs
+
+job-prefix = Job #{ $id }:
+job-cancelled = Job cancelled.
+job-not-found = Job #{ $id } not found, it may have finished already.
+job-hidden = (hidden)
+stage-queued = queued
+stage-resolving = resolving
+stage-manifest = manifest
+stage-extracting = extracting
+stage-patching = patching
+stage-uploading = uploading
+status-queued = Queued at position { $position }...
+status-resolving = Resolving { $url }...
+status-resolving-urls = Resolving urls...
+resolve-failed = Failed to resolve { $url }: { $error }
+cancel-button = Cancel
+
+## /dump
+
+partition-unsupported = Partition { $partition } is not supported!
+dump-dumping = Dumping { $partitions } from { $url }...
+dump-attached = Same dump already running as job #{ $id }, waiting for its files...
+dump-verifying = Verifying dumped partitions...
+dump-verify-failed = Failed to verify partitions: { $error }
+dump-no-files = No dumped file found.
+dump-uploading = Partitions dumped successfully! Uploading { $count } files...
+dump-failed = Failed to dump partitions: { $error }
+upload-done = All files uploaded successfully.
+upload-failed = Failed to upload file: { $error }
+link-disabled = Download links are not enabled on this bot.
+links-entry = { $name } ({ $size }): { $url }
+links-expire = Links expire in { $duration }.
+links-single-use = Links expire in { $duration } and work only once.
+links-sent = Download links sent.
+links-failed = Failed to publish files: { $error }
+
+## /list
+
+list-url = URL: { $url }
+list-fetch-failed = Error fetching image: { $error }
+list-one-format = Only one of --json, --csv and --format can be used
+list-expired = This list has expired, please /list again.
+reply-document-unsupported = { $name } is a file posted to Telegram, which the bot can't download. Reply to a message with its download URL instead.
+picker-select-first = Select at least one partition first.
+picker-dump-selected = Dump selected ({ $count })
+picker-patch-ksu = Patch { $partition } with KSU
+picker-prev = « Prev
+picker-next = Next »
+inline-summary = ROM summary
+inline-summary-details = Security patch { $patch }, { $count } partitions
+inline-failed = Error fetching image
+inline-busy = You already have jobs running, try again once they finish.
+
+## /diff
+
+diff-comparing = Comparing { $old } and { $new }...
+diff-failed = Error comparing images: { $error }
+
+## /patch
+
+patch-method-twice = Give the method either as argument or with --method
+patch-invalid-partition = Invalid partition { $partition }, use boot, init_boot or vendor_boot
+patch-invalid-method = Invalid method { $method }, use ksu or magisk
+patch-patching = Patching { $partition } with { $method } from { $url }
+patch-uploading = Patch { $partition } successfully, uploading...
+patch-not-found = Patched file { $path } not found!
+patch-failed = Failed to patch { $partition }: { $error }
+
+## /latest
+
+latest-found =
+    Device: { $codename }
+    Region: { $region }
+    Version: { $version }
+    Security patch level: { $patch }
+    Source: { $source }
+    URL: { $url }
+
+    Use /list, /dump or /patch with this URL.
+latest-none = No OTA found for { $codename }
+latest-failed = Failed to look up { $codename }: { $error }
+
+## /queue, /status, /cancel
+
+queue-running = Running ({ $count }):
+queue-queued = Queued ({ $count }):
+status-details =
+    Job #{ $id }
+    Kind: { $kind }
+    Stage: { $stage }
+    Arguments: { $arguments }
+    Submitted: { $age } ago
+status-running-for = Running for: { $duration }
+status-attached = Attached to job #{ $id }
+status-position = Queue position: { $position }
+cancel-not-owner = You can only cancel your own jobs.
+cancel-cancelling = Cancelling job #{ $id }...
+cancel-no-jobs = You have no running or queued jobs.
+
+## /history
+
+history-invalid-count = n must be a positive number
+history-admins-only = --all is for admins only
+history-empty = No jobs yet.
+history-failed = Failed to read job history: { $error }
+history-entry = #{ $id } { $created } UTC { $kind } { $outcome } in { $duration }
+history-user = User
+history-url = URL
+history-partitions = Partitions
+history-method = Method
+history-kmi = KMI
+history-hashes = Hashes
+history-error = Error
+outcome-succeeded = succeeded
+outcome-failed = failed
+outcome-cancelled = cancelled
+
+## /lang
+
+lang-current = Language of this chat: { $locale }. Available: { $available }
+lang-set = Language of this chat set to { $name }.
+lang-reset = Language reset, everyone gets their own Telegram language.
+lang-unknown = Unknown language { $locale }. Available: { $available }
+
+## Admin commands
+
+maintenance-active = The bot is under maintenance, new jobs are paused for now.
+maintenance-active-message = The bot is under maintenance, new jobs are paused: { $message }
+maintenance-on = Maintenance mode on, new jobs are paused.
+maintenance-off = Maintenance mode off, new jobs are accepted.
+reload-done = Configuration reloaded.
+reload-restart-needed = Changes to { $keys } apply after a restart.
+reload-failed = Failed to reload config: { $error }
+tools-entry = { $name }: { $version }
+tools-unknown-version = unknown version
+tools-updating = Updating { $tool }...
+tools-updated = Tools updated.
+tools-update-failed = Failed to update tools: { $error }
+tools-unavailable = Tools are unavailable: { $error }
+stats =
+    Uptime: { $uptime }
+    Jobs: { $running } running, { $queued } queued
+    Finished jobs: { $finished }
+    Downloaded: { $downloaded }
+    Uploaded: { $uploaded }
+    File cache: { $files } files, hit rate { $hit_rate }
+    Disk: tmp { $tmp }, database { $database }, tools { $tools }
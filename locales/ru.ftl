@@ -0,0 +1,185 @@
+# Русские сообщения, недостающие берутся из en.ftl.
+
+language-name = Русский
+help-title = *[Бот для извлечения разделов из payload на Rust](https://github.com/kmiit/payload_dump_bot-rs)\.*
+help-usage = Использование:
+
+help-dump = Извлечь раздел(ы) по ссылке
+help-dump-url = можно не указывать в ответе на сообщение со ссылкой
+help-dump-partitions = через запятую, например boot,vendor_boot
+help-dump--verify = сверить извлечённые образы с хешами из payload
+help-dump--link = прислать ссылки на скачивание вместо загрузки файлов
+help-list = Показать разделы по ссылке или выгрузить их в JSON/CSV
+help-list-url = можно не указывать в ответе на сообщение со ссылкой
+help-list--json = прислать список разделов файлом JSON
+help-list--csv = прислать список разделов файлом CSV
+help-list--format = формат выгрузки, json или csv
+help-list--sort = name, size или порядок в payload
+help-list--filter = только разделы, подходящие под маску
+help-list--min-size = только разделы не меньше указанного размера, например 64M
+help-diff = Сравнить разделы, уровень патча, отпечаток и ядро двух payload
+help-latest = Найти ссылку на последнюю OTA для устройства
+help-patch = Пропатчить загрузочный раздел
+help-patch-url = можно не указывать в ответе на сообщение со ссылкой
+help-patch-method = kernelsu(k, ksu), magisk(m), по умолчанию kernelsu
+help-patch--method = то же, что аргумент method
+help-patch--link = прислать ссылку на скачивание вместо загрузки образа
+help-queue = Показать выполняемые задачи и очередь
+help-status = Показать этап и место задачи в очереди
+help-cancel = Отменить задачу, по умолчанию последнюю вашу
+help-history = Показать ваши последние n задач
+help-history--all = задачи всех пользователей, только для администраторов
+help-lang = Язык сообщений в этом чате
+help-lang-language = код языка или auto, чтобы у каждого был язык его Telegram
+help-stats = Статистика задач, трафика, кэша и диска
+help-reload = Перечитать файл конфигурации
+help-tools = Показать версии ksud и magiskboot или обновить их
+help-tools-update = скачать последние релизы
+help-tools-tool = ksud или magiskboot, по умолчанию оба
+help-maintenance = Приостановить приём новых задач или возобновить его
+help-maintenance-state = on или off, без аргумента показывает текущее состояние
+help-maintenance-message = сообщение для пользователей на время паузы, в кавычках
+help-help = Показать эту справку.
+
+usage = Использование: { $usage }
+arg-unknown-option = Неизвестный параметр --{ $name }
+arg-no-value = --{ $name } не принимает значение
+arg-needs-value = --{ $name } требует значение { $value }
+arg-unexpected = Лишний аргумент { $arg }
+arg-missing = Не указан { $name }
+arg-dangling-escape = Одиночный \ в конце
+arg-unclosed-quote = Незакрытая кавычка { $quote }
+invalid-job-id = Неверный номер задачи { $id }
+
+denied-user = Извините, это закрытый бот, у вас нет доступа.
+denied-command = Извините, /{ $command } отключена в этом чате.
+denied-admin = Эта команда только для администраторов.
+
+job-prefix = Задача #{ $id }:
+job-cancelled = Задача отменена.
+job-not-found = Задача #{ $id } не найдена, возможно, она уже завершилась.
+job-hidden = (скрыто)
+stage-queued = в очереди
+stage-resolving = разбор ссылки
+stage-manifest = чтение манифеста
+stage-extracting = извлечение
+stage-patching = патчинг
+stage-uploading = отправка
+status-queued = В очереди, место { $position }...
+status-resolving = Разбираю ссылку { $url }...
+status-resolving-urls = Разбираю ссылки...
+resolve-failed = Не удалось разобрать ссылку { $url }: { $error }
+cancel-button = Отмена
+
+partition-unsupported = Раздел { $partition } не поддерживается!
+dump-dumping = Извлекаю { $partitions } из { $url }...
+dump-attached = Такой же дамп уже выполняет задача #{ $id }, ожидаю её файлы...
+dump-verifying = Проверяю извлечённые разделы...
+dump-verify-failed = Проверка разделов не прошла: { $error }
+dump-no-files = Извлечённые файлы не найдены.
+dump-uploading = Разделы извлечены! Отправляю файлов: { $count }...
+dump-failed = Не удалось извлечь разделы: { $error }
+upload-done = Все файлы отправлены.
+upload-failed = Не удалось отправить файл: { $error }
+link-disabled = Ссылки на скачивание в этом боте не включены.
+links-entry = { $name } ({ $size }): { $url }
+links-expire = Ссылки действуют { $duration }.
+links-single-use = Ссылки действуют { $duration } и только один раз.
+links-sent = Ссылки на скачивание отправлены.
+links-failed = Не удалось опубликовать файлы: { $error }
+
+list-url = Ссылка: { $url }
+list-fetch-failed = Ошибка чтения образа: { $error }
+list-one-format = Можно указать только одно из --json, --csv и --format
+list-expired = Этот список устарел, вызовите /list ещё раз.
+reply-document-unsupported = { $name } — файл, отправленный в Telegram, бот не может его скачать. Ответьте на сообщение со ссылкой для загрузки.
+picker-select-first = Сначала выберите хотя бы один раздел.
+picker-dump-selected = Извлечь выбранные ({ $count })
+picker-patch-ksu = Пропатчить { $partition } KSU
+picker-prev = « Назад
+picker-next = Далее »
+inline-summary = Сводка по прошивке
+inline-summary-details = Патч безопасности { $patch }, разделов: { $count }
+inline-failed = Ошибка при получении образа
+inline-busy = У вас уже выполняются задачи, попробуйте снова, когда они завершатся.
+
+diff-comparing = Сравниваю { $old } и { $new }...
+diff-failed = Ошибка сравнения образов: { $error }
+
+patch-method-twice = Укажите метод либо аргументом, либо через --method
+patch-invalid-partition = Неверный раздел { $partition }, используйте boot, init_boot или vendor_boot
+patch-invalid-method = Неверный метод { $method }, используйте ksu или magisk
+patch-patching = Патчу { $partition } методом { $method } из { $url }
+patch-uploading = { $partition } пропатчен, отправляю...
+patch-not-found = Пропатченный файл { $path } не найден!
+patch-failed = Не удалось пропатчить { $partition }: { $error }
+
+latest-found =
+    Устройство: { $codename }
+    Регион: { $region }
+    Версия: { $version }
+    Уровень патча безопасности: { $patch }
+    Источник: { $source }
+    Ссылка: { $url }
+
+    Используйте эту ссылку с /list, /dump или /patch.
+latest-none = Для { $codename } OTA не найдена
+latest-failed = Не удалось найти { $codename }: { $error }
+
+queue-running = Выполняются ({ $count }):
+queue-queued = В очереди ({ $count }):
+status-details =
+    Задача #{ $id }
+    Тип: { $kind }
+    Этап: { $stage }
+    Аргументы: { $arguments }
+    Создана: { $age } назад
+status-running-for = Выполняется: { $duration }
+status-attached = Присоединена к задаче #{ $id }
+status-position = Место в очереди: { $position }
+cancel-not-owner = Можно отменять только свои задачи.
+cancel-cancelling = Отменяю задачу #{ $id }...
+cancel-no-jobs = У вас нет выполняемых задач или задач в очереди.
+
+history-invalid-count = n должно быть положительным числом
+history-admins-only = --all доступен только администраторам
+history-empty = Задач пока не было.
+history-failed = Не удалось прочитать историю задач: { $error }
+history-entry = #{ $id } { $created } UTC { $kind } { $outcome } за { $duration }
+history-user = Пользователь
+history-url = Ссылка
+history-partitions = Разделы
+history-method = Метод
+history-kmi = KMI
+history-hashes = Хеши
+history-error = Ошибка
+outcome-succeeded = успешно
+outcome-failed = с ошибкой
+outcome-cancelled = отменена
+
+lang-current = Язык этого чата: { $locale }. Доступны: { $available }
+lang-set = Язык этого чата: { $name }.
+lang-reset = Язык сброшен, у каждого будет язык его Telegram.
+lang-unknown = Неизвестный язык { $locale }. Доступны: { $available }
+
+maintenance-active = Бот на обслуживании, новые задачи временно не принимаются.
+maintenance-active-message = Бот на обслуживании, новые задачи временно не принимаются: { $message }
+maintenance-on = Режим обслуживания включён, новые задачи приостановлены.
+maintenance-off = Режим обслуживания выключен, новые задачи принимаются.
+reload-done = Конфигурация перечитана.
+reload-restart-needed = Изменения { $keys } применятся после перезапуска.
+reload-failed = Не удалось перечитать конфигурацию: { $error }
+tools-entry = { $name }: { $version }
+tools-unknown-version = версия неизвестна
+tools-updating = Обновляю { $tool }...
+tools-updated = Инструменты обновлены.
+tools-update-failed = Не удалось обновить инструменты: { $error }
+tools-unavailable = Инструменты недоступны: { $error }
+stats =
+    Время работы: { $uptime }
+    Задачи: выполняется { $running }, в очереди { $queued }
+    Завершённые задачи: { $finished }
+    Скачано: { $downloaded }
+    Отправлено: { $uploaded }
+    Кэш файлов: { $files } файлов, попаданий { $hit_rate }
+    Диск: tmp { $tmp }, база { $database }, инструменты { $tools }
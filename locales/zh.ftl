@@ -0,0 +1,185 @@
+# 简体中文消息，缺少的消息使用 en.ftl。
+
+language-name = 简体中文
+help-title = *[使用 Rust 编写的 Payload 提取机器人](https://github.com/kmiit/payload_dump_bot-rs)*
+help-usage = 用法：
+
+help-dump = 从链接中提取分区
+help-dump-url = 回复包含链接的消息时可省略
+help-dump-partitions = 用逗号分隔，例如 boot,vendor_boot
+help-dump--verify = 用 payload 中的哈希校验提取出的镜像
+help-dump--link = 发送下载链接而不是上传文件
+help-list = 列出链接中的分区信息，或导出为 JSON/CSV 文件
+help-list-url = 回复包含链接的消息时可省略
+help-list--json = 以 JSON 文件发送分区信息
+help-list--csv = 以 CSV 文件发送分区信息
+help-list--format = 导出格式，json 或 csv
+help-list--sort = name、size 或 payload 顺序
+help-list--filter = 只显示匹配通配符的分区
+help-list--min-size = 只显示不小于该大小的分区，例如 64M
+help-diff = 比较两个 payload 的分区、安全补丁、指纹和内核
+help-latest = 查找设备最新的 OTA 链接
+help-patch = 修补启动分区
+help-patch-url = 回复包含链接的消息时可省略
+help-patch-method = kernelsu(k, ksu)、magisk(m)，默认 kernelsu
+help-patch--method = 同 method 参数
+help-patch--link = 发送下载链接而不是上传镜像
+help-queue = 显示运行中和排队中的任务
+help-status = 显示任务的阶段和排队位置
+help-cancel = 取消任务，默认取消你最近的任务
+help-history = 显示你最近的 n 个任务
+help-history--all = 显示所有用户的任务，仅限管理员
+help-lang = 设置本聊天的消息语言
+help-lang-language = 语言代码，或 auto 使用各自的 Telegram 语言
+help-stats = 显示任务、流量、缓存和磁盘统计
+help-reload = 重新读取配置文件
+help-tools = 显示或更新 ksud 和 magiskboot 的版本
+help-tools-update = 下载最新版本
+help-tools-tool = ksud 或 magiskboot，省略时更新两者
+help-maintenance = 暂停或恢复接收新任务
+help-maintenance-state = on 或 off，省略时显示当前状态
+help-maintenance-message = 维护期间向用户显示的消息，需加引号
+help-help = 显示此帮助。
+
+usage = 用法：{ $usage }
+arg-unknown-option = 未知选项 --{ $name }
+arg-no-value = --{ $name } 不接受值
+arg-needs-value = --{ $name } 需要一个 { $value }
+arg-unexpected = 多余的参数 { $arg }
+arg-missing = 缺少 { $name }
+arg-dangling-escape = 末尾有多余的 \
+arg-unclosed-quote = 引号 { $quote } 未闭合
+invalid-job-id = 无效的任务编号 { $id }
+
+denied-user = 抱歉，这是私有机器人，你没有使用权限。
+denied-command = 抱歉，本聊天已禁用 /{ $command }。
+denied-admin = 此命令仅限管理员使用。
+
+job-prefix = 任务 #{ $id }：
+job-cancelled = 任务已取消。
+job-not-found = 未找到任务 #{ $id }，可能已经完成。
+job-hidden = （已隐藏）
+stage-queued = 排队中
+stage-resolving = 解析链接
+stage-manifest = 读取清单
+stage-extracting = 提取中
+stage-patching = 修补中
+stage-uploading = 上传中
+status-queued = 排队中，第 { $position } 位...
+status-resolving = 正在解析 { $url }...
+status-resolving-urls = 正在解析链接...
+resolve-failed = 解析 { $url } 失败：{ $error }
+cancel-button = 取消
+
+partition-unsupported = 不支持分区 { $partition }！
+dump-dumping = 正在从 { $url } 提取 { $partitions }...
+dump-attached = 任务 #{ $id } 正在提取相同的分区，等待其文件...
+dump-verifying = 正在校验提取的分区...
+dump-verify-failed = 分区校验失败：{ $error }
+dump-no-files = 没有找到提取的文件。
+dump-uploading = 分区提取成功！正在上传 { $count } 个文件...
+dump-failed = 提取分区失败：{ $error }
+upload-done = 所有文件上传成功。
+upload-failed = 上传文件失败：{ $error }
+link-disabled = 此机器人未启用下载链接。
+links-entry = { $name }（{ $size }）：{ $url }
+links-expire = 链接将在 { $duration } 后失效。
+links-single-use = 链接将在 { $duration } 后失效，且只能使用一次。
+links-sent = 已发送下载链接。
+links-failed = 发布文件失败：{ $error }
+
+list-url = 链接：{ $url }
+list-fetch-failed = 读取镜像出错：{ $error }
+list-one-format = --json、--csv 和 --format 只能使用一个
+list-expired = 此列表已过期，请重新 /list。
+reply-document-unsupported = { $name } 是发送到 Telegram 的文件，机器人无法下载。请回复包含下载链接的消息。
+picker-select-first = 请先选择至少一个分区。
+picker-dump-selected = 提取所选（{ $count }）
+picker-patch-ksu = 用 KSU 修补 { $partition }
+picker-prev = « 上一页
+picker-next = 下一页 »
+inline-summary = ROM 概要
+inline-summary-details = 安全补丁 { $patch }，共 { $count } 个分区
+inline-failed = 获取镜像出错
+inline-busy = 你已有任务正在运行，请在完成后再试。
+
+diff-comparing = 正在比较 { $old } 和 { $new }...
+diff-failed = 比较镜像出错：{ $error }
+
+patch-method-twice = 请通过参数或 --method 之一指定修补方式
+patch-invalid-partition = 无效的分区 { $partition }，请使用 boot、init_boot 或 vendor_boot
+patch-invalid-method = 无效的修补方式 { $method }，请使用 ksu 或 magisk
+patch-patching = 正在用 { $method } 修补 { $url } 中的 { $partition }
+patch-uploading = { $partition } 修补成功，正在上传...
+patch-not-found = 未找到修补后的文件 { $path }！
+patch-failed = 修补 { $partition } 失败：{ $error }
+
+latest-found =
+    设备：{ $codename }
+    地区：{ $region }
+    版本：{ $version }
+    安全补丁级别：{ $patch }
+    来源：{ $source }
+    链接：{ $url }
+
+    可将此链接用于 /list、/dump 或 /patch。
+latest-none = 未找到 { $codename } 的 OTA
+latest-failed = 查找 { $codename } 失败：{ $error }
+
+queue-running = 运行中（{ $count }）：
+queue-queued = 排队中（{ $count }）：
+status-details =
+    任务 #{ $id }
+    类型：{ $kind }
+    阶段：{ $stage }
+    参数：{ $arguments }
+    提交于：{ $age } 前
+status-running-for = 已运行：{ $duration }
+status-attached = 已附加到任务 #{ $id }
+status-position = 排队位置：{ $position }
+cancel-not-owner = 你只能取消自己的任务。
+cancel-cancelling = 正在取消任务 #{ $id }...
+cancel-no-jobs = 你没有运行中或排队中的任务。
+
+history-invalid-count = n 必须是正整数
+history-admins-only = --all 仅限管理员使用
+history-empty = 还没有任务。
+history-failed = 读取任务历史失败：{ $error }
+history-entry = #{ $id } { $created } UTC { $kind } { $outcome }，用时 { $duration }
+history-user = 用户
+history-url = 链接
+history-partitions = 分区
+history-method = 方式
+history-kmi = KMI
+history-hashes = 哈希
+history-error = 错误
+outcome-succeeded = 成功
+outcome-failed = 失败
+outcome-cancelled = 已取消
+
+lang-current = 本聊天的语言：{ $locale }。可用：{ $available }
+lang-set = 本聊天的语言已设置为{ $name }。
+lang-reset = 已重置语言，每个人使用自己的 Telegram 语言。
+lang-unknown = 未知语言 { $locale }。可用：{ $available }
+
+maintenance-active = 机器人正在维护，暂不接收新任务。
+maintenance-active-message = 机器人正在维护，暂不接收新任务：{ $message }
+maintenance-on = 已开启维护模式，新任务已暂停。
+maintenance-off = 已关闭维护模式，恢复接收新任务。
+reload-done = 配置已重新加载。
+reload-restart-needed = { $keys } 的更改需重启后生效。
+reload-failed = 重新加载配置失败：{ $error }
+tools-entry = { $name }：{ $version }
+tools-unknown-version = 未知版本
+tools-updating = 正在更新 { $tool }...
+tools-updated = 工具已更新。
+tools-update-failed = 更新工具失败：{ $error }
+tools-unavailable = 工具不可用：{ $error }
+stats =
+    运行时间：{ $uptime }
+    任务：运行中 { $running }，排队中 { $queued }
+    已结束任务：{ $finished }
+    已下载：{ $downloaded }
+    已上传：{ $uploaded }
+    文件缓存：{ $files } 个文件，命中率 { $hit_rate }
+    磁盘：tmp { $tmp }，数据库 { $database }，工具 { $tools }
@@ -0,0 +1,121 @@
+use crate::config::Config;
+use crate::i18n::tr;
+use anyhow::Result;
+use log::warn;
+use std::collections::{HashMap, HashSet};
+use std::sync::{LazyLock, RwLock};
+
+/// `COMMAND_PERMISSIONS` key applying to chats without their own entry.
+const DEFAULT_CHAT: &str = "default";
+
+/// Current access rules, replaced by [`init`] on reload.
+static ACCESS: LazyLock<RwLock<Access>> = LazyLock::new(Default::default);
+
+/// Why a command was refused.
+#[derive(Debug)]
+pub enum Denied {
+    User,
+    Command(String),
+    /// Admin command sent by someone else.
+    Admin,
+}
+
+impl Denied {
+    /// Reply sent to the refused user.
+    pub fn message(&self) -> String {
+        match self {
+            Self::User => tr!("denied-user"),
+            Self::Command(command) => tr!("denied-command", command = command.as_str()),
+            Self::Admin => tr!("denied-admin"),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Access {
+    users: HashSet<u64>,
+    chats: HashSet<i64>,
+    admins: HashSet<u64>,
+    permissions: HashMap<String, HashMap<String, bool>>,
+}
+
+impl Access {
+    fn from(config: &Config) -> Result<Self> {
+        for chat in config.command_permissions.keys() {
+            if chat != DEFAULT_CHAT && chat.parse::<i64>().is_err() {
+                return Err(anyhow::anyhow!(
+                    "Invalid chat id in COMMAND_PERMISSIONS: {chat}"
+                ));
+            }
+        }
+        Ok(Self {
+            users: config.allowed_users.iter().copied().collect(),
+            chats: config.allowed_chats.iter().copied().collect(),
+            admins: config.admins.iter().copied().collect(),
+            permissions: config.command_permissions.clone(),
+        })
+    }
+
+    fn command_enabled(&self, chat: Option<i64>, command: &str) -> bool {
+        let chat = chat.map(|chat| chat.to_string());
+        chat.as_deref()
+            .into_iter()
+            .chain([DEFAULT_CHAT])
+            .find_map(|key| self.permissions.get(key)?.get(command).copied())
+            .unwrap_or(true)
+    }
+
+    fn check(&self, user: u64, chat: Option<i64>, command: &str) -> Result<(), Denied> {
+        if self.admins.contains(&user) {
+            return Ok(());
+        }
+        let restricted = !self.users.is_empty() || !self.chats.is_empty();
+        let in_chat = chat.is_some_and(|chat| self.chats.contains(&chat));
+        let place = chat.map_or("inline".to_string(), |chat| chat.to_string());
+        if restricted && !self.users.contains(&user) && !in_chat {
+            warn!("{place}: Refused /{command} from unauthorized user {user}");
+            return Err(Denied::User);
+        }
+        if !self.command_enabled(chat, command) {
+            warn!("{place}: Refused /{command} from {user}, disabled in this chat");
+            return Err(Denied::Command(command.to_string()));
+        }
+        Ok(())
+    }
+}
+
+pub fn init(config: &Config) -> Result<()> {
+    *ACCESS.write().unwrap() = Access::from(config)?;
+    Ok(())
+}
+
+pub fn admins() -> Vec<u64> {
+    ACCESS.read().unwrap().admins.iter().copied().collect()
+}
+
+pub fn is_admin(user: u64) -> bool {
+    ACCESS.read().unwrap().admins.contains(&user)
+}
+
+/// Checks whether `user` may run an admin command.
+pub fn check_admin(user: u64) -> Result<(), Denied> {
+    if is_admin(user) {
+        return Ok(());
+    }
+    warn!("Refused admin command from {user}");
+    Err(Denied::Admin)
+}
+
+/// Checks whether `user` may run `command` in `chat`.
+///
+/// With `ALLOWED_USERS` or `ALLOWED_CHATS` set, only listed users, and anyone
+/// in a listed chat, get through. Admins bypass every check.
+pub fn check(user: u64, chat: i64, command: &str) -> Result<(), Denied> {
+    ACCESS.read().unwrap().check(user, Some(chat), command)
+}
+
+/// Checks whether `user` may run `command` outside of a chat, as in inline
+/// queries. Only `ALLOWED_USERS` and the `default` permissions apply.
+pub fn check_user(user: u64, command: &str) -> Result<(), Denied> {
+    ACCESS.read().unwrap().check(user, None, command)
+}
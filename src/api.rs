@@ -0,0 +1,609 @@
+use crate::config::{self, Config};
+use crate::history::Outcome;
+use crate::jobs::{self, Stage};
+use crate::patch_boot::{PatchMethod, PatchPartition, patch_boot};
+use crate::payload::Dumped;
+use crate::utils::TempDir;
+use crate::{dumps, payload};
+use crate::{file_server, resolver, utils};
+use anyhow::{Context, Result, anyhow};
+use axum::extract::{Extension, Json, Path as UrlPath, Query};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Router, middleware};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, LazyLock, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// API jobs are shown without a chat.
+const API_CHAT: i64 = 0;
+/// Set in the user ids of API clients, which keeps them apart from Telegram user ids.
+const API_USER_BIT: u64 = 1 << 62;
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+/// Jobs submitted through the API, kept for `API_RESULT_TTL` after they finish.
+static JOBS: LazyLock<Mutex<HashMap<u64, ApiJob>>> = LazyLock::new(Default::default);
+
+struct Settings {
+    tokens: Vec<[u8; 32]>,
+    ttl: Duration,
+}
+
+/// The client behind a request, the user id its jobs run and are recorded as.
+#[derive(Clone, Copy, PartialEq)]
+struct Client(u64);
+
+impl Client {
+    /// Derives a stable id from the token digest, so each token gets its own
+    /// per-user limits and history.
+    fn from(digest: &[u8; 32]) -> Self {
+        let bytes = digest[..8].try_into().unwrap();
+        Self(u64::from_be_bytes(bytes) & (API_USER_BIT * 2 - 1) | API_USER_BIT)
+    }
+}
+
+/// Finds the configured token matching `token`. Compares digests of every
+/// token without returning early, so timing leaks neither length nor content.
+fn find_token(tokens: &[[u8; 32]], token: &str) -> Option<Client> {
+    let digest: [u8; 32] = Sha256::digest(token).into();
+    tokens.iter().fold(None, |found, allowed| {
+        let diff = allowed.iter().zip(&digest).fold(0, |d, (a, b)| d | (a ^ b));
+        match diff {
+            0 => Some(Client::from(allowed)),
+            _ => found,
+        }
+    })
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum JobRequest {
+    Dump {
+        url: String,
+        partitions: Vec<String>,
+        #[serde(default)]
+        verify: bool,
+    },
+    Patch {
+        url: String,
+        partition: String,
+        #[serde(default = "default_method")]
+        method: String,
+    },
+}
+
+fn default_method() -> String {
+    "ksu".to_string()
+}
+
+impl JobRequest {
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Dump { .. } => "dump",
+            Self::Patch { .. } => "patch",
+        }
+    }
+
+    /// Rejects requests that would fail before doing any work.
+    fn validate(&self) -> Result<()> {
+        match self {
+            Self::Dump { partitions, .. } => {
+                if partitions.is_empty() || partitions.iter().any(|p| p.is_empty()) {
+                    return Err(anyhow!("partitions must list at least one partition"));
+                }
+                let config = config::get();
+                let unsupported =
+                    config.unsupported_partitions(partitions.iter().map(|p| p.as_str()));
+                if !unsupported.is_empty() {
+                    return Err(anyhow!(
+                        "Partition {} is not supported",
+                        unsupported.join(", ")
+                    ));
+                }
+            }
+            Self::Patch {
+                partition, method, ..
+            } => {
+                PatchPartition::from(partition)?;
+                PatchMethod::from(method)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Arguments as the bot would show them.
+    fn description(&self) -> String {
+        match self {
+            Self::Dump {
+                url, partitions, ..
+            } => format!("{url} {}", partitions.join(",")),
+            Self::Patch {
+                url,
+                partition,
+                method,
+            } => format!("{url} {partition} {method}"),
+        }
+    }
+}
+
+struct ApiJob {
+    client: Client,
+    kind: &'static str,
+    finished: Option<(Instant, Finished)>,
+}
+
+enum Finished {
+    Succeeded(Output),
+    Failed(String),
+    Cancelled,
+}
+
+struct Output {
+    files: Vec<OutputFile>,
+    kmi: Option<String>,
+    kernel_version: Option<String>,
+    _files: OutputDir,
+}
+
+/// Keeps the files of an [`Output`] on disk until the job is dropped.
+enum OutputDir {
+    Dump { _dumped: Arc<Dumped> },
+    Patch { _dir: TempDir },
+}
+
+#[derive(Clone, Serialize)]
+struct OutputFile {
+    name: String,
+    size: u64,
+    hash: Option<String>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+#[derive(Serialize)]
+struct JobStatus {
+    id: u64,
+    kind: &'static str,
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stage: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    position: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    files: Vec<OutputFile>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kmi: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kernel_version: Option<String>,
+}
+
+/// Starts the REST API if `API_LISTEN` is set.
+pub fn init(config: &Config) -> Result<()> {
+    let Some(listen) = &config.api_listen else {
+        return Ok(());
+    };
+    let address: SocketAddr = listen.parse().context("Invalid API_LISTEN")?;
+    let _ = SETTINGS.set(Settings {
+        tokens: config
+            .api_tokens
+            .iter()
+            .filter(|t| !t.is_empty())
+            .map(|t| Sha256::digest(t).into())
+            .collect(),
+        ttl: Duration::from_secs(config.api_result_ttl),
+    });
+
+    info!("Serving the API on {address}");
+    let app = Router::new()
+        .route("/jobs", axum::routing::post(submit))
+        .route("/jobs/{id}", get(status).delete(cancel))
+        .route("/jobs/{id}/files/{name}", get(file))
+        .route("/list", get(list))
+        .layer(middleware::from_fn(authorize));
+    tokio::spawn(async move {
+        if let Err(e) = axum_server::bind(address)
+            .serve(app.into_make_service())
+            .await
+        {
+            error!("API server error: {e}");
+        }
+    });
+    tokio::spawn(async {
+        loop {
+            tokio::time::sleep(CLEANUP_INTERVAL).await;
+            let Some(settings) = SETTINGS.get() else {
+                continue;
+            };
+            JOBS.lock().unwrap().retain(
+                |_, job| !matches!(job.finished, Some((at, _)) if at.elapsed() >= settings.ttl),
+            );
+        }
+    });
+    Ok(())
+}
+
+fn error(status: StatusCode, message: impl Display) -> Response {
+    (status, Json(json!({ "error": message.to_string() }))).into_response()
+}
+
+/// Lets requests with one of `API_TOKENS` as bearer token through.
+async fn authorize(
+    headers: HeaderMap,
+    mut request: axum::extract::Request,
+    next: middleware::Next,
+) -> Response {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    let client = SETTINGS
+        .get()
+        .zip(token)
+        .and_then(|(s, t)| find_token(&s.tokens, t));
+    let Some(client) = client else {
+        warn!("Refused API request to {}", request.uri().path());
+        return error(StatusCode::UNAUTHORIZED, "Invalid or missing token");
+    };
+    request.extensions_mut().insert(client);
+    next.run(request).await
+}
+
+async fn submit(Extension(client): Extension<Client>, Json(request): Json<JobRequest>) -> Response {
+    if let Err(e) = request.validate() {
+        return error(StatusCode::BAD_REQUEST, e);
+    }
+    if let Some(refused) = maintenance() {
+        return refused;
+    }
+    let kind = request.kind();
+    let job = jobs::submit(client.0, API_CHAT, kind, &request.description());
+    let id = job.id;
+    info!("API: Submitted {kind} job #{id} for client {}", client.0);
+    JOBS.lock().unwrap().insert(
+        id,
+        ApiJob {
+            client,
+            kind,
+            finished: None,
+        },
+    );
+    tokio::spawn(jobs::scope(job, run(id, request)));
+    (StatusCode::ACCEPTED, Json(json!({ "id": id }))).into_response()
+}
+
+/// Refuses new jobs while in maintenance mode.
+fn maintenance() -> Option<Response> {
+    let message = jobs::maintenance()?;
+    let message = match message.is_empty() {
+        true => "Under maintenance".to_string(),
+        false => format!("Under maintenance: {message}"),
+    };
+    Some(error(StatusCode::SERVICE_UNAVAILABLE, message))
+}
+
+async fn run(id: u64, request: JobRequest) {
+    let result = match request {
+        JobRequest::Dump {
+            url,
+            partitions,
+            verify,
+        } => dump(url, partitions.join(","), verify).await,
+        JobRequest::Patch {
+            url,
+            partition,
+            method,
+        } => patch(url, partition, method).await,
+    };
+    let finished = match result {
+        Ok(output) => {
+            jobs::record(|r| r.finish(Outcome::Succeeded));
+            Finished::Succeeded(output)
+        }
+        Err(e) if e.is::<jobs::Cancelled>() => Finished::Cancelled,
+        Err(e) => {
+            error!("API job #{id} failed: {e}");
+            jobs::record(|r| r.finish(Outcome::Failed(e.to_string())));
+            Finished::Failed(e.to_string())
+        }
+    };
+    if let Some(job) = JOBS.lock().unwrap().get_mut(&id) {
+        job.finished = Some((Instant::now(), finished));
+    }
+}
+
+async fn dump(url: String, partition: String, verify: bool) -> Result<Output> {
+    jobs::record(|r| {
+        r.url = Some(url.clone());
+        r.partitions = Some(partition.clone());
+    });
+    jobs::set_stage(Stage::Resolving);
+    let url = resolver::resolve(&url).await?;
+    let shared = dumps::shared(
+        &url,
+        &partition,
+        || async {
+            if !jobs::wait_for_turn().await {
+                return Ok(None);
+            }
+            let dumped = payload::dump_partition(url.clone(), partition.clone())
+                .await
+                .map(Arc::new);
+            Ok(Some(dumped))
+        },
+        |_| async { Ok::<_, anyhow::Error>(()) },
+    )
+    .await?;
+    let Some((dumped, _lead)) = shared else {
+        return Err(jobs::Cancelled.into());
+    };
+    let dumped = dumped?;
+    let hashes = dumped
+        .files
+        .iter()
+        .map(|f| format!("{}={}", f.name, f.hash.as_deref().unwrap_or("N/A")))
+        .collect::<Vec<_>>()
+        .join(", ");
+    jobs::record(|r| r.hashes = Some(hashes));
+    if verify {
+        payload::verify_partitions(&dumped.files).await?;
+    }
+    let files = dumped
+        .files
+        .iter()
+        .map(|f| OutputFile {
+            name: f
+                .path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            size: f.size,
+            hash: f.hash.clone(),
+            path: f.path.clone(),
+        })
+        .collect();
+    Ok(Output {
+        files,
+        kmi: None,
+        kernel_version: None,
+        _files: OutputDir::Dump { _dumped: dumped },
+    })
+}
+
+async fn patch(url: String, partition: String, method: String) -> Result<Output> {
+    jobs::record(|r| {
+        r.url = Some(url.clone());
+        r.partitions = Some(partition.clone());
+        r.method = Some(method.clone());
+    });
+    if !jobs::wait_for_turn().await {
+        return Err(jobs::Cancelled.into());
+    }
+    jobs::set_stage(Stage::Resolving);
+    let url = resolver::resolve(&url).await?;
+    let patched = patch_boot(
+        url,
+        PatchPartition::from(&partition)?,
+        PatchMethod::from(&method)?,
+    )
+    .await?;
+    let name = patched
+        .path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let hash = utils::sha256_file(&patched.path)
+        .map_err(|e| warn!("Failed to hash {}: {e}", patched.path.display()))
+        .ok();
+    jobs::record(|r| {
+        r.kmi = Some(patched.kernel.kmi.clone());
+        r.hashes = hash.as_ref().map(|h| format!("{name}={h}"));
+    });
+    Ok(Output {
+        files: vec![OutputFile {
+            size: patched.path.metadata()?.len(),
+            name,
+            hash,
+            path: patched.path.clone(),
+        }],
+        kmi: Some(patched.kernel.kmi),
+        kernel_version: Some(patched.kernel.version),
+        _files: OutputDir::Patch { _dir: patched.dir },
+    })
+}
+
+async fn status(Extension(client): Extension<Client>, UrlPath(id): UrlPath<u64>) -> Response {
+    let jobs = JOBS.lock().unwrap();
+    let Some(job) = jobs.get(&id).filter(|job| job.client == client) else {
+        return error(StatusCode::NOT_FOUND, format!("Job {id} not found"));
+    };
+    let mut ret = JobStatus {
+        id,
+        kind: job.kind,
+        status: "queued",
+        stage: None,
+        position: None,
+        error: None,
+        files: Vec::new(),
+        kmi: None,
+        kernel_version: None,
+    };
+    match &job.finished {
+        Some((_, Finished::Succeeded(output))) => {
+            ret.status = "succeeded";
+            ret.files = output.files.clone();
+            ret.kmi = output.kmi.clone();
+            ret.kernel_version = output.kernel_version.clone();
+        }
+        Some((_, Finished::Failed(e))) => {
+            ret.status = "failed";
+            ret.error = Some(e.clone());
+        }
+        Some((_, Finished::Cancelled)) => ret.status = "cancelled",
+        None => {
+            if let Some(info) = jobs::get(id) {
+                ret.status = if info.is_running() {
+                    "running"
+                } else {
+                    "queued"
+                };
+                ret.stage = Some(info.stage.to_string());
+            }
+            ret.position = jobs::position(id);
+        }
+    }
+    Json(ret).into_response()
+}
+
+async fn cancel(Extension(client): Extension<Client>, UrlPath(id): UrlPath<u64>) -> Response {
+    let running = JOBS
+        .lock()
+        .unwrap()
+        .get(&id)
+        .filter(|job| job.client == client)
+        .map(|job| job.finished.is_none());
+    match running {
+        Some(true) if jobs::cancel(id) => {
+            info!("API: Cancelling job #{id}");
+            (StatusCode::ACCEPTED, Json(json!({ "id": id }))).into_response()
+        }
+        Some(_) => error(
+            StatusCode::CONFLICT,
+            format!("Job {id} has already finished"),
+        ),
+        None => error(StatusCode::NOT_FOUND, format!("Job {id} not found")),
+    }
+}
+
+async fn file(
+    Extension(client): Extension<Client>,
+    UrlPath((id, name)): UrlPath<(u64, String)>,
+) -> Response {
+    let found = JOBS
+        .lock()
+        .unwrap()
+        .get(&id)
+        .filter(|job| job.client == client)
+        .and_then(|job| match &job.finished {
+            Some((_, Finished::Succeeded(output))) => {
+                output.files.iter().find(|f| f.name == name).cloned()
+            }
+            _ => None,
+        });
+    let Some(found) = found else {
+        return error(StatusCode::NOT_FOUND, format!("No file {name} in job {id}"));
+    };
+    match tokio::fs::File::open(&found.path).await {
+        Ok(f) => file_server::download(f, &found.name, found.size),
+        Err(e) => error(StatusCode::NOT_FOUND, e),
+    }
+}
+
+/// Lists a ROM as a lookup job, answering once it is done like `/list` of the bot.
+async fn list(
+    Extension(client): Extension<Client>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Response {
+    let Some(url) = query.get("url").cloned() else {
+        return error(StatusCode::BAD_REQUEST, "Missing url");
+    };
+    if let Some(refused) = maintenance() {
+        return refused;
+    }
+    let job = jobs::submit(client.0, API_CHAT, "list", &url);
+    info!(
+        "API: Listing {url} as job #{} for client {}",
+        job.id, client.0
+    );
+    jobs::scope(job, async move {
+        let token = jobs::token();
+        let info = tokio::select! {
+            info = list_job(url) => info,
+            _ = token.cancelled() => Err(jobs::Cancelled.into()),
+        };
+        match info {
+            Ok(info) => {
+                jobs::record(|r| r.finish(Outcome::Succeeded));
+                Json(info).into_response()
+            }
+            Err(e) if e.is::<jobs::Cancelled>() => error(StatusCode::CONFLICT, "Job cancelled"),
+            Err(e) => {
+                jobs::record(|r| r.finish(Outcome::Failed(e.to_string())));
+                error(StatusCode::BAD_GATEWAY, e)
+            }
+        }
+    })
+    .await
+}
+
+async fn list_job(url: String) -> Result<payload::RomInfo> {
+    if !jobs::wait_for_turn().await {
+        return Err(jobs::Cancelled.into());
+    }
+    jobs::set_stage(Stage::Resolving);
+    jobs::record(|r| r.url = Some(url.clone()));
+    let url = resolver::resolve(&url).await?;
+    jobs::set_stage(Stage::Extracting);
+    jobs::record(|r| r.url = Some(url.clone()));
+    payload::get_rom_info(url).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(body: serde_json::Value) -> JobRequest {
+        serde_json::from_value(body).unwrap()
+    }
+
+    #[test]
+    fn rejects_invalid_requests_up_front() {
+        let url = "https://example.com/ota.zip";
+        let dump = |partitions: &[&str]| {
+            request(json!({ "kind": "dump", "url": url, "partitions": partitions })).validate()
+        };
+        assert!(dump(&["boot", "vendor_boot"]).is_ok());
+        assert!(dump(&[]).is_err());
+        assert!(dump(&["boot", ""]).is_err());
+        assert!(dump(&["super"]).is_err());
+
+        let patch = |partition: &str, method: &str| {
+            let body =
+                json!({ "kind": "patch", "url": url, "partition": partition, "method": method });
+            request(body).validate()
+        };
+        assert!(patch("init_boot", "ksu").is_ok());
+        assert!(patch("system", "ksu").is_err());
+        assert!(patch("boot", "apatch").is_err());
+        assert!(
+            request(json!({ "kind": "patch", "url": url, "partition": "boot" }))
+                .validate()
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn tokens_map_to_separate_clients() {
+        let tokens: Vec<[u8; 32]> = ["first", "second"]
+            .iter()
+            .map(|t| Sha256::digest(t).into())
+            .collect();
+        let first = find_token(&tokens, "first").unwrap();
+        let second = find_token(&tokens, "second").unwrap();
+        assert!(first != second);
+        assert!(first == find_token(&tokens, "first").unwrap());
+        assert!(first.0 & API_USER_BIT != 0 && (first.0 as i64) > 0);
+        assert!(find_token(&tokens, "firs").is_none());
+        assert!(find_token(&tokens, "").is_none());
+    }
+}
@@ -0,0 +1,374 @@
+use crate::i18n::{self, tr};
+use crate::markup::escape_md;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Positional argument of a command.
+pub struct Positional {
+    pub name: &'static str,
+    pub required: bool,
+    pub help: &'static str,
+}
+
+impl Positional {
+    pub const fn required(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            required: true,
+            help,
+        }
+    }
+
+    pub const fn optional(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            required: false,
+            help,
+        }
+    }
+}
+
+/// `--flag`, taking a value if `value` names one.
+pub struct Flag {
+    pub name: &'static str,
+    pub short: Option<char>,
+    pub value: Option<&'static str>,
+    pub help: &'static str,
+}
+
+impl Flag {
+    pub const fn switch(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            short: None,
+            value: None,
+            help,
+        }
+    }
+
+    pub const fn value(name: &'static str, value: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            short: None,
+            value: Some(value),
+            help,
+        }
+    }
+
+    /// Also accepts the flag as `-c`.
+    pub const fn short(mut self, c: char) -> Self {
+        self.short = Some(c);
+        self
+    }
+}
+
+/// Grammar of a command, used both to parse it and to document it.
+pub struct Spec {
+    pub command: &'static str,
+    pub help: &'static str,
+    pub positionals: &'static [Positional],
+    pub flags: &'static [Flag],
+}
+
+/// Parsed arguments of a command.
+#[derive(Default, Debug)]
+pub struct Args {
+    positionals: Vec<String>,
+    flags: HashMap<&'static str, Option<String>>,
+}
+
+impl Args {
+    pub fn get(&self, idx: usize) -> Option<&str> {
+        self.positionals.get(idx).map(|s| s.as_str())
+    }
+
+    pub fn flag(&self, name: &str) -> bool {
+        self.flags.contains_key(name)
+    }
+
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.flags.get(name)?.as_deref()
+    }
+}
+
+/// Invalid command line, shown to the user together with the usage.
+#[derive(Debug)]
+pub struct UsageError {
+    message: String,
+    usage: String,
+}
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}\n{}", self.message, self.usage)
+    }
+}
+
+impl UsageError {
+    /// The error without the usage.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl std::error::Error for UsageError {}
+
+impl Spec {
+    pub fn parse(&self, input: &str) -> Result<Args, UsageError> {
+        self.parse_tokens(tokenize(input).map_err(|e| self.error(e))?)
+    }
+
+    /// Parses arguments that are already split, such as the process arguments.
+    pub fn parse_tokens(&self, tokens: Vec<String>) -> Result<Args, UsageError> {
+        let mut args = Args::default();
+        let mut tokens = tokens.into_iter();
+        while let Some(token) = tokens.next() {
+            let short = self
+                .flags
+                .iter()
+                .find(|f| f.short.is_some_and(|c| token == format!("-{c}")));
+            let name = match short {
+                Some(flag) => flag.name,
+                None => match token.strip_prefix("--").filter(|n| !n.is_empty()) {
+                    Some(name) => name,
+                    None => {
+                        args.positionals.push(token);
+                        continue;
+                    }
+                },
+            };
+            let (name, inline) = match name.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (name, None),
+            };
+            let flag = self
+                .flags
+                .iter()
+                .find(|f| f.name == name)
+                .ok_or_else(|| self.error(tr!("arg-unknown-option", name = name)))?;
+            let value = match (flag.value, inline) {
+                (None, None) => None,
+                (None, Some(_)) => return Err(self.error(tr!("arg-no-value", name = name))),
+                (Some(_), Some(value)) => Some(value),
+                (Some(value), None) => Some(tokens.next().ok_or_else(|| {
+                    self.error(tr!("arg-needs-value", name = name, value = value))
+                })?),
+            };
+            args.flags.insert(flag.name, value);
+        }
+        if args.positionals.len() > self.positionals.len() {
+            let arg = args.positionals[self.positionals.len()].as_str();
+            return Err(self.error(tr!("arg-unexpected", arg = arg)));
+        }
+        if let Some(missing) = self
+            .positionals
+            .iter()
+            .skip(args.positionals.len())
+            .find(|p| p.required)
+        {
+            return Err(self.error(tr!("arg-missing", name = missing.name)));
+        }
+        Ok(args)
+    }
+
+    /// Error for this command that isn't caught by [`Spec::parse`].
+    pub fn error(&self, message: impl Into<String>) -> UsageError {
+        UsageError {
+            message: message.into(),
+            usage: tr!("usage", usage = self.usage()),
+        }
+    }
+
+    /// `/cmd [required] <optional> <--flag value>`
+    pub fn usage(&self) -> String {
+        let mut ret = format!("/{}", self.command);
+        for p in self.positionals {
+            match p.required {
+                true => ret.push_str(&format!(" [{}]", p.name)),
+                false => ret.push_str(&format!(" <{}>", p.name)),
+            }
+        }
+        for f in self.flags {
+            match f.value {
+                Some(value) => ret.push_str(&format!(" <--{} {value}>", f.name)),
+                None => ret.push_str(&format!(" <--{}>", f.name)),
+            }
+        }
+        ret
+    }
+
+    /// Help text of the command in the current locale.
+    pub fn description(&self) -> String {
+        i18n::translate(&format!("help-{}", self.command), None)
+            .unwrap_or_else(|| self.help.to_string())
+    }
+
+    /// MarkdownV2 help entry, quoted to fit in the help message.
+    ///
+    /// Texts are looked up in the catalogs as `help-<command>`,
+    /// `help-<command>-<argument>` and `help-<command>--<flag>`.
+    fn help_md(&self) -> String {
+        let code = |s: &str| format!("`{}`", s.replace('\\', "\\\\").replace('`', "\\`"));
+        let text = |key: String, default: &str| {
+            let key = key.replace(|c: char| !c.is_ascii_alphanumeric() && c != '-', "-");
+            escape_md(&i18n::translate(&key, None).unwrap_or_else(|| default.to_string()))
+        };
+        let mut ret = format!(
+            "> {}\n>   {}\n",
+            code(&self.usage()),
+            escape_md(&self.description())
+        );
+        for p in self.positionals.iter().filter(|p| !p.help.is_empty()) {
+            let help = text(format!("help-{}-{}", self.command, p.name), p.help);
+            ret.push_str(&format!(">    {}: {help}\n", code(p.name)));
+        }
+        for f in self.flags {
+            let help = text(format!("help-{}--{}", self.command, f.name), f.help);
+            ret.push_str(&format!(
+                ">    {}: {help}\n",
+                code(&format!("--{}", f.name))
+            ));
+        }
+        ret
+    }
+}
+
+/// Builds the MarkdownV2 help message from command specs, in the current locale.
+pub fn help_message(specs: &[&Spec]) -> String {
+    let entries = specs
+        .iter()
+        .map(|s| s.help_md())
+        .collect::<Vec<_>>()
+        .join(">\n");
+    format!(
+        "{}\n\n> **{}**\n{entries}",
+        tr!("help-title"),
+        escape_md(&tr!("help-usage"))
+    )
+}
+
+/// Quotes `s` so [`Spec::parse`] reads it back as a single argument.
+pub fn quote(s: &str) -> String {
+    if !s.is_empty() && !s.contains(|c: char| c.is_whitespace() || "\"'\\".contains(c)) {
+        return s.to_string();
+    }
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Splits `input` on whitespace, honoring single and double quotes and `\` escapes.
+fn tokenize(input: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut current: Option<String> = None;
+    let mut quote = None;
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"') | None, '\\') => {
+                let escaped = chars.next().ok_or_else(|| tr!("arg-dangling-escape"))?;
+                current.get_or_insert_default().push(escaped);
+            }
+            (Some(_), c) => current.get_or_insert_default().push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                current.get_or_insert_default();
+            }
+            (None, c) if c.is_whitespace() => tokens.extend(current.take()),
+            (None, c) => current.get_or_insert_default().push(c),
+        }
+    }
+    if let Some(q) = quote {
+        return Err(tr!("arg-unclosed-quote", quote = q.to_string()));
+    }
+    tokens.extend(current);
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPEC: Spec = Spec {
+        command: "dump",
+        help: "Dump partitions",
+        positionals: &[
+            Positional::required("url", "payload URL"),
+            Positional::optional("partitions", "comma separated"),
+        ],
+        flags: &[
+            Flag::switch("verify", "check hashes").short('v'),
+            Flag::value("format", "fmt", "output format"),
+        ],
+    };
+
+    #[test]
+    fn tokenizes_quotes_and_escapes() {
+        let tokenize = |s: &str| tokenize(s).unwrap();
+        assert_eq!(tokenize("  a  b\tc "), ["a", "b", "c"]);
+        assert_eq!(tokenize(r#""a b" 'c d'"#), ["a b", "c d"]);
+        assert_eq!(tokenize(r#"a\ b "say \"hi\"""#), ["a b", r#"say "hi""#]);
+        assert_eq!(tokenize(r#"'no \escape'"#), [r"no \escape"]);
+        assert_eq!(tokenize(r#"x"y z"w"#), ["xy zw"]);
+        assert_eq!(tokenize(r#""" ''"#), ["", ""]);
+        assert!(super::tokenize(r#"say "hi"#).is_err());
+        assert!(super::tokenize(r"trailing \").is_err());
+    }
+
+    #[test]
+    fn quote_round_trips() {
+        for s in ["plain", "", "a b", r#"say "hi""#, r"back\slash", "it's"] {
+            assert_eq!(tokenize(&quote(s)).unwrap(), [s], "{s}");
+        }
+        assert_eq!(quote("plain"), "plain");
+    }
+
+    #[test]
+    fn parses_positionals_and_flags() {
+        let args = SPEC
+            .parse("https://a/rom.zip boot,init_boot --format json -v")
+            .unwrap();
+        assert_eq!(args.get(0), Some("https://a/rom.zip"));
+        assert_eq!(args.get(1), Some("boot,init_boot"));
+        assert_eq!(args.get(2), None);
+        assert!(args.flag("verify"));
+        assert_eq!(args.value("format"), Some("json"));
+
+        let args = SPEC.parse("--format=csv https://a/rom.zip").unwrap();
+        assert_eq!(args.value("format"), Some("csv"));
+        assert_eq!(args.get(1), None);
+        assert!(!args.flag("verify"));
+        assert_eq!(args.value("verify"), None);
+
+        let args = SPEC
+            .parse_tokens(vec!["https://a/rom.zip".into(), "--verify".into()])
+            .unwrap();
+        assert!(args.flag("verify"));
+    }
+
+    #[test]
+    fn reports_usage_errors() {
+        let message = |input: &str| SPEC.parse(input).unwrap_err().message().to_string();
+        assert_eq!(message("url --bogus"), "Unknown option --bogus");
+        assert_eq!(message("url --verify=yes"), "--verify takes no value");
+        assert_eq!(message("url --format"), "--format needs a fmt");
+        assert_eq!(message("url boot extra"), "Unexpected argument extra");
+        assert_eq!(message("--verify"), "Missing url");
+        assert_eq!(message("'url"), "Unclosed ' quote");
+
+        let err = SPEC.parse("").unwrap_err();
+        assert!(err.to_string().ends_with(&SPEC.usage()), "{err}");
+    }
+
+    #[test]
+    fn generates_usage_and_help() {
+        assert_eq!(
+            SPEC.usage(),
+            "/dump [url] <partitions> <--verify> <--format fmt>"
+        );
+        assert_eq!(SPEC.description(), "Dump partitions");
+        let help = help_message(&[&SPEC]);
+        assert!(help.contains("`/dump [url] <partitions> <--verify> <--format fmt>`"));
+        assert!(help.contains("`url`: payload URL"));
+        assert!(help.contains("`--format`: output format"));
+    }
+}
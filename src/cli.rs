@@ -0,0 +1,257 @@
+use crate::args::{Args, Flag, Positional, Spec, UsageError};
+use crate::i18n::tr;
+use crate::patch_boot::{self, PatchMethod, PatchPartition};
+use crate::{commands, payload, tool};
+use anyhow::{Context, Result, anyhow};
+use payload_dumper::utils::format_size;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const BOT: Spec = Spec {
+    command: "bot",
+    help: "Run the Telegram bot, the default without a subcommand",
+    positionals: &[],
+    flags: &[],
+};
+
+const LIST: Spec = Spec {
+    command: "list",
+    help: "Print partition info of url, or export it as JSON/CSV",
+    positionals: &[Positional::required("url", "")],
+    flags: &[
+        Flag::switch("json", "print the partition info as JSON"),
+        Flag::switch("csv", "print the partition info as CSV"),
+        Flag::value("format", "format", "export format, json or csv"),
+        Flag::value("sort", "key", "name, size or payload order"),
+        Flag::value("filter", "glob", "only partitions matching the glob"),
+        Flag::value(
+            "min-size",
+            "size",
+            "only partitions at least this large, e.g. 64M",
+        ),
+    ],
+};
+
+const DUMP: Spec = Spec {
+    command: "dump",
+    help: "Dump partition(s) from url into a directory",
+    positionals: &[
+        Positional::required("url", ""),
+        Positional::required("partitions", "comma separated, e.g. boot,vendor_boot"),
+    ],
+    flags: &[
+        Flag::switch(
+            "verify",
+            "check the dumped images against the payload hashes",
+        ),
+        Flag::value("output", "dir", "where to write the images, default .").short('o'),
+    ],
+};
+
+const PATCH: Spec = Spec {
+    command: "patch",
+    help: "Patch a boot partition of url, or a local image of it",
+    positionals: &[
+        Positional::required("image", "url of the payload or path of the image"),
+        Positional::required("partition", "boot(b), init_boot(ib), vendor_boot(vb)"),
+        Positional::optional("method", "kernelsu(k, ksu), magisk(m), default kernelsu"),
+    ],
+    flags: &[
+        Flag::value("method", "method", "same as the method argument"),
+        Flag::value(
+            "boot",
+            "file",
+            "boot image to read the KMI from when patching another local image",
+        ),
+        Flag::value("output", "dir", "where to write the image, default .").short('o'),
+    ],
+};
+
+const KMI: Spec = Spec {
+    command: "kmi",
+    help: "Print the KMI and kernel version of a boot image",
+    positionals: &[Positional::required("boot.img", "")],
+    flags: &[],
+};
+
+/// Subcommands in the order they are documented.
+const SPECS: &[&Spec] = &[&BOT, &LIST, &DUMP, &PATCH, &KMI];
+
+/// What to do, from the process arguments.
+pub enum Command {
+    Bot,
+    Help,
+    Run(&'static Spec, Args),
+}
+
+/// Process arguments: the global `--config` and a subcommand.
+pub struct Cli {
+    pub config: Option<PathBuf>,
+    pub command: Command,
+}
+
+pub fn parse() -> Result<Cli> {
+    let mut args = std::env::args().skip(1);
+    let mut config = None;
+    let mut rest = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.strip_prefix("--config") {
+            Some("") => config = Some(args.next().context("--config needs a path")?.into()),
+            Some(value) if value.starts_with('=') => config = Some(value[1..].into()),
+            _ => rest.push(arg),
+        }
+    }
+    let mut rest = rest.into_iter();
+    let command = match rest.next().as_deref() {
+        None | Some("bot") => Command::Bot,
+        Some("help" | "--help" | "-h") => Command::Help,
+        Some(name) => {
+            let spec = SPECS
+                .iter()
+                .find(|s| s.command == name)
+                .ok_or_else(|| anyhow!("Unknown subcommand {name}\n\n{}", help()))?;
+            let args = spec
+                .parse_tokens(rest.collect())
+                .map_err(|e| usage_error(spec, e))?;
+            Command::Run(spec, args)
+        }
+    };
+    Ok(Cli { config, command })
+}
+
+/// Usage of every subcommand.
+pub fn help() -> String {
+    let mut ret = format!("Usage: {} [--config path] <subcommand>\n", binary());
+    for spec in SPECS {
+        ret.push_str(&format!("\n  {}\n      {}\n", usage(spec), spec.help));
+        for p in spec.positionals.iter().filter(|p| !p.help.is_empty()) {
+            ret.push_str(&format!("      {}: {}\n", p.name, p.help));
+        }
+        for f in spec.flags {
+            match f.short {
+                Some(c) => ret.push_str(&format!("      -{c}, --{}: {}\n", f.name, f.help)),
+                None => ret.push_str(&format!("      --{}: {}\n", f.name, f.help)),
+            }
+        }
+    }
+    ret
+}
+
+/// Runs a subcommand other than `bot`.
+pub async fn run(spec: &'static Spec, args: Args) -> Result<()> {
+    match spec.command {
+        "list" => list(args).await,
+        "dump" => dump(args).await,
+        "patch" => patch(args).await,
+        "kmi" => kmi(args).await,
+        command => Err(anyhow!("Unknown subcommand {command}")),
+    }
+}
+
+async fn list(args: Args) -> Result<()> {
+    let (export, options) =
+        commands::list_options(&LIST, &args).map_err(|e| usage_error(&LIST, e))?;
+    let url = args.get(0).unwrap_or_default().to_string();
+    match export {
+        Some(format) => std::io::stdout().write_all(&payload::export_image(url, format).await?)?,
+        None => println!("{}", payload::list_image(url, &options).await?.text),
+    }
+    Ok(())
+}
+
+async fn dump(args: Args) -> Result<()> {
+    let url = args.get(0).unwrap_or_default().to_string();
+    let partitions = args.get(1).unwrap_or_default().to_string();
+    let output = output_dir(&args)?;
+    let dumped = payload::dump_partition(url, partitions).await?;
+    if args.flag("verify") {
+        payload::verify_partitions(&dumped.files).await?;
+    }
+    for file in &dumped.files {
+        let dest = output.join(file.path.file_name().unwrap_or_default());
+        move_file(&file.path, &dest)?;
+        println!("{} ({})", dest.display(), format_size(file.size));
+    }
+    Ok(())
+}
+
+async fn patch(args: Args) -> Result<()> {
+    let image = args.get(0).unwrap_or_default();
+    let partition = args.get(1).unwrap_or_default();
+    let method = match (args.value("method"), args.get(2)) {
+        (Some(_), Some(_)) => {
+            return Err(usage_error(&PATCH, PATCH.error(tr!("patch-method-twice"))));
+        }
+        (Some(method), None) | (None, Some(method)) => method,
+        (None, None) => "ksu",
+    };
+    let partition = PatchPartition::from(partition)?;
+    let method = PatchMethod::from(method)?;
+    let output = output_dir(&args)?;
+    tool::ToolManager::new()?.init().await?;
+    let patched = match Path::new(image).is_file() {
+        true => {
+            let boot = args.value("boot").map(Path::new);
+            patch_boot::patch_image(Path::new(image), boot, partition, method).await?
+        }
+        false => patch_boot::patch_boot(image.to_string(), partition, method).await?,
+    };
+    let dest = output.join(patched.path.file_name().unwrap_or_default());
+    move_file(&patched.path, &dest)
+        .with_context(|| format!("Patched file {} not found", patched.path.display()))?;
+    println!(
+        "{}\nKMI: {}\nKernel version: {}",
+        dest.display(),
+        patched.kernel.kmi,
+        patched.kernel.version
+    );
+    Ok(())
+}
+
+async fn kmi(args: Args) -> Result<()> {
+    let image = Path::new(args.get(0).unwrap_or_default());
+    if !image.is_file() {
+        return Err(anyhow!("{} not found", image.display()));
+    }
+    tool::ToolManager::new()?.init().await?;
+    let kernel = patch_boot::read_kmi(image).await?;
+    println!("KMI: {}\nKernel version: {}", kernel.kmi, kernel.version);
+    Ok(())
+}
+
+/// `--output` directory, created if missing.
+fn output_dir(args: &Args) -> Result<PathBuf> {
+    let dir = PathBuf::from(args.value("output").unwrap_or("."));
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    Ok(dir)
+}
+
+/// Renames `from` to `to`, copying if they are on different filesystems.
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+    if fs::rename(from, to).is_err() {
+        fs::copy(from, to)?;
+        fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+fn binary() -> String {
+    std::env::args()
+        .next()
+        .and_then(|arg| {
+            Path::new(&arg)
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+        })
+        .unwrap_or_else(|| env!("CARGO_PKG_NAME").to_string())
+}
+
+/// `binary dump [url] [partitions] <--verify>`
+fn usage(spec: &Spec) -> String {
+    format!("{} {}", binary(), spec.usage().trim_start_matches('/'))
+}
+
+fn usage_error(spec: &Spec, e: UsageError) -> anyhow::Error {
+    anyhow!("{}\nUsage: {}", e.message(), usage(spec))
+}
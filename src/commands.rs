@@ -1,34 +1,201 @@
-use std::path::PathBuf;
-use crate::patch_boot::patch_boot;
-use crate::utils::to_tg_md;
-use crate::{config, payload};
+use crate::args::{Args, Flag, Positional, Spec, UsageError};
+use crate::history::Outcome;
+use crate::i18n::{self, tr};
+use crate::jobs::Stage;
+use crate::markup::{to_html_pre, to_tg_md};
+use crate::patch_boot::{PatchMethod, PatchPartition, patch_boot};
+use crate::tool::ToolManager;
+use crate::{
+    access, args, config, diff, dumps, file_cache, file_server, history, jobs, metrics, payload,
+    picker, proxy, resolver, rom_index, stats, url_policy, utils,
+};
 use anyhow::Result;
 use log::{debug, error, info, warn};
-use std::time::Duration;
+use payload_dumper::utils::format_size;
+use std::collections::HashMap;
+use std::future::IntoFuture;
+use std::path::Path;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
 use teloxide::macros::BotCommands;
-use teloxide::payloads::SendMessageSetters;
-use teloxide::prelude::{Message, ResponseResult};
+use teloxide::payloads::{
+    AnswerCallbackQuerySetters, AnswerInlineQuerySetters, EditMessageReplyMarkupSetters,
+    EditMessageTextSetters, SendMessageSetters, SetMyCommandsSetters,
+};
+use teloxide::prelude::{CallbackQuery, InlineQuery, Message, ResponseResult};
 use teloxide::requests::Requester;
 use teloxide::sugar::request::RequestReplyExt;
-use teloxide::types::{InputFile, InputMedia, InputMediaDocument, ParseMode};
-use teloxide::{Bot, RequestError};
-
-const HELP_MESSAGE: &str = r#"*[Payload dumper bot written in rust](https://github.com/kmiit/payload_dump_bot-rs)\.*
-
-> **Usage:**
-> `/dump \[url] \[partition1<,partition2,partition3\.\.\.>]`
->   Dump partition\(s\) from url
->
-> `/list \[url]`
->   List partition info of url
->
-> `/patch \[url] \[partition] \[method]`
->   Patch a boot partition
->    `partition`: boot\(b\), init\_boot\(ib\), vendor\_boot\(vb\)
->    `method`: kernelsu\(k, ksu\), magisk\(m\)
->
-> `/help`
->   Show this help msg\."#;
+use teloxide::types::{
+    BotCommand, BotCommandScope, ChatId, FileId, InlineKeyboardButton, InlineKeyboardMarkup,
+    InlineQueryResult, InlineQueryResultArticle, InlineQueryResultCachedDocument, InputFile,
+    InputMedia, InputMediaDocument, InputMessageContent, InputMessageContentText,
+    LinkPreviewOptions, MessageEntityKind, ParseMode, Recipient,
+};
+use teloxide::{ApiError, Bot, RequestError};
+
+/// Callback data prefix of the Cancel button, followed by the job id.
+const CANCEL_PREFIX: &str = "jc:";
+
+const DUMP: Spec = Spec {
+    command: "dump",
+    help: "Dump partition(s) from url",
+    positionals: &[
+        Positional::required("url", "omit it when replying to a message with the URL"),
+        Positional::required("partitions", "comma separated, e.g. boot,vendor_boot"),
+    ],
+    flags: &[
+        Flag::switch(
+            "verify",
+            "check the dumped images against the payload hashes",
+        ),
+        Flag::switch("link", "send download links instead of uploading the files"),
+    ],
+};
+
+const LIST: Spec = Spec {
+    command: "list",
+    help: "List partition info of url, or export it as a JSON/CSV file",
+    positionals: &[Positional::required(
+        "url",
+        "omit it when replying to a message with the URL",
+    )],
+    flags: &[
+        Flag::switch("json", "send the partition info as a JSON file"),
+        Flag::switch("csv", "send the partition info as a CSV file"),
+        Flag::value("format", "format", "export format, json or csv"),
+        Flag::value("sort", "key", "name, size or payload order"),
+        Flag::value("filter", "glob", "only partitions matching the glob"),
+        Flag::value(
+            "min-size",
+            "size",
+            "only partitions at least this large, e.g. 64M",
+        ),
+    ],
+};
+
+const DIFF: Spec = Spec {
+    command: "diff",
+    help: "Compare partitions, patch level, fingerprint and kernel of two payloads",
+    positionals: &[
+        Positional::required("url1", ""),
+        Positional::required("url2", ""),
+    ],
+    flags: &[],
+};
+
+const LATEST: Spec = Spec {
+    command: "latest",
+    help: "Find the latest OTA url of a device",
+    positionals: &[
+        Positional::required("codename", ""),
+        Positional::optional("region", ""),
+    ],
+    flags: &[],
+};
+
+const PATCH: Spec = Spec {
+    command: "patch",
+    help: "Patch a boot partition",
+    positionals: &[
+        Positional::required("url", "omit it when replying to a message with the URL"),
+        Positional::required("partition", "boot(b), init_boot(ib), vendor_boot(vb)"),
+        Positional::optional("method", "kernelsu(k, ksu), magisk(m), default kernelsu"),
+    ],
+    flags: &[
+        Flag::value("method", "method", "same as the method argument"),
+        Flag::switch(
+            "link",
+            "send a download link instead of uploading the image",
+        ),
+    ],
+};
+
+const QUEUE: Spec = Spec {
+    command: "queue",
+    help: "Show running and queued jobs",
+    positionals: &[],
+    flags: &[],
+};
+
+const STATUS: Spec = Spec {
+    command: "status",
+    help: "Show the stage and queue position of a job",
+    positionals: &[Positional::required("job id", "")],
+    flags: &[],
+};
+
+const CANCEL: Spec = Spec {
+    command: "cancel",
+    help: "Cancel a job, by default your latest one",
+    positionals: &[Positional::optional("job id", "")],
+    flags: &[],
+};
+
+const HISTORY: Spec = Spec {
+    command: "history",
+    help: "Show your last n jobs",
+    positionals: &[Positional::optional("n", "")],
+    flags: &[Flag::switch("all", "show every user's jobs, admins only")],
+};
+
+const LANG: Spec = Spec {
+    command: "lang",
+    help: "Set the language of messages in this chat",
+    positionals: &[Positional::optional(
+        "language",
+        "language code, or auto for everyone's own Telegram language",
+    )],
+    flags: &[],
+};
+
+const HELP: Spec = Spec {
+    command: "help",
+    help: "Show this help msg.",
+    positionals: &[],
+    flags: &[],
+};
+
+/// Commands in the order they are documented.
+const SPECS: &[&Spec] = &[
+    &DUMP, &LIST, &DIFF, &LATEST, &PATCH, &QUEUE, &STATUS, &CANCEL, &HISTORY, &LANG, &HELP,
+];
+
+const STATS: Spec = Spec {
+    command: "stats",
+    help: "Show job, traffic, cache and disk statistics",
+    positionals: &[],
+    flags: &[],
+};
+
+const RELOAD: Spec = Spec {
+    command: "reload",
+    help: "Re-read the config file",
+    positionals: &[],
+    flags: &[],
+};
+
+const TOOLS: Spec = Spec {
+    command: "tools",
+    help: "Show ksud and magiskboot versions, or update them",
+    positionals: &[
+        Positional::optional("update", "download the latest releases"),
+        Positional::optional("tool", "ksud or magiskboot, both if omitted"),
+    ],
+    flags: &[],
+};
+
+const MAINTENANCE: Spec = Spec {
+    command: "maintenance",
+    help: "Pause new jobs, or resume them",
+    positionals: &[
+        Positional::optional("state", "on or off, shows the current state if omitted"),
+        Positional::optional("message", "shown to users while on, quoted"),
+    ],
+    flags: &[],
+};
+
+/// Commands only admins may run, documented to admins only.
+const ADMIN_SPECS: &[&Spec] = &[&STATS, &RELOAD, &TOOLS, &MAINTENANCE];
 
 #[derive(BotCommands, Clone, Debug)]
 #[command(
@@ -44,48 +211,495 @@ pub enum Command {
     Patch { arg: String },
     #[command(description = "List images in the payload")]
     List { arg: String },
+    #[command(description = "Compare partitions of two payloads")]
+    Diff { arg: String },
+    #[command(description = "Find the latest OTA of a device")]
+    Latest { arg: String },
+    #[command(description = "Show running and queued jobs")]
+    Queue,
+    #[command(description = "Show the state of a job")]
+    Status { arg: String },
+    #[command(description = "Cancel a job")]
+    Cancel { arg: String },
+    #[command(description = "Show your recent jobs")]
+    History { arg: String },
+    #[command(description = "Set the language of this chat")]
+    Lang { arg: String },
+    #[command(description = "Show statistics")]
+    Stats,
+    #[command(description = "Reload the config")]
+    Reload,
+    #[command(description = "Show or update tools")]
+    Tools { arg: String },
+    #[command(description = "Pause or resume new jobs")]
+    Maintenance { arg: String },
     #[command(description = "Help cmd")]
     Help,
     #[command(description = "Start command")]
     Start,
 }
 
+impl Command {
+    /// Command name as typed, used for permission checks.
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Dump { .. } | Self::Dumper { .. } => "dump",
+            Self::Patch { .. } => "patch",
+            Self::List { .. } => "list",
+            Self::Diff { .. } => "diff",
+            Self::Latest { .. } => "latest",
+            Self::Queue => "queue",
+            Self::Status { .. } => "status",
+            Self::Cancel { .. } => "cancel",
+            Self::History { .. } => "history",
+            Self::Lang { .. } => "lang",
+            Self::Stats => "stats",
+            Self::Reload => "reload",
+            Self::Tools { .. } => "tools",
+            Self::Maintenance { .. } => "maintenance",
+            Self::Help => "help",
+            Self::Start => "start",
+        }
+    }
+}
+
+impl Command {
+    fn is_admin(&self) -> bool {
+        matches!(
+            self,
+            Self::Stats | Self::Reload | Self::Tools { .. } | Self::Maintenance { .. }
+        )
+    }
+
+    /// Commands submitting a job, refused during maintenance.
+    fn is_job(&self) -> bool {
+        matches!(
+            self,
+            Self::Dump { .. }
+                | Self::Dumper { .. }
+                | Self::Patch { .. }
+                | Self::Diff { .. }
+                | Self::List { .. }
+                | Self::Latest { .. }
+        )
+    }
+}
+
+/// Whether the sender of `msg` may run `cmd` in this chat.
+fn permission(msg: &Message, cmd: &Command) -> Result<(), access::Denied> {
+    match cmd {
+        Command::Help | Command::Start => Ok(()),
+        cmd if cmd.is_admin() => access::check_admin(sender_id(msg)),
+        cmd => access::check(sender_id(msg), msg.chat.id.0, cmd.name()),
+    }
+}
+
+/// Dispatcher filter letting through commands the sender may run in this chat.
+pub fn authorized(msg: Message, cmd: Command) -> bool {
+    permission(&msg, &cmd).is_ok()
+}
+
+/// Politely refuses a command rejected by [`authorized`].
+pub async fn refuse(bot: Bot, msg: Message, cmd: Command) -> ResponseResult<()> {
+    metrics::command(cmd.name());
+    if let Err(denied) = permission(&msg, &cmd) {
+        let text = i18n::scope(locale(&msg), async { denied.message() }).await;
+        bot.send_message(msg.chat.id, text).reply_to(msg.id).await?;
+    }
+    Ok(())
+}
+
 pub async fn answer(bot: Bot, msg: Message, cmd: Command) -> ResponseResult<()> {
-    tokio::spawn(async move {
+    metrics::command(cmd.name());
+    let locale = locale(&msg);
+    tokio::spawn(i18n::scope(locale, async move {
+        let user = sender_id(&msg);
+        if cmd.is_job()
+            && !access::is_admin(user)
+            && let Some(message) = jobs::maintenance()
+        {
+            let notice = maintenance_notice(&message);
+            if let Err(e) = bot.send_message(msg.chat.id, notice).reply_to(msg.id).await {
+                error!("Error sending maintenance notice: {e}");
+            }
+            return;
+        }
         match cmd {
             Command::Dump { arg } | Command::Dumper { arg } => {
-                if let Err(e) = dump_cmd(bot, msg, arg).await {
-                    error!("Error in dump_cmd: {e}");
+                let Some(arg) = reply_arg(&bot, &msg, arg).await else {
+                    return;
+                };
+                let job = jobs::submit(user, msg.chat.id.0, "dump", &arg);
+                if let Err(e) = jobs::scope(job, dump_cmd(bot, msg, arg)).await {
+                    report("dump_cmd", e);
                 }
             }
             Command::Patch { arg } => {
-                if let Err(e) = patch_cmd(bot, msg, arg).await {
-                    error!("Error in patch_cmd: {e}");
+                let Some(arg) = reply_arg(&bot, &msg, arg).await else {
+                    return;
+                };
+                let job = jobs::submit(user, msg.chat.id.0, "patch", &arg);
+                if let Err(e) = jobs::scope(job, patch_cmd(bot, msg, arg)).await {
+                    report("patch_cmd", e);
                 }
             }
             Command::List { arg } => {
-                if let Err(e) = list_cmd(bot, msg, arg).await {
-                    error!("Error in list_cmd: {e}");
+                let Some(arg) = reply_arg(&bot, &msg, arg).await else {
+                    return;
+                };
+                let job = jobs::submit(user, msg.chat.id.0, "list", &arg);
+                if let Err(e) = jobs::scope(job, list_cmd(bot, msg, arg)).await {
+                    report("list_cmd", e);
+                }
+            }
+            Command::Diff { arg } => {
+                let job = jobs::submit(user, msg.chat.id.0, "diff", &arg);
+                if let Err(e) = jobs::scope(job, diff_cmd(bot, msg, arg)).await {
+                    report("diff_cmd", e);
+                }
+            }
+            Command::Latest { arg } => {
+                let job = jobs::submit(user, msg.chat.id.0, "latest", &arg);
+                if let Err(e) = jobs::scope(job, latest_cmd(bot, msg, arg)).await {
+                    report("latest_cmd", e);
+                }
+            }
+            Command::Queue => {
+                if let Err(e) = queue_cmd(bot, msg).await {
+                    report("queue_cmd", e);
+                }
+            }
+            Command::Status { arg } => {
+                if let Err(e) = status_cmd(bot, msg, arg).await {
+                    report("status_cmd", e);
                 }
             }
-            Command::Help { .. } | Command::Start { .. } => {
+            Command::Cancel { arg } => {
+                if let Err(e) = cancel_cmd(bot, msg, arg).await {
+                    report("cancel_cmd", e);
+                }
+            }
+            Command::History { arg } => {
+                if let Err(e) = history_cmd(bot, msg, arg).await {
+                    report("history_cmd", e);
+                }
+            }
+            Command::Lang { arg } => {
+                if let Err(e) = lang_cmd(bot, msg, arg).await {
+                    report("lang_cmd", e);
+                }
+            }
+            Command::Stats => {
+                if let Err(e) = stats_cmd(bot, msg).await {
+                    report("stats_cmd", e);
+                }
+            }
+            Command::Reload => {
+                if let Err(e) = reload_cmd(bot, msg).await {
+                    report("reload_cmd", e);
+                }
+            }
+            Command::Tools { arg } => {
+                if let Err(e) = tools_cmd(bot, msg, arg).await {
+                    report("tools_cmd", e);
+                }
+            }
+            Command::Maintenance { arg } => {
+                if let Err(e) = maintenance_cmd(bot, msg, arg).await {
+                    report("maintenance_cmd", e);
+                }
+            }
+            Command::Help | Command::Start => {
                 if let Err(e) = help_cmd(bot, msg).await {
-                    error!("Error in help_cmd: {e}");
+                    report("help_cmd", e);
                 }
             }
         };
-    });
+    }));
+    Ok(())
+}
+
+/// Answers `@bot <url>` with the ROM summary and the partitions uploaded before.
+pub async fn inline(bot: Bot, q: InlineQuery) -> ResponseResult<()> {
+    let locale = i18n::locale_for(q.from.id.0 as i64, q.from.language_code.as_deref());
+    tokio::spawn(i18n::scope(locale, async move {
+        if let Err(e) = inline_query(bot, q).await {
+            report("inline_query", e);
+        }
+    }));
+    Ok(())
+}
+
+/// Pause before looking up an inline query, so typing doesn't fetch every prefix.
+const INLINE_DEBOUNCE: Duration = Duration::from_millis(700);
+/// How long the ROM info of an inline lookup is reused.
+const INLINE_CACHE_TTL: Duration = Duration::from_secs(600);
+const INLINE_CACHE_SIZE: usize = 64;
+
+/// Latest inline query of each user, older ones are dropped.
+static INLINE_LATEST: LazyLock<Mutex<HashMap<u64, String>>> = LazyLock::new(Default::default);
+/// When an inline lookup was made and what it found.
+type InlineEntry = (Instant, Arc<payload::RomInfo>);
+/// ROM info of recent inline lookups, by URL.
+static INLINE_CACHE: LazyLock<Mutex<HashMap<String, InlineEntry>>> =
+    LazyLock::new(Default::default);
+
+fn inline_cached(url: &str) -> Option<Arc<payload::RomInfo>> {
+    let mut cache = INLINE_CACHE.lock().unwrap();
+    cache.retain(|_, (added, _)| added.elapsed() < INLINE_CACHE_TTL);
+    cache.get(url).map(|(_, info)| info.clone())
+}
+
+fn inline_cache(url: &str, info: Arc<payload::RomInfo>) {
+    let mut cache = INLINE_CACHE.lock().unwrap();
+    if cache.len() >= INLINE_CACHE_SIZE {
+        let oldest = cache
+            .iter()
+            .min_by_key(|(_, (added, _))| *added)
+            .map(|(url, _)| url.clone());
+        cache.remove(&oldest.unwrap_or_default());
+    }
+    cache.insert(url.to_string(), (Instant::now(), info));
+}
+
+/// Waits out [`INLINE_DEBOUNCE`], returns false if the user sent a newer query meanwhile.
+async fn inline_debounce(user: u64, id: &str) -> bool {
+    INLINE_LATEST.lock().unwrap().insert(user, id.to_string());
+    tokio::time::sleep(INLINE_DEBOUNCE).await;
+    let mut latest = INLINE_LATEST.lock().unwrap();
+    if latest.get(&user).is_some_and(|latest| latest == id) {
+        latest.remove(&user);
+        return true;
+    }
+    false
+}
+
+fn inline_notice(id: &str, title: String, message: String) -> Vec<InlineQueryResult> {
+    let content = InputMessageContentText::new(message.clone());
+    vec![InlineQueryResult::Article(
+        InlineQueryResultArticle::new(id, title, InputMessageContent::Text(content))
+            .description(message),
+    )]
+}
+
+async fn inline_lookup(url: &str) -> Result<Arc<payload::RomInfo>> {
+    if let Some(info) = inline_cached(url) {
+        return Ok(info);
+    }
+    let info = Arc::new(payload::get_rom_info(resolver::resolve(url).await?).await?);
+    inline_cache(url, info.clone());
+    Ok(info)
+}
+
+/// Inline lookups skip the job queue, but are debounced, cached and refused
+/// while the user already runs `MAX_JOBS_PER_USER` jobs or maintenance is on.
+async fn inline_query(bot: Bot, q: InlineQuery) -> ResponseResult<()> {
+    let url = q.query.trim();
+    let user = q.from.id.0;
+    if !is_url(url) || access::check_user(user, "list").is_err() {
+        return bot.answer_inline_query(q.id, []).await.map(drop);
+    }
+    if !inline_debounce(user, &q.id.0).await {
+        return Ok(());
+    }
+    info!("{user}: Received inline query, url: {url}");
+    let admin = access::is_admin(user);
+    let results = match jobs::maintenance() {
+        Some(message) if !admin => {
+            let message = maintenance_notice(&message);
+            inline_notice("maintenance", tr!("inline-failed"), message)
+        }
+        _ if !admin && inline_cached(url).is_none() && jobs::user_busy(user) => {
+            inline_notice("busy", tr!("inline-failed"), tr!("inline-busy"))
+        }
+        _ => inline_results(url, inline_lookup(url).await),
+    };
+    bot.answer_inline_query(q.id, results)
+        .cache_time(60)
+        .is_personal(true)
+        .await
+        .map(drop)
+}
+
+fn inline_results(url: &str, info: Result<Arc<payload::RomInfo>>) -> Vec<InlineQueryResult> {
+    match info {
+        Ok(info) => {
+            let summary = payload::summarize(&info, &payload::ListOptions::default());
+            let text = to_html_pre(&format!("{}\n{}", tr!("list-url", url = url), summary.text));
+            let content = InputMessageContentText::new(text).parse_mode(ParseMode::Html);
+            let patch = info.security_patch_level.as_deref().unwrap_or("N/A");
+            let count = info.total_partitions;
+            let mut results = vec![InlineQueryResult::Article(
+                InlineQueryResultArticle::new(
+                    "summary",
+                    tr!("inline-summary"),
+                    InputMessageContent::Text(content),
+                )
+                .description(tr!(
+                    "inline-summary-details",
+                    patch = patch,
+                    count = count
+                )),
+            )];
+            for p in &info.partitions {
+                let Some(hash) = &p.hash else {
+                    continue;
+                };
+                let Some(file_id) = file_cache::get(hash) else {
+                    continue;
+                };
+                let document = InlineQueryResultCachedDocument::new(
+                    format!("p{}", results.len()),
+                    format!("{}.img", p.name),
+                    FileId(file_id),
+                )
+                .description(p.size_readable.clone())
+                .caption(partition_caption(&p.name, p.size_bytes, Some(hash)))
+                .parse_mode(ParseMode::MarkdownV2);
+                results.push(InlineQueryResult::CachedDocument(document));
+            }
+            results
+        }
+        Err(e) => {
+            let message = tr!("list-fetch-failed", error = e.to_string());
+            inline_notice("error", tr!("inline-failed"), message)
+        }
+    }
+}
+
+/// Handles buttons of the partition picker attached to `/list` replies,
+/// and the Cancel button of job status messages.
+pub async fn callback(bot: Bot, q: CallbackQuery) -> ResponseResult<()> {
+    let chat = q
+        .message
+        .as_ref()
+        .map_or(q.from.id.0 as i64, |m| m.chat().id.0);
+    let locale = i18n::locale_for(chat, q.from.language_code.as_deref());
+    i18n::scope(locale, handle_callback(bot, q, locale)).await
+}
+
+async fn handle_callback(bot: Bot, q: CallbackQuery, locale: &'static str) -> ResponseResult<()> {
+    if let Some(id) = q
+        .data
+        .as_deref()
+        .and_then(|d| d.strip_prefix(CANCEL_PREFIX))
+        .and_then(|id| id.parse().ok())
+    {
+        let text = cancel_job(q.from.id.0, id);
+        bot.answer_callback_query(q.id).text(text).await?;
+        return Ok(());
+    }
+    let Some((id, action)) = q.data.as_deref().and_then(picker::parse) else {
+        return Ok(());
+    };
+    let Some(msg) = q
+        .message
+        .as_ref()
+        .and_then(|m| m.regular_message())
+        .cloned()
+    else {
+        return Ok(());
+    };
+    match action {
+        picker::Action::Toggle(_) | picker::Action::Page(_) => match picker::update(id, &action) {
+            Some(keyboard) => {
+                bot.answer_callback_query(q.id).await?;
+                bot.edit_message_reply_markup(msg.chat.id, msg.id)
+                    .reply_markup(keyboard)
+                    .await?;
+            }
+            None => {
+                bot.answer_callback_query(q.id)
+                    .text(tr!("list-expired"))
+                    .await?;
+            }
+        },
+        picker::Action::Noop => {
+            bot.answer_callback_query(q.id).await?;
+        }
+        picker::Action::Dump | picker::Action::PatchKsu => {
+            let Some(session) = picker::get(id) else {
+                bot.answer_callback_query(q.id)
+                    .text(tr!("list-expired"))
+                    .await?;
+                return Ok(());
+            };
+            info!(
+                "{}: Picker action from {}, url: {}",
+                msg.chat.id, q.from.id, session.url
+            );
+            let command = match action {
+                picker::Action::Dump => "dump",
+                _ => "patch",
+            };
+            if let Err(denied) = access::check(q.from.id.0, msg.chat.id.0, command) {
+                bot.answer_callback_query(q.id)
+                    .text(denied.message())
+                    .await?;
+                return Ok(());
+            }
+            if let Some(message) = jobs::maintenance()
+                && !access::is_admin(q.from.id.0)
+            {
+                bot.answer_callback_query(q.id)
+                    .text(maintenance_notice(&message))
+                    .show_alert(true)
+                    .await?;
+                return Ok(());
+            }
+            if let picker::Action::Dump = action {
+                let selected = session.selected_names();
+                if selected.is_empty() {
+                    bot.answer_callback_query(q.id)
+                        .text(tr!("picker-select-first"))
+                        .await?;
+                    return Ok(());
+                }
+                bot.answer_callback_query(q.id).await?;
+                let arg = format!("{} {}", session.url, selected.join(","));
+                let job = jobs::submit(q.from.id.0, msg.chat.id.0, "dump", &arg);
+                tokio::spawn(i18n::scope(locale, async move {
+                    if let Err(e) = jobs::scope(job, dump_cmd(bot, msg, arg)).await {
+                        report("dump_cmd", e);
+                    }
+                }));
+            } else if let Some(partition) = session.ksu_partition() {
+                bot.answer_callback_query(q.id).await?;
+                let arg = format!("{} {partition} ksu", session.url);
+                let job = jobs::submit(q.from.id.0, msg.chat.id.0, "patch", &arg);
+                tokio::spawn(i18n::scope(locale, async move {
+                    if let Err(e) = jobs::scope(job, patch_cmd(bot, msg, arg)).await {
+                        report("patch_cmd", e);
+                    }
+                }));
+            }
+        }
+    }
     Ok(())
 }
 
 async fn dump_cmd(bot: Bot, msg: Message, arg: String) -> Result<Message, RequestError> {
-    let cmd: Vec<&str> = arg.split_whitespace().collect();
-    if cmd.len() != 2 {
-        warn!("{}: Dump: Invalid command: {arg}", msg.chat.id);
+    let args = match DUMP.parse(&arg) {
+        Ok(args) => args,
+        Err(e) => return usage_error(&bot, &msg, e).await,
+    };
+    if args.flag("link") && !file_server::enabled() {
+        return usage_error(&bot, &msg, DUMP.error(tr!("link-disabled"))).await;
+    }
+    let config = config::get();
+    let url = args.get(0).unwrap_or_default().to_string();
+    let partition = args.get(1).unwrap_or_default().to_string();
+    let unsupported_partitions = config.unsupported_partitions(partition.split(','));
+    if !unsupported_partitions.is_empty() {
+        let unsupported = unsupported_partitions.join(", ");
+        warn!(
+            "{}: Dump: Partition {unsupported} is not supported",
+            msg.chat.id
+        );
         let msg = bot
             .send_message(
                 msg.chat.id,
-                "Invalid command! Usage: /dump <url> <partition1,partition2,...>",
+                tr!("partition-unsupported", partition = unsupported.as_str()),
             )
             .reply_to(msg.id)
             .await?;
@@ -93,36 +707,6 @@ async fn dump_cmd(bot: Bot, msg: Message, arg: String) -> Result<Message, Reques
         bot.delete_message(msg.chat.id, msg.id).await?;
         return Ok(msg);
     }
-    let config = config::load_config().unwrap_or_default();
-    let url = cmd[0].to_string();
-    let partition = cmd[1].to_string();
-    let mut unsupported_partitions: Vec<String> = Vec::new();
-    let partitions = partition.split(',').collect::<Vec<_>>();
-    if !config.supported_partitions.is_empty() {
-        let _ = partitions.iter().map(|p| {
-            if config.supported_partitions.contains(&p.to_string()) {
-                unsupported_partitions.push(p.to_string());
-            }
-        });
-
-        if !unsupported_partitions.is_empty() {
-            warn!(
-                "{}: Dump: Partition {} is not supported",
-                msg.chat.id,
-                unsupported_partitions.join(", ")
-            );
-            let msg = bot
-                .send_message(
-                    msg.chat.id,
-                    format!("Partition {partition} is not supported!"),
-                )
-                .reply_to(msg.id)
-                .await?;
-            tokio::time::sleep(Duration::from_secs(10)).await;
-            bot.delete_message(msg.chat.id, msg.id).await?;
-            return Ok(msg);
-        }
-    }
     info!(
         "{}: Received dump command, url: {url}, partition: {partition}",
         msg.chat.id
@@ -130,41 +714,93 @@ async fn dump_cmd(bot: Bot, msg: Message, arg: String) -> Result<Message, Reques
     debug!(
         "{}: Sender: {}, chat_id: {}",
         msg.id,
-        msg.from.unwrap().id,
+        sender_id(&msg),
         msg.chat.id
     );
     let status_msg = bot
-        .send_message(msg.chat.id, format!("Dumping {partition}..."))
+        .send_message(
+            msg.chat.id,
+            job_prefix() + &tr!("status-resolving", url = url.as_str()),
+        )
+        .reply_markup(cancel_keyboard())
         .reply_to(msg.id)
         .await?;
-    match payload::dump_partition(url, partition).await {
-        Ok((files, temp_dir)) => {
+    jobs::record(|r| {
+        r.url = Some(url.clone());
+        r.partitions = Some(partition.clone());
+    });
+    jobs::set_stage(Stage::Resolving);
+    let Some(url) = resolve_url(&bot, &status_msg, &url).await? else {
+        return Ok(status_msg);
+    };
+    let shared = dumps::shared(
+        &url,
+        &partition,
+        || lead_dump(&bot, &status_msg, &url, &partition),
+        |leader| {
+            info!(
+                "{}: Attaching to job #{leader} dumping the same partitions",
+                msg.chat.id
+            );
+            let text = job_prefix() + &tr!("dump-attached", id = leader);
+            let (bot, status_msg) = (&bot, &status_msg);
+            async move { set_status(bot, status_msg, text).await.map(drop) }
+        },
+    )
+    .await?;
+    // The lead is held until the upload is done, so later requests still attach.
+    let Some((dumped, _lead)) = shared else {
+        return Ok(status_msg);
+    };
+    match dumped {
+        Ok(dumped) => {
+            let files = &dumped.files;
             let num_files = files.len();
             info!(
                 "Successfully dumped {num_files} files to {}",
-                temp_dir.display()
+                dumped.dir.path().display()
             );
 
-            if num_files == 0 {
-                bot.send_message(msg.chat.id, "No dumped file found.")
-                    .await?;
+            let hashes = files
+                .iter()
+                .map(|f| format!("{}={}", f.name, f.hash.as_deref().unwrap_or("N/A")))
+                .collect::<Vec<_>>()
+                .join(", ");
+            jobs::record(|r| r.hashes = Some(hashes));
+            let verified = if args.flag("verify") {
+                set_status(&bot, &status_msg, tr!("dump-verifying")).await?;
+                payload::verify_partitions(files).await
             } else {
+                Ok(())
+            };
+            if let Err(e) = verified {
+                finish(Outcome::Failed(e.to_string()));
+                error!("Failed to verify partitions: {e}");
                 bot.edit_message_text(
                     status_msg.chat.id,
                     status_msg.id,
-                    format!("Partitions dumped successfully! Uploading {num_files} files...",),
+                    tr!("dump-verify-failed", error = e.to_string()),
                 )
                 .await?;
+            } else if num_files == 0 {
+                finish(Outcome::Failed("No dumped file found".to_string()));
+                bot.send_message(msg.chat.id, tr!("dump-no-files")).await?;
+            } else if args.flag("link") {
+                let paths = files.iter().map(|f| f.path.as_path());
+                send_links(&bot, &msg, &status_msg, paths).await?;
+            } else {
+                set_status(&bot, &status_msg, tr!("dump-uploading", count = num_files)).await?;
                 let mut caption = String::new();
                 for path in files.iter().clone() {
-                    caption.push_str(&format!(
-                        "> `{}`\\(`{}`\\): `{}`\n>\n",
-                        path.name,
+                    caption.push_str(&partition_caption(
+                        &path.name,
                         path.size,
-                        path.hash.as_deref().unwrap_or("N/A").trim_matches('"')
+                        path.hash.as_deref(),
                     ));
+                    caption.push_str(">\n");
                 }
 
+                jobs::set_stage(Stage::Uploading);
                 let mut media: Vec<InputMedia> = Vec::with_capacity(files.len());
 
                 for (idx, path) in files.iter().enumerate() {
@@ -180,26 +816,47 @@ async fn dump_cmd(bot: Bot, msg: Message, arg: String) -> Result<Message, Reques
                     }
                 }
 
-                match bot
-                    .send_media_group(msg.chat.id, media)
-                    .reply_to(msg.id)
-                    .await
-                {
-                    Ok(_) => {
+                match upload(bot.send_media_group(msg.chat.id, media).reply_to(msg.id)).await {
+                    None => {
+                        info!("Upload cancelled.");
+                        bot.edit_message_text(
+                            status_msg.chat.id,
+                            status_msg.id,
+                            tr!("job-cancelled"),
+                        )
+                        .await?;
+                    }
+                    Some(Ok(sent)) => {
+                        finish(Outcome::Succeeded);
                         info!("All files uploaded successfully.");
+                        stats::add_uploaded(files.iter().map(|f| f.size).sum());
+                        for file in files {
+                            metrics::uploaded(file.size);
+                        }
+                        for (file, sent) in files.iter().zip(&sent) {
+                            if let (Some(hash), Some(doc)) = (&file.hash, sent.document()) {
+                                file_cache::save(hash, &file.name, file.size, &doc.file.id.0);
+                            }
+                        }
                         bot.edit_message_text(
                             status_msg.chat.id,
                             status_msg.id,
-                            "All files uploaded successfully.",
+                            tr!("upload-done"),
                         )
                         .await?;
                     }
-                    Err(err) => {
+                    Some(Err(err)) if too_large(&err) && file_server::enabled() => {
+                        warn!("Files too large for Telegram, sending links instead: {err}");
+                        let paths = files.iter().map(|f| f.path.as_path());
+                        send_links(&bot, &msg, &status_msg, paths).await?;
+                    }
+                    Some(Err(err)) => {
+                        finish(Outcome::Failed(format!("Failed to upload file: {err}")));
                         error!("Error while uploading files: {err}");
                         bot.edit_message_text(
                             status_msg.chat.id,
                             status_msg.id,
-                            format!("Failed to upload file: {err}"),
+                            tr!("upload-failed", error = err.to_string()),
                         )
                         .await?;
                     }
@@ -208,20 +865,14 @@ async fn dump_cmd(bot: Bot, msg: Message, arg: String) -> Result<Message, Reques
 
             tokio::time::sleep(Duration::from_secs(10)).await;
             bot.delete_message(msg.chat.id, status_msg.id).await?;
-            info!("Cleaning up temporary directory: {}", temp_dir.display());
-            if let Err(e) = std::fs::remove_dir_all(&temp_dir) {
-                error!(
-                    "Failed to clean up temp directory {}: {e}",
-                    temp_dir.display(),
-                );
-            }
         }
         Err(e) => {
+            finish(Outcome::Failed(e.to_string()));
             error!("Failed to dump partitions: {e}");
             bot.edit_message_text(
                 status_msg.chat.id,
                 status_msg.id,
-                format!("Failed to dump partitions: {e}"),
+                failure(&e, tr!("dump-failed", error = e.to_string())),
             )
             .await?;
         }
@@ -229,114 +880,373 @@ async fn dump_cmd(bot: Bot, msg: Message, arg: String) -> Result<Message, Reques
     Ok(status_msg)
 }
 
+/// Waits for a turn and dumps `partition`, `None` if the job ended before the dump.
+async fn lead_dump(
+    bot: &Bot,
+    status_msg: &Message,
+    url: &str,
+    partition: &str,
+) -> Result<Option<Result<Arc<payload::Dumped>>>, RequestError> {
+    if !wait_for_turn(bot, status_msg).await? {
+        return Ok(None);
+    }
+    set_status(
+        bot,
+        status_msg,
+        tr!("dump-dumping", partitions = partition, url = url),
+    )
+    .await?;
+    let dumped = payload::dump_partition(url.to_string(), partition.to_string())
+        .await
+        .map(Arc::new);
+    Ok(Some(dumped))
+}
+
 async fn list_cmd(bot: Bot, msg: Message, arg: String) -> Result<Message, RequestError> {
-    let url = arg.split_whitespace().collect::<Vec<_>>()[0];
+    let (url, export, options) = match parse_list_args(&arg) {
+        Ok(args) => args,
+        Err(e) => return usage_error(&bot, &msg, e).await,
+    };
     info!("{}: Received list command, url: {url}", msg.chat.id);
     debug!(
         "{}: Sender: {}, chat_id: {}",
         msg.id,
-        msg.from.unwrap().id,
+        sender_id(&msg),
         msg.chat.id
     );
-    let ret = payload::list_image(url.to_string())
-        .await
-        .unwrap_or_else(|e| format!("Error fetching image: {e}"));
-    let escaped_ret = ret
-        .replace('&', "&amp;")
-        .replace('<', "&lt;")
-        .replace('>', "&gt;");
-    let html_message = format!("<pre>{}</pre>", escaped_ret);
-    bot.send_message(msg.chat.id, html_message)
-        .parse_mode(ParseMode::Html)
+    if !jobs::wait_for_turn().await {
+        return bot
+            .send_message(msg.chat.id, tr!("job-cancelled"))
+            .reply_to(msg.id)
+            .await;
+    }
+    jobs::set_stage(Stage::Resolving);
+    jobs::record(|r| r.url = Some(url.clone()));
+    let url = match lookup(resolver::resolve(&url)).await {
+        Ok(url) => url,
+        Err(e) => {
+            let text = tr!("resolve-failed", url = url.as_str(), error = e.to_string());
+            finish(Outcome::Failed(e.to_string()));
+            return bot
+                .send_message(msg.chat.id, failure(&e, text))
+                .reply_to(msg.id)
+                .await;
+        }
+    };
+    jobs::set_stage(Stage::Extracting);
+    jobs::record(|r| r.url = Some(url.clone()));
+    if let Some(format) = export {
+        return match lookup(payload::export_image(url, format)).await {
+            Ok(data) => {
+                finish(Outcome::Succeeded);
+                let file =
+                    InputFile::memory(data).file_name(format!("partitions.{}", format.extension()));
+                bot.send_document(msg.chat.id, file).reply_to(msg.id).await
+            }
+            Err(e) => {
+                let text = tr!("list-fetch-failed", error = e.to_string());
+                finish(Outcome::Failed(e.to_string()));
+                bot.send_message(msg.chat.id, failure(&e, text))
+                    .reply_to(msg.id)
+                    .await
+            }
+        };
+    }
+    match lookup(payload::list_image(url.clone(), &options)).await {
+        Ok(listing) => {
+            finish(Outcome::Succeeded);
+            let keyboard = picker::create(url.clone(), listing.partitions);
+            bot.send_message(
+                msg.chat.id,
+                to_html_pre(&format!(
+                    "{}\n{}",
+                    tr!("list-url", url = url.as_str()),
+                    listing.text
+                )),
+            )
+            .parse_mode(ParseMode::Html)
+            .reply_markup(keyboard)
+            .reply_to(msg.id)
+            .await
+        }
+        Err(e) => {
+            let text = tr!("list-fetch-failed", error = e.to_string());
+            finish(Outcome::Failed(e.to_string()));
+            bot.send_message(msg.chat.id, to_html_pre(&failure(&e, text)))
+                .parse_mode(ParseMode::Html)
+                .reply_to(msg.id)
+                .await
+        }
+    }
+}
+
+/// Runs a step of a lookup job, unless the job is cancelled first.
+///
+/// Lookups reply once they are done, so they wait for their turn without a status message.
+async fn lookup<T>(fut: impl Future<Output = Result<T>>) -> Result<T> {
+    let token = jobs::token();
+    tokio::select! {
+        ret = fut => ret,
+        _ = token.cancelled() => Err(jobs::Cancelled.into()),
+    }
+}
+
+async fn diff_cmd(bot: Bot, msg: Message, arg: String) -> Result<Message, RequestError> {
+    let args = match DIFF.parse(&arg) {
+        Ok(args) => args,
+        Err(e) => return usage_error(&bot, &msg, e).await,
+    };
+    let urls = [
+        args.get(0).unwrap_or_default(),
+        args.get(1).unwrap_or_default(),
+    ];
+    info!(
+        "{}: Received diff command, urls: {} {}",
+        msg.chat.id, urls[0], urls[1]
+    );
+    let status_msg = bot
+        .send_message(msg.chat.id, job_prefix() + &tr!("status-resolving-urls"))
+        .reply_markup(cancel_keyboard())
         .reply_to(msg.id)
+        .await?;
+    if !wait_for_turn(&bot, &status_msg).await? {
+        return Ok(status_msg);
+    }
+    jobs::set_stage(Stage::Resolving);
+    jobs::record(|r| r.url = Some(urls.join(" ")));
+    let Some(old_url) = resolve_url(&bot, &status_msg, urls[0]).await? else {
+        return Ok(status_msg);
+    };
+    let Some(new_url) = resolve_url(&bot, &status_msg, urls[1]).await? else {
+        return Ok(status_msg);
+    };
+    jobs::set_stage(Stage::Extracting);
+    jobs::record(|r| r.url = Some(format!("{old_url} {new_url}")));
+    set_status(
+        &bot,
+        &status_msg,
+        tr!(
+            "diff-comparing",
+            old = old_url.as_str(),
+            new = new_url.as_str()
+        ),
+    )
+    .await?;
+    let ret = match diff::diff_image(old_url, new_url).await {
+        Ok(ret) => {
+            finish(Outcome::Succeeded);
+            ret
+        }
+        Err(e) => {
+            finish(Outcome::Failed(e.to_string()));
+            failure(&e, tr!("diff-failed", error = e.to_string()))
+        }
+    };
+    bot.edit_message_text(status_msg.chat.id, status_msg.id, to_html_pre(&ret))
+        .parse_mode(ParseMode::Html)
         .await
 }
 
+fn parse_list_args(
+    arg: &str,
+) -> Result<(String, Option<payload::ExportFormat>, payload::ListOptions), UsageError> {
+    let args = LIST.parse(arg)?;
+    let (export, options) = list_options(&LIST, &args)?;
+    let url = args.get(0).unwrap_or_default().to_string();
+    Ok((url, export, options))
+}
+
+/// Export format and listing options from the flags of `/list`, which the
+/// command line `list` shares.
+pub fn list_options(
+    spec: &Spec,
+    args: &Args,
+) -> Result<(Option<payload::ExportFormat>, payload::ListOptions), UsageError> {
+    let invalid = |e: anyhow::Error| spec.error(e.to_string());
+    let export = match (args.flag("json"), args.flag("csv"), args.value("format")) {
+        (false, false, None) => None,
+        (true, false, None) => Some(payload::ExportFormat::Json),
+        (false, true, None) => Some(payload::ExportFormat::Csv),
+        (false, false, Some(format)) => Some(payload::ExportFormat::from(format).map_err(invalid)?),
+        _ => return Err(spec.error(tr!("list-one-format"))),
+    };
+    let mut options = payload::ListOptions::default();
+    if let Some(sort) = args.value("sort") {
+        options.sort = payload::ListSort::from(sort).map_err(invalid)?;
+    }
+    if let Some(filter) = args.value("filter") {
+        options.filter = Some(utils::glob_to_regex(filter).map_err(invalid)?);
+    }
+    if let Some(min_size) = args.value("min-size") {
+        options.min_size = utils::parse_size(min_size).map_err(invalid)?;
+    }
+    Ok((export, options))
+}
+
 async fn patch_cmd(bot: Bot, msg: Message, arg: String) -> Result<Message, RequestError> {
-    let args = arg.split_whitespace().collect::<Vec<_>>();
-    let url = args[0];
-    let patch_partition = args[1];
-    let patch_method = if args.len() < 3 { "ksu" } else { args[2] };
+    let args = match PATCH.parse(&arg) {
+        Ok(args) => args,
+        Err(e) => return usage_error(&bot, &msg, e).await,
+    };
+    let url = args.get(0).unwrap_or_default();
+    let patch_partition = args.get(1).unwrap_or_default();
+    let patch_method = match (args.value("method"), args.get(2)) {
+        (Some(_), Some(_)) => {
+            let e = PATCH.error(tr!("patch-method-twice"));
+            return usage_error(&bot, &msg, e).await;
+        }
+        (Some(method), None) | (None, Some(method)) => method,
+        (None, None) => "ksu",
+    };
+    let Ok(partition) = PatchPartition::from(patch_partition) else {
+        let e = PATCH.error(tr!("patch-invalid-partition", partition = patch_partition));
+        return usage_error(&bot, &msg, e).await;
+    };
+    let Ok(method) = PatchMethod::from(patch_method) else {
+        let e = PATCH.error(tr!("patch-invalid-method", method = patch_method));
+        return usage_error(&bot, &msg, e).await;
+    };
+    if args.flag("link") && !file_server::enabled() {
+        return usage_error(&bot, &msg, PATCH.error(tr!("link-disabled"))).await;
+    }
     let status_msg = bot
         .send_message(
             msg.chat.id,
-            format!("Patching {patch_partition} with {patch_method}"),
+            job_prefix() + &tr!("status-resolving", url = url),
         )
+        .reply_markup(cancel_keyboard())
         .reply_to(msg.id)
         .await?;
-    match patch_boot(
-        url.to_string(),
-        patch_partition.to_string(),
-        patch_method.to_string(),
+    if !wait_for_turn(&bot, &status_msg).await? {
+        return Ok(status_msg);
+    }
+    jobs::set_stage(Stage::Resolving);
+    jobs::record(|r| {
+        r.url = Some(url.to_string());
+        r.partitions = Some(patch_partition.to_string());
+        r.method = Some(patch_method.to_string());
+    });
+    let Some(url) = resolve_url(&bot, &status_msg, url).await? else {
+        return Ok(status_msg);
+    };
+    set_status(
+        &bot,
+        &status_msg,
+        tr!(
+            "patch-patching",
+            partition = patch_partition,
+            method = patch_method,
+            url = url.as_str()
+        ),
     )
-    .await
-    {
+    .await?;
+    match patch_boot(url, partition, method).await {
         Ok(patched_file) => {
+            let hash = utils::sha256_file(&patched_file.path)
+                .map_err(|e| warn!("Failed to hash {}: {e}", patched_file.path.display()))
+                .ok();
+            jobs::record(|r| {
+                r.kmi = Some(patched_file.kernel.kmi.clone());
+                r.hashes = hash.map(|h| {
+                    let name = patched_file.path.file_name().unwrap_or_default();
+                    format!("{}={h}", name.to_string_lossy())
+                });
+            });
             info!(
                 "Patch {patch_partition} with {patch_method} successfully, patched file: {}",
                 patched_file.path.display()
             );
-            bot.edit_message_text(
-                status_msg.chat.id,
-                status_msg.id,
-                format!("Patch {patch_partition} successfully, uploading..."),
+            set_status(
+                &bot,
+                &status_msg,
+                tr!("patch-uploading", partition = patch_partition),
             )
             .await?;
             let document = InputMediaDocument::new(InputFile::file(patched_file.path.clone()))
                 .caption(to_tg_md(format!(
                     ">KMI: `{}`\n>Kernel Version: `{}`",
-                    patched_file.kmi, patched_file.kernel_version
+                    patched_file.kernel.kmi, patched_file.kernel.version
                 )))
                 .parse_mode(ParseMode::MarkdownV2);
-            if PathBuf::from(patched_file.path.clone()).exists() {
-                match bot.send_media_group(status_msg.chat.id, vec![InputMedia::Document(document)])
-                    .reply_to(msg.id)
-                    .await {
-                    Ok(_) => {
+            jobs::set_stage(Stage::Uploading);
+            if patched_file.path.exists() && args.flag("link") {
+                send_links(&bot, &msg, &status_msg, [patched_file.path.as_path()]).await?;
+            } else if patched_file.path.exists() {
+                match upload(
+                    bot.send_media_group(status_msg.chat.id, vec![InputMedia::Document(document)])
+                        .reply_to(msg.id),
+                )
+                .await
+                {
+                    None => {
+                        info!("Upload cancelled.");
+                        bot.edit_message_text(
+                            status_msg.chat.id,
+                            status_msg.id,
+                            tr!("job-cancelled"),
+                        )
+                        .await?;
+                    }
+                    Some(Ok(_)) => {
+                        finish(Outcome::Succeeded);
                         info!("All files uploaded successfully.");
+                        if let Ok(meta) = patched_file.path.metadata() {
+                            stats::add_uploaded(meta.len());
+                            metrics::uploaded(meta.len());
+                        }
                         bot.edit_message_text(
                             status_msg.chat.id,
                             status_msg.id,
-                            "All files uploaded successfully.",
+                            tr!("upload-done"),
                         )
-                            .await?;
+                        .await?;
                         tokio::time::sleep(Duration::from_secs(10)).await;
                         bot.delete_message(msg.chat.id, status_msg.id).await?;
                     }
-                    Err(err) => {
+                    Some(Err(err)) if too_large(&err) && file_server::enabled() => {
+                        warn!(
+                            "Patched image too large for Telegram, sending a link instead: {err}"
+                        );
+                        send_links(&bot, &msg, &status_msg, [patched_file.path.as_path()]).await?;
+                    }
+                    Some(Err(err)) => {
+                        finish(Outcome::Failed(format!("Failed to upload file: {err}")));
                         error!("Error while uploading files: {err}");
                         bot.edit_message_text(
                             status_msg.chat.id,
                             status_msg.id,
-                            format!("Failed to upload file: {err}"),
+                            tr!("upload-failed", error = err.to_string()),
                         )
-                            .await?;
+                        .await?;
                     }
                 }
             } else {
+                finish(Outcome::Failed("Patched file not found".to_string()));
                 bot.edit_message_text(
                     status_msg.chat.id,
                     status_msg.id,
-                    format!("Patched file {} not found!", patched_file.path.display()),
+                    tr!(
+                        "patch-not-found",
+                        path = patched_file.path.display().to_string()
+                    ),
                 )
-                    .await?;
-            }
-
-            let temp_dir = patched_file.path.parent().unwrap();
-            info!("Cleaning up temporary directory: {}", temp_dir.display());
-            if let Err(e) = std::fs::remove_dir_all(&temp_dir) {
-                error!(
-                    "Failed to clean up temp directory {}: {e}",
-                    temp_dir.display(),
-                );
+                .await?;
             }
         }
         Err(e) => {
+            finish(Outcome::Failed(e.to_string()));
             error!("Failed to patch {patch_partition}: {e}");
             bot.edit_message_text(
                 status_msg.chat.id,
                 status_msg.id,
-                format!("Failed to patch {patch_partition}: {e}"),
+                failure(
+                    &e,
+                    tr!(
+                        "patch-failed",
+                        partition = patch_partition,
+                        error = e.to_string()
+                    ),
+                ),
             )
             .await?;
         }
@@ -344,9 +1254,914 @@ async fn patch_cmd(bot: Bot, msg: Message, arg: String) -> Result<Message, Reque
     Ok(status_msg)
 }
 
+async fn latest_cmd(bot: Bot, msg: Message, arg: String) -> Result<Message, RequestError> {
+    let args = match LATEST.parse(&arg) {
+        Ok(args) => args,
+        Err(e) => return usage_error(&bot, &msg, e).await,
+    };
+    let codename = args.get(0).unwrap_or_default();
+    let region = args.get(1);
+    info!(
+        "{}: Received latest command, codename: {codename}, region: {}",
+        msg.chat.id,
+        region.unwrap_or("any")
+    );
+    if !jobs::wait_for_turn().await {
+        return bot
+            .send_message(msg.chat.id, tr!("job-cancelled"))
+            .reply_to(msg.id)
+            .await;
+    }
+    jobs::set_stage(Stage::Resolving);
+    let config = config::get();
+    let providers = rom_index::providers(&config);
+    let found = lookup(rom_index::find_latest(&providers, codename, region)).await;
+    match &found {
+        Ok(found) => {
+            let url = found.as_ref().map(|(_, release)| release.url.clone());
+            jobs::record(|r| {
+                r.url = url;
+                r.finish(Outcome::Succeeded);
+            });
+        }
+        Err(e) => finish(Outcome::Failed(e.to_string())),
+    }
+    let ret = match found {
+        Ok(Some((provider, release))) => tr!(
+            "latest-found",
+            codename = codename,
+            region = release.region.as_deref().unwrap_or("N/A"),
+            version = release.version.as_deref().unwrap_or("N/A"),
+            patch = release.security_patch.as_deref().unwrap_or("N/A"),
+            source = provider.to_string(),
+            url = release.url.as_str()
+        ),
+        Ok(None) => tr!("latest-none", codename = codename),
+        Err(e) => failure(
+            &e,
+            tr!("latest-failed", codename = codename, error = e.to_string()),
+        ),
+    };
+    bot.send_message(msg.chat.id, to_html_pre(&ret))
+        .parse_mode(ParseMode::Html)
+        .reply_to(msg.id)
+        .await
+}
+
+async fn queue_cmd(bot: Bot, msg: Message) -> Result<Message, RequestError> {
+    let (running, queued): (Vec<_>, Vec<_>) =
+        jobs::list().into_iter().partition(|j| j.is_running());
+    let mut ret = format!("{}\n", tr!("queue-running", count = running.len()));
+    for job in &running {
+        ret.push_str(&format!(
+            "  #{} {} [{}] {}: {}\n",
+            job.id,
+            job.kind,
+            stage_name(job.stage),
+            utils::format_duration(job.age()),
+            job_description(job, &msg)
+        ));
+    }
+    ret.push_str(&format!(
+        "\n{}\n",
+        tr!("queue-queued", count = queued.len())
+    ));
+    for (idx, job) in queued.iter().enumerate() {
+        ret.push_str(&format!(
+            "  {}. #{} {} {}: {}\n",
+            idx + 1,
+            job.id,
+            job.kind,
+            utils::format_duration(job.age()),
+            job_description(job, &msg)
+        ));
+    }
+    bot.send_message(msg.chat.id, to_html_pre(&ret))
+        .parse_mode(ParseMode::Html)
+        .reply_to(msg.id)
+        .await
+}
+
+async fn status_cmd(bot: Bot, msg: Message, arg: String) -> Result<Message, RequestError> {
+    let id = match STATUS
+        .parse(&arg)
+        .and_then(|args| parse_job_id(&STATUS, args.get(0).unwrap_or_default()))
+    {
+        Ok(id) => id,
+        Err(e) => return usage_error(&bot, &msg, e).await,
+    };
+    let ret = match jobs::get(id) {
+        Some(job) => {
+            let mut ret = tr!(
+                "status-details",
+                id = job.id,
+                kind = job.kind.clone(),
+                stage = stage_name(job.stage),
+                arguments = job_description(&job, &msg),
+                age = utils::format_duration(job.age())
+            );
+            ret.push('\n');
+            if let Some(leader) = job.attached_to {
+                ret.push_str(&tr!("status-attached", id = leader));
+                ret.push('\n');
+            }
+            match (job.started, jobs::position(id)) {
+                (Some(started), _) => {
+                    let duration = utils::format_duration(started.elapsed());
+                    ret.push_str(&tr!("status-running-for", duration = duration));
+                    ret.push('\n');
+                }
+                (None, Some(position)) => {
+                    ret.push_str(&tr!("status-position", position = position));
+                    ret.push('\n');
+                }
+                (None, None) => {}
+            }
+            ret
+        }
+        None => tr!("job-not-found", id = id),
+    };
+    bot.send_message(msg.chat.id, to_html_pre(&ret))
+        .parse_mode(ParseMode::Html)
+        .reply_to(msg.id)
+        .await
+}
+
+/// Arguments of `job`, hidden from other chats since they may contain private links.
+fn job_description(job: &jobs::JobInfo, msg: &Message) -> String {
+    if job.chat == msg.chat.id.0 {
+        job.description.clone()
+    } else {
+        tr!("job-hidden")
+    }
+}
+
+fn stage_name(stage: Stage) -> String {
+    tr!(&format!("stage-{stage}"))
+}
+
+/// Edits the progress shown on `status_msg`, keeping the Cancel button.
+async fn set_status(
+    bot: &Bot,
+    status_msg: &Message,
+    text: impl Into<String>,
+) -> Result<Message, RequestError> {
+    bot.edit_message_text(status_msg.chat.id, status_msg.id, text)
+        .reply_markup(cancel_keyboard())
+        .await
+}
+
+fn cancel_keyboard() -> InlineKeyboardMarkup {
+    let id = jobs::current().unwrap_or_default();
+    InlineKeyboardMarkup::new([[InlineKeyboardButton::callback(
+        tr!("cancel-button"),
+        format!("{CANCEL_PREFIX}{id}"),
+    )]])
+}
+
+/// Sends `request` unless the current job is cancelled first, which aborts the upload.
+async fn upload<R, T>(request: R) -> Option<Result<T, RequestError>>
+where
+    R: IntoFuture<Output = Result<T, RequestError>>,
+{
+    let token = jobs::token();
+    let ret = tokio::select! {
+        ret = request.into_future() => ret,
+        _ = token.cancelled() => return None,
+    };
+    if let Err(e) = &ret {
+        metrics::telegram_error(error_kind(e));
+    }
+    Some(ret)
+}
+
+/// Logs the error a handler ended with and counts it.
+fn report(handler: &str, e: RequestError) {
+    metrics::telegram_error(error_kind(&e));
+    error!("Error in {handler}: {e}");
+}
+
+/// Label of `e` in the Telegram error metric.
+fn error_kind(e: &RequestError) -> &'static str {
+    match e {
+        RequestError::Api(_) => "api",
+        RequestError::MigrateToChatId(_) => "migrate_to_chat_id",
+        RequestError::RetryAfter(_) => "retry_after",
+        RequestError::Network(_) => "network",
+        RequestError::InvalidJson { .. } => "invalid_json",
+        RequestError::Io(_) => "io",
+    }
+}
+
+/// Sets the outcome saved to the job history.
+fn finish(outcome: Outcome) {
+    jobs::record(|r| r.finish(outcome));
+}
+
+/// Whether Telegram refused an upload for its size.
+fn too_large(err: &RequestError) -> bool {
+    matches!(err, RequestError::Api(ApiError::RequestEntityTooLarge))
+        || err.to_string().to_ascii_lowercase().contains("too big")
+}
+
+/// Publishes `paths` on the file server and replies with their download links.
+async fn send_links<'a>(
+    bot: &Bot,
+    msg: &Message,
+    status_msg: &Message,
+    paths: impl IntoIterator<Item = &'a Path>,
+) -> Result<(), RequestError> {
+    let published = paths
+        .into_iter()
+        .map(file_server::publish)
+        .collect::<Result<Vec<_>>>();
+    let files = match published {
+        Ok(files) => files,
+        Err(e) => {
+            finish(Outcome::Failed(format!("Failed to publish files: {e}")));
+            error!("Failed to publish files: {e}");
+            bot.edit_message_text(
+                status_msg.chat.id,
+                status_msg.id,
+                tr!("links-failed", error = e.to_string()),
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+    finish(Outcome::Succeeded);
+    let mut text = files
+        .iter()
+        .map(|f| {
+            tr!(
+                "links-entry",
+                name = f.name.as_str(),
+                size = format_size(f.size),
+                url = f.url.as_str()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    if let Some((ttl, single_use)) = file_server::ttl() {
+        let duration = utils::format_duration(ttl);
+        text.push_str("\n\n");
+        text.push_str(&match single_use {
+            true => tr!("links-single-use", duration = duration),
+            false => tr!("links-expire", duration = duration),
+        });
+    }
+    // A preview would fetch, and so use up, single use links.
+    bot.send_message(msg.chat.id, text)
+        .reply_to(msg.id)
+        .link_preview_options(LinkPreviewOptions {
+            is_disabled: true,
+            url: None,
+            prefer_small_media: false,
+            prefer_large_media: false,
+            show_above_text: false,
+        })
+        .await?;
+    bot.edit_message_text(status_msg.chat.id, status_msg.id, tr!("links-sent"))
+        .await?;
+    Ok(())
+}
+
+/// `message` describing `e`, or a plain notice if the job was cancelled.
+fn failure(e: &anyhow::Error, message: String) -> String {
+    if e.is::<jobs::Cancelled>() {
+        tr!("job-cancelled")
+    } else {
+        message
+    }
+}
+
+/// Cancels job `id` on behalf of `user`, returns the reply.
+fn cancel_job(user: u64, id: u64) -> String {
+    match jobs::get(id) {
+        Some(job) if job.user != user && !access::is_admin(user) => tr!("cancel-not-owner"),
+        Some(_) if jobs::cancel(id) => tr!("cancel-cancelling", id = id),
+        _ => tr!("job-not-found", id = id),
+    }
+}
+
+async fn cancel_cmd(bot: Bot, msg: Message, arg: String) -> Result<Message, RequestError> {
+    let user = sender_id(&msg);
+    let id = match CANCEL
+        .parse(&arg)
+        .and_then(|args| args.get(0).map(|id| parse_job_id(&CANCEL, id)).transpose())
+    {
+        Ok(id) => id,
+        Err(e) => return usage_error(&bot, &msg, e).await,
+    };
+    let id = id.or_else(|| {
+        jobs::list()
+            .into_iter()
+            .filter(|j| j.user == user)
+            .map(|j| j.id)
+            .max()
+    });
+    let ret = match id {
+        Some(id) => cancel_job(user, id),
+        None => tr!("cancel-no-jobs"),
+    };
+    bot.send_message(msg.chat.id, ret).reply_to(msg.id).await
+}
+
+async fn history_cmd(bot: Bot, msg: Message, arg: String) -> Result<Message, RequestError> {
+    let user = sender_id(&msg);
+    let args = match HISTORY.parse(&arg) {
+        Ok(args) => args,
+        Err(e) => return usage_error(&bot, &msg, e).await,
+    };
+    let limit = match args.get(0).map(|n| n.parse::<usize>()) {
+        None => 10,
+        Some(Ok(n)) if n > 0 => n.min(20),
+        Some(_) => {
+            return usage_error(&bot, &msg, HISTORY.error(tr!("history-invalid-count"))).await;
+        }
+    };
+    let filter = match args.flag("all") {
+        false => Some(user),
+        true if access::is_admin(user) => None,
+        true => return usage_error(&bot, &msg, HISTORY.error(tr!("history-admins-only"))).await,
+    };
+    let ret = match history::recent(filter, limit) {
+        Ok(records) if records.is_empty() => tr!("history-empty"),
+        Ok(records) => records
+            .iter()
+            .map(|r| {
+                let mut ret = tr!(
+                    "history-entry",
+                    id = r.id,
+                    created = r.created.as_str(),
+                    kind = r.kind.as_str(),
+                    outcome = tr!(&format!("outcome-{}", r.outcome)),
+                    duration = utils::format_duration(Duration::from_secs_f64(r.duration))
+                );
+                ret.push('\n');
+                if filter.is_none() {
+                    ret.push_str(&format!("  {}: {}\n", tr!("history-user"), r.user));
+                }
+                for (title, value) in [
+                    ("history-url", &r.url),
+                    ("history-partitions", &r.partitions),
+                    ("history-method", &r.method),
+                    ("history-kmi", &r.kmi),
+                    ("history-hashes", &r.hashes),
+                    ("history-error", &r.error),
+                ] {
+                    if let Some(value) = value {
+                        ret.push_str(&format!("  {}: {value}\n", tr!(title)));
+                    }
+                }
+                ret
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Err(e) => tr!("history-failed", error = e.to_string()),
+    };
+    bot.send_message(msg.chat.id, to_html_pre(&ret))
+        .parse_mode(ParseMode::Html)
+        .reply_to(msg.id)
+        .await
+}
+
+fn parse_job_id(spec: &Spec, s: &str) -> Result<u64, UsageError> {
+    s.trim_start_matches('#')
+        .parse()
+        .map_err(|_| spec.error(tr!("invalid-job-id", id = s)))
+}
+
+/// Replies with `e` and removes the reply after a while.
+async fn usage_error(bot: &Bot, msg: &Message, e: UsageError) -> Result<Message, RequestError> {
+    warn!("{}: Invalid command: {e}", msg.chat.id);
+    let reply = bot
+        .send_message(msg.chat.id, e.to_string())
+        .reply_to(msg.id)
+        .await?;
+    tokio::time::sleep(Duration::from_secs(10)).await;
+    bot.delete_message(reply.chat.id, reply.id).await?;
+    Ok(reply)
+}
+
+/// `Job #n: ` prefix for status messages of the current job.
+fn job_prefix() -> String {
+    jobs::current()
+        .map(|id| format!("{} ", tr!("job-prefix", id = id)))
+        .unwrap_or_default()
+}
+
+/// Waits until the current job may start, keeping its queue position on `status_msg`.
+///
+/// Returns false if the job was cancelled while queued.
+async fn wait_for_turn(bot: &Bot, status_msg: &Message) -> Result<bool, RequestError> {
+    let token = jobs::token();
+    let mut shown = None;
+    loop {
+        let changed = jobs::changed();
+        if token.is_cancelled() {
+            bot.edit_message_text(status_msg.chat.id, status_msg.id, tr!("job-cancelled"))
+                .await?;
+            return Ok(false);
+        }
+        let Some(position) = jobs::try_start() else {
+            return Ok(true);
+        };
+        if shown != Some(position) {
+            set_status(
+                bot,
+                status_msg,
+                job_prefix() + &tr!("status-queued", position = position),
+            )
+            .await?;
+            shown = Some(position);
+        }
+        tokio::select! {
+            _ = changed => {}
+            _ = token.cancelled() => {}
+        }
+    }
+}
+
+fn sender_id(msg: &Message) -> u64 {
+    msg.from
+        .as_ref()
+        .map(|u| u.id.0)
+        .unwrap_or(msg.chat.id.0 as u64)
+}
+
+/// MarkdownV2 quote line with the name, size and payload hash of a partition.
+fn partition_caption(name: &str, size: u64, hash: Option<&str>) -> String {
+    format!(
+        "> `{name}`\\(`{size}`\\): `{}`\n",
+        hash.unwrap_or("N/A").trim_matches('"')
+    )
+}
+
+/// [`with_reply_url`], replying with the error if the replied-to message can't be used.
+async fn reply_arg(bot: &Bot, msg: &Message, arg: String) -> Option<String> {
+    match with_reply_url(msg, arg) {
+        Ok(arg) => Some(arg),
+        Err(e) => {
+            if let Err(e) = bot.send_message(msg.chat.id, e).reply_to(msg.id).await {
+                error!("Error sending reply error: {e}");
+            }
+            None
+        }
+    }
+}
+
+/// Prepends the URL of the replied-to message to `arg`, unless `arg` starts with one.
+///
+/// Fails for a replied-to document without a link: the Bot API only hands out
+/// files up to 20 MB, and its download links carry the bot token.
+fn with_reply_url(msg: &Message, arg: String) -> Result<String, String> {
+    let explicit = arg
+        .split_whitespace()
+        .next()
+        .is_some_and(|first| is_url(first.trim_matches(['"', '\''])));
+    let Some(reply) = msg.reply_to_message().filter(|_| !explicit) else {
+        return Ok(arg);
+    };
+    match message_url(reply) {
+        Some(url) => {
+            debug!("{}: Using URL {url} of the replied-to message", msg.chat.id);
+            Ok(format!("{} {arg}", args::quote(&url))
+                .trim_end()
+                .to_string())
+        }
+        None => match reply.document() {
+            Some(document) => {
+                let name = document.file_name.as_deref().unwrap_or("file");
+                Err(tr!("reply-document-unsupported", name = name))
+            }
+            None => Ok(arg),
+        },
+    }
+}
+
+/// First link in the text or caption of `msg`, including links hidden behind text.
+fn message_url(msg: &Message) -> Option<String> {
+    let entities = msg
+        .parse_entities()
+        .into_iter()
+        .chain(msg.parse_caption_entities())
+        .flatten();
+    for entity in entities {
+        match entity.kind() {
+            MessageEntityKind::Url => return Some(entity.text().to_string()),
+            MessageEntityKind::TextLink { url } => return Some(url.to_string()),
+            _ => {}
+        }
+    }
+    msg.text()
+        .into_iter()
+        .chain(msg.caption())
+        .flat_map(str::split_whitespace)
+        .find(|word| is_url(word))
+        .map(str::to_string)
+}
+
+fn is_url(s: &str) -> bool {
+    reqwest::Url::parse(s).is_ok_and(|u| u.has_host())
+}
+
+/// Resolves `url` to a direct link, reporting failures on `status_msg`.
+async fn resolve_url(
+    bot: &Bot,
+    status_msg: &Message,
+    url: &str,
+) -> Result<Option<String>, RequestError> {
+    let token = jobs::token();
+    let resolved = tokio::select! {
+        resolved = resolver::resolve(url) => resolved,
+        _ = token.cancelled() => Err(jobs::Cancelled.into()),
+    };
+    match resolved {
+        Ok(url) => Ok(Some(url)),
+        Err(e) => {
+            finish(Outcome::Failed(format!("Failed to resolve {url}: {e}")));
+            error!("Failed to resolve {url}: {e}");
+            bot.edit_message_text(
+                status_msg.chat.id,
+                status_msg.id,
+                failure(&e, tr!("resolve-failed", url = url, error = e.to_string())),
+            )
+            .await?;
+            Ok(None)
+        }
+    }
+}
+
 async fn help_cmd(bot: Bot, msg: Message) -> Result<Message, RequestError> {
-    bot.send_message(msg.chat.id, HELP_MESSAGE)
+    let mut specs = SPECS.to_vec();
+    if access::is_admin(sender_id(&msg)) {
+        specs.extend(ADMIN_SPECS);
+    }
+    bot.send_message(msg.chat.id, args::help_message(&specs))
         .parse_mode(ParseMode::MarkdownV2)
         .reply_to(msg.id)
         .await
 }
+
+async fn lang_cmd(bot: Bot, msg: Message, arg: String) -> Result<Message, RequestError> {
+    let args = match LANG.parse(&arg) {
+        Ok(args) => args,
+        Err(e) => return usage_error(&bot, &msg, e).await,
+    };
+    let available = i18n::available().join(", ");
+    let ret = match args.get(0) {
+        None => tr!(
+            "lang-current",
+            locale = i18n::current(),
+            available = available
+        ),
+        Some("auto") => match i18n::set_chat_locale(msg.chat.id.0, None) {
+            Ok(()) => tr!("lang-reset"),
+            Err(e) => e.to_string(),
+        },
+        Some(code) => match i18n::find(code) {
+            Some(locale) => match i18n::set_chat_locale(msg.chat.id.0, Some(locale)) {
+                Ok(()) => {
+                    i18n::scope(locale, async {
+                        tr!("lang-set", name = tr!("language-name"))
+                    })
+                    .await
+                }
+                Err(e) => e.to_string(),
+            },
+            None => tr!("lang-unknown", locale = code, available = available),
+        },
+    };
+    bot.send_message(msg.chat.id, ret).reply_to(msg.id).await
+}
+
+/// Locale of replies to `msg`.
+fn locale(msg: &Message) -> &'static str {
+    let language_code = msg.from.as_ref().and_then(|u| u.language_code.as_deref());
+    i18n::locale_for(msg.chat.id.0, language_code)
+}
+
+/// Registers the command menu, admins also get the admin commands in their private chat.
+///
+/// Descriptions are registered for every catalog, Telegram picks the user's language.
+pub async fn register_commands(bot: &Bot) -> Result<()> {
+    for locale in i18n::available() {
+        let language = (locale != i18n::FALLBACK).then_some(locale);
+        let (public, all) = i18n::scope(locale, async {
+            let command = |s: &&Spec| BotCommand::new(s.command, s.description());
+            let public = SPECS.iter().map(command).collect::<Vec<_>>();
+            let admin = ADMIN_SPECS.iter().map(command);
+            let all = public.iter().cloned().chain(admin).collect::<Vec<_>>();
+            (public, all)
+        })
+        .await;
+        let mut request = bot.set_my_commands(public);
+        if let Some(language) = language {
+            request = request.language_code(language);
+        }
+        request.await?;
+        for admin in access::admins() {
+            let mut request = bot
+                .set_my_commands(all.clone())
+                .scope(BotCommandScope::Chat {
+                    chat_id: Recipient::Id(ChatId(admin as i64)),
+                });
+            if let Some(language) = language {
+                request = request.language_code(language);
+            }
+            // Fails until the admin has started a chat with the bot.
+            if let Err(e) = request.await {
+                warn!("Failed to register admin commands for {admin}: {e}");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn maintenance_notice(message: &str) -> String {
+    match message {
+        "" => tr!("maintenance-active"),
+        message => tr!("maintenance-active-message", message = message),
+    }
+}
+
+async fn stats_cmd(bot: Bot, msg: Message) -> Result<Message, RequestError> {
+    let stats = stats::snapshot();
+    let (running, queued): (Vec<_>, Vec<_>) =
+        jobs::list().into_iter().partition(|j| j.is_running());
+    let finished = match history::outcome_counts() {
+        Ok(counts) if counts.is_empty() => "0".to_string(),
+        Ok(counts) => counts
+            .iter()
+            .map(|(outcome, count)| format!("{} {count}", tr!(&format!("outcome-{outcome}"))))
+            .collect::<Vec<_>>()
+            .join(", "),
+        Err(e) => e.to_string(),
+    };
+    let hit_rate = match stats.cache_hit_rate() {
+        Some(rate) => format!(
+            "{:.0}% ({}/{})",
+            rate * 100.0,
+            stats.cache_hits,
+            stats.cache_hits + stats.cache_misses
+        ),
+        None => "N/A".to_string(),
+    };
+    let config = config::get();
+    let database = config.history_db.clone();
+    let sizes = tokio::task::spawn_blocking(move || {
+        let tools = ToolManager::new().map(|tm| utils::dir_size(&tm.dir()));
+        (
+            utils::dir_size(Path::new(utils::TEMP_ROOT)),
+            utils::dir_size(&database),
+            tools.map(format_size).unwrap_or_else(|_| "N/A".to_string()),
+        )
+    })
+    .await;
+    let (tmp, database, tools) = match sizes {
+        Ok((tmp, database, tools)) => (format_size(tmp), format_size(database), tools),
+        Err(e) => {
+            error!("Failed to measure disk usage: {e}");
+            let na = || "N/A".to_string();
+            (na(), na(), na())
+        }
+    };
+    let mut ret = tr!(
+        "stats",
+        uptime = utils::format_duration(stats.uptime),
+        running = running.len(),
+        queued = queued.len(),
+        finished = finished,
+        downloaded = format_size(stats.downloaded),
+        uploaded = format_size(stats.uploaded),
+        files = file_cache::count().unwrap_or_default(),
+        hit_rate = hit_rate,
+        tmp = tmp,
+        database = database,
+        tools = tools
+    );
+    if let Some(message) = jobs::maintenance() {
+        ret.push_str("\n\n");
+        ret.push_str(&maintenance_notice(&message));
+    }
+    bot.send_message(msg.chat.id, to_html_pre(&ret))
+        .parse_mode(ParseMode::Html)
+        .reply_to(msg.id)
+        .await
+}
+
+/// Loads the config file again and swaps it in if it is valid, returning the
+/// changed keys that only apply after a restart.
+///
+/// Access control, proxies and job limits apply at once, running jobs keep the
+/// rest of the config they started with.
+pub async fn reload_config(bot: &Bot) -> Result<Vec<String>> {
+    let config = config::load()?;
+    url_policy::validate(&config)?;
+    proxy::init(&config)?;
+    access::init(&config)?;
+    jobs::init(&config);
+    let pending = config::restart_needed(&config);
+    if !pending.is_empty() {
+        warn!("Changes to {} apply after a restart", pending.join(", "));
+    }
+    config::set(config);
+    if let Err(e) = register_commands(bot).await {
+        warn!("Failed to register commands: {e}");
+    }
+    Ok(pending)
+}
+
+async fn reload_cmd(bot: Bot, msg: Message) -> Result<Message, RequestError> {
+    info!(
+        "{}: Reloading config on behalf of {}",
+        msg.chat.id,
+        sender_id(&msg)
+    );
+    let ret = match reload_config(&bot).await {
+        Ok(pending) if pending.is_empty() => tr!("reload-done"),
+        Ok(pending) => format!(
+            "{}\n{}",
+            tr!("reload-done"),
+            tr!("reload-restart-needed", keys = pending.join(", "))
+        ),
+        Err(e) => {
+            error!("Failed to reload config: {e}");
+            tr!("reload-failed", error = e.to_string())
+        }
+    };
+    bot.send_message(msg.chat.id, ret).reply_to(msg.id).await
+}
+
+fn tool_versions(tm: &ToolManager) -> String {
+    tm.versions()
+        .into_iter()
+        .map(|(name, version)| {
+            let version = version.unwrap_or_else(|| tr!("tools-unknown-version"));
+            tr!("tools-entry", name = name, version = version)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+async fn tools_cmd(bot: Bot, msg: Message, arg: String) -> Result<Message, RequestError> {
+    let args = match TOOLS.parse(&arg) {
+        Ok(args) => args,
+        Err(e) => return usage_error(&bot, &msg, e).await,
+    };
+    let tm = match ToolManager::new() {
+        Ok(tm) => tm,
+        Err(e) => {
+            error!("Tools are unavailable: {e}");
+            return bot
+                .send_message(msg.chat.id, tr!("tools-unavailable", error = e.to_string()))
+                .reply_to(msg.id)
+                .await;
+        }
+    };
+    match args.get(0) {
+        None => {
+            bot.send_message(msg.chat.id, tool_versions(&tm))
+                .reply_to(msg.id)
+                .await
+        }
+        Some("update") => {
+            let tool = args.get(1);
+            let status_msg = bot
+                .send_message(
+                    msg.chat.id,
+                    tr!("tools-updating", tool = tool.unwrap_or("ksud, magiskboot")),
+                )
+                .reply_to(msg.id)
+                .await?;
+            let ret = match tm.update(tool).await {
+                Ok(()) => format!("{}\n{}", tr!("tools-updated"), tool_versions(&tm)),
+                Err(e) => {
+                    error!("Failed to update tools: {e}");
+                    tr!("tools-update-failed", error = e.to_string())
+                }
+            };
+            bot.edit_message_text(status_msg.chat.id, status_msg.id, ret)
+                .await
+        }
+        Some(other) => {
+            let e = TOOLS.error(tr!("arg-unexpected", arg = other));
+            usage_error(&bot, &msg, e).await
+        }
+    }
+}
+
+async fn maintenance_cmd(bot: Bot, msg: Message, arg: String) -> Result<Message, RequestError> {
+    let args = match MAINTENANCE.parse(&arg) {
+        Ok(args) => args,
+        Err(e) => return usage_error(&bot, &msg, e).await,
+    };
+    let ret = match args.get(0) {
+        None => match jobs::maintenance() {
+            Some(message) => maintenance_notice(&message),
+            None => tr!("maintenance-off"),
+        },
+        Some("on") => {
+            let message = args.get(1).unwrap_or_default().to_string();
+            info!("{}: Maintenance mode on: {message}", msg.chat.id);
+            jobs::set_maintenance(Some(message));
+            tr!("maintenance-on")
+        }
+        Some("off") => {
+            info!("{}: Maintenance mode off", msg.chat.id);
+            jobs::set_maintenance(None);
+            tr!("maintenance-off")
+        }
+        Some(other) => {
+            let e = MAINTENANCE.error(tr!("arg-unexpected", arg = other));
+            return usage_error(&bot, &msg, e).await;
+        }
+    };
+    bot.send_message(msg.chat.id, ret).reply_to(msg.id).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn message(fields: serde_json::Value) -> Message {
+        let mut msg = json!({
+            "message_id": 1,
+            "date": 0,
+            "chat": { "id": 1, "type": "private", "first_name": "a" },
+        });
+        msg.as_object_mut()
+            .unwrap()
+            .extend(fields.as_object().unwrap().clone());
+        serde_json::from_value(msg).unwrap()
+    }
+
+    fn reply_to(replied: serde_json::Value, arg: &str) -> Result<String, String> {
+        let replied = serde_json::to_value(message(replied)).unwrap();
+        with_reply_url(
+            &message(json!({ "text": "/dump", "reply_to_message": replied })),
+            arg.to_string(),
+        )
+    }
+
+    const URL: &str = "https://a.com/rom.zip";
+    const TEXT: &str = "get it at https://a.com/rom.zip";
+
+    fn expected() -> Result<String, String> {
+        Ok(format!("{URL} boot"))
+    }
+
+    fn document() -> serde_json::Value {
+        json!({ "file_id": "f", "file_unique_id": "u", "file_name": "ota.zip" })
+    }
+
+    #[test]
+    fn takes_url_entities_of_the_text() {
+        let entity = json!({ "type": "url", "offset": 10, "length": URL.len() });
+        let replied = json!({ "text": TEXT, "entities": [entity] });
+        assert_eq!(reply_to(replied, "boot"), expected());
+    }
+
+    #[test]
+    fn takes_links_behind_text() {
+        let link = json!({ "type": "text_link", "offset": 0, "length": 4, "url": URL });
+        let replied = json!({ "text": "here", "entities": [link] });
+        assert_eq!(reply_to(replied, "boot"), expected());
+    }
+
+    #[test]
+    fn takes_urls_among_the_words() {
+        assert_eq!(reply_to(json!({ "text": TEXT }), "boot"), expected());
+        assert_eq!(
+            reply_to(json!({ "text": "no link" }), "boot").as_deref(),
+            Ok("boot")
+        );
+    }
+
+    #[test]
+    fn takes_the_caption_of_documents() {
+        let replied = json!({ "document": document(), "caption": TEXT });
+        assert_eq!(reply_to(replied, "boot"), expected());
+    }
+
+    #[test]
+    fn refuses_documents_without_a_link() {
+        let err = reply_to(json!({ "document": document() }), "boot").unwrap_err();
+        assert!(err.contains("ota.zip"), "{err}");
+    }
+
+    #[test]
+    fn prefers_an_explicit_url() {
+        let explicit = "https://b.com/other.zip boot";
+        assert_eq!(
+            reply_to(json!({ "document": document() }), explicit).as_deref(),
+            Ok(explicit)
+        );
+        assert_eq!(
+            with_reply_url(&message(json!({ "text": "/dump" })), "boot".to_string()).as_deref(),
+            Ok("boot")
+        );
+    }
+}
@@ -1,10 +1,46 @@
-use anyhow::Result;
+use crate::utils;
+use anyhow::{Context, Result, anyhow};
+use log::{error, info};
+use reqwest::Url;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
-use std::process::exit;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, OnceLock, RwLock};
+use std::time::Duration;
+use toml::{Table, Value};
+
+const DEFAULT_PATH: &str = "config.toml";
+/// Prefix of environment variables overriding config keys, as in `PEB_TOKEN`.
+const ENV_PREFIX: &str = "PEB_";
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+/// Keys read once at startup, a trailing `_` covers every key with that prefix.
+const RESTART_KEYS: &[&str] = &[
+    "TOKEN",
+    "API_",
+    "RUST_LOG",
+    "HISTORY_DB",
+    "WEBHOOK_",
+    "FILE_SERVER_",
+    "FILE_LINK_",
+    "METRICS_LISTEN",
+];
+
+/// Config file given with `--config`.
+static PATH: OnceLock<PathBuf> = OnceLock::new();
+/// Config loaded by [`init`], which settings in [`RESTART_KEYS`] keep using.
+static STARTED: OnceLock<Arc<Config>> = OnceLock::new();
+/// Config used for new work, replaced by [`set`] on reload.
+static CURRENT: LazyLock<RwLock<Arc<Config>>> = LazyLock::new(Default::default);
+
+tokio::task_local! {
+    /// Config a job started with, see [`scope`].
+    static SNAPSHOT: Arc<Config>;
+}
 
 #[derive(Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
     #[serde(rename = "TOKEN")]
     pub token: String,
@@ -14,12 +50,89 @@ pub struct Config {
     rust_log: String,
     #[serde(rename = "SUPPORTED_PARTITIONS")]
     pub supported_partitions: Vec<String>,
+    #[serde(rename = "ALLOWED_USERS")]
+    pub allowed_users: Vec<u64>,
+    #[serde(rename = "ALLOWED_CHATS")]
+    pub allowed_chats: Vec<i64>,
+    #[serde(rename = "ADMINS")]
+    pub admins: Vec<u64>,
+    #[serde(rename = "MAX_JOBS")]
+    pub max_jobs: usize,
+    #[serde(rename = "MAX_JOBS_PER_USER")]
+    pub max_jobs_per_user: usize,
+    #[serde(rename = "HISTORY_DB")]
+    pub history_db: PathBuf,
+    #[serde(rename = "WEBHOOK_URL", skip_serializing_if = "Option::is_none")]
+    pub webhook_url: Option<String>,
+    #[serde(rename = "WEBHOOK_LISTEN")]
+    pub webhook_listen: String,
+    #[serde(rename = "WEBHOOK_SECRET", skip_serializing_if = "Option::is_none")]
+    pub webhook_secret: Option<String>,
+    #[serde(rename = "WEBHOOK_TLS_CERT", skip_serializing_if = "Option::is_none")]
+    pub webhook_tls_cert: Option<PathBuf>,
+    #[serde(rename = "WEBHOOK_TLS_KEY", skip_serializing_if = "Option::is_none")]
+    pub webhook_tls_key: Option<PathBuf>,
+    #[serde(rename = "WEBHOOK_SELF_SIGNED")]
+    pub webhook_self_signed: bool,
+    #[serde(rename = "WEBHOOK_DROP_PENDING_UPDATES")]
+    pub webhook_drop_pending_updates: bool,
+    #[serde(rename = "FILE_SERVER_LISTEN", skip_serializing_if = "Option::is_none")]
+    pub file_server_listen: Option<String>,
+    #[serde(rename = "FILE_SERVER_URL", skip_serializing_if = "Option::is_none")]
+    pub file_server_url: Option<String>,
+    #[serde(rename = "FILE_SERVER_DIR")]
+    pub file_server_dir: PathBuf,
+    #[serde(rename = "FILE_SERVER_MAX_SIZE")]
+    pub file_server_max_size: String,
+    #[serde(rename = "FILE_LINK_TTL")]
+    pub file_link_ttl: u64,
+    #[serde(rename = "FILE_LINK_SINGLE_USE")]
+    pub file_link_single_use: bool,
+    #[serde(rename = "API_LISTEN", skip_serializing_if = "Option::is_none")]
+    pub api_listen: Option<String>,
+    #[serde(rename = "API_TOKENS")]
+    pub api_tokens: Vec<String>,
+    #[serde(rename = "API_RESULT_TTL")]
+    pub api_result_ttl: u64,
+    #[serde(rename = "METRICS_LISTEN", skip_serializing_if = "Option::is_none")]
+    pub metrics_listen: Option<String>,
+    #[serde(rename = "URL_ALLOWED_SCHEMES")]
+    pub url_allowed_schemes: Vec<String>,
+    #[serde(rename = "URL_ALLOWED_PORTS")]
+    pub url_allowed_ports: Vec<u16>,
+    #[serde(rename = "URL_ALLOWLIST")]
+    pub url_allowlist: Vec<String>,
+    #[serde(rename = "ROM_INDEXES")]
+    pub rom_indexes: Vec<RomIndexConfig>,
+    #[serde(rename = "PROXY", skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    #[serde(rename = "PROXY_OVERRIDES")]
+    pub proxy_overrides: HashMap<String, String>,
+    #[serde(rename = "COMMAND_PERMISSIONS")]
+    pub command_permissions: HashMap<String, HashMap<String, bool>>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "TYPE", rename_all = "lowercase", deny_unknown_fields)]
+pub enum RomIndexConfig {
+    Json {
+        #[serde(rename = "URL")]
+        url: String,
+    },
+    Updater {
+        #[serde(rename = "URL")]
+        url: String,
+    },
+    File {
+        #[serde(rename = "PATH")]
+        path: PathBuf,
+    },
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
-            token: "YOUR_BOT_TOKEN".to_string(),
+            token: String::new(),
             api_url: "https://api.telegram.org".to_string(),
             rust_log: "debug".to_string(),
             supported_partitions: vec![
@@ -36,26 +149,389 @@ impl Default for Config {
                 "vendor_boot".to_string(),
                 "vendor_dlkm".to_string(),
             ],
+            allowed_users: Vec::new(),
+            allowed_chats: Vec::new(),
+            admins: Vec::new(),
+            max_jobs: default_max_jobs(),
+            max_jobs_per_user: default_max_jobs_per_user(),
+            history_db: default_history_db(),
+            webhook_url: None,
+            webhook_listen: default_webhook_listen(),
+            webhook_secret: None,
+            webhook_tls_cert: None,
+            webhook_tls_key: None,
+            webhook_self_signed: false,
+            webhook_drop_pending_updates: false,
+            file_server_listen: None,
+            file_server_url: None,
+            file_server_dir: default_file_server_dir(),
+            file_server_max_size: default_file_server_max_size(),
+            file_link_ttl: default_file_link_ttl(),
+            file_link_single_use: false,
+            api_listen: None,
+            api_tokens: Vec::new(),
+            api_result_ttl: default_api_result_ttl(),
+            metrics_listen: None,
+            url_allowed_schemes: default_url_allowed_schemes(),
+            url_allowed_ports: default_url_allowed_ports(),
+            url_allowlist: Vec::new(),
+            rom_indexes: Vec::new(),
+            proxy: None,
+            proxy_overrides: HashMap::new(),
+            command_permissions: HashMap::new(),
+        }
+    }
+}
+
+fn default_max_jobs() -> usize {
+    2
+}
+
+fn default_max_jobs_per_user() -> usize {
+    1
+}
+
+fn default_history_db() -> PathBuf {
+    PathBuf::from("history.db")
+}
+
+fn default_webhook_listen() -> String {
+    "0.0.0.0:8443".to_string()
+}
+
+fn default_file_server_dir() -> PathBuf {
+    PathBuf::from("files")
+}
+
+fn default_file_server_max_size() -> String {
+    "20G".to_string()
+}
+
+fn default_file_link_ttl() -> u64 {
+    24 * 3600
+}
+
+fn default_api_result_ttl() -> u64 {
+    3600
+}
+
+fn default_url_allowed_schemes() -> Vec<String> {
+    vec!["http".to_string(), "https".to_string()]
+}
+
+fn default_url_allowed_ports() -> Vec<u16> {
+    vec![80, 443]
+}
+
+impl Config {
+    /// Checks that `TOKEN` is set, which only the bot needs.
+    pub fn check_token(&self) -> Result<()> {
+        if self.token.is_empty() || self.token == "YOUR_BOT_TOKEN" {
+            return Err(anyhow!(
+                "TOKEN is not set, put it in the config file or {ENV_PREFIX}TOKEN"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Entries of `partitions` missing from a non-empty `SUPPORTED_PARTITIONS`.
+    pub fn unsupported_partitions<'a>(
+        &self,
+        partitions: impl IntoIterator<Item = &'a str>,
+    ) -> Vec<&'a str> {
+        if self.supported_partitions.is_empty() {
+            return Vec::new();
+        }
+        partitions
+            .into_iter()
+            .filter(|p| !self.supported_partitions.iter().any(|s| s == p))
+            .collect()
+    }
+
+    /// Checks values the types alone don't, naming the offending key.
+    fn validate(&self) -> Result<()> {
+        Url::parse(&self.api_url).context("Invalid API_URL")?;
+        if self.max_jobs == 0 {
+            return Err(anyhow!("MAX_JOBS must be at least 1"));
+        }
+        if self.max_jobs_per_user == 0 {
+            return Err(anyhow!("MAX_JOBS_PER_USER must be at least 1"));
+        }
+        if self.url_allowed_schemes.is_empty() {
+            return Err(anyhow!("URL_ALLOWED_SCHEMES must not be empty"));
+        }
+        if let Some(url) = &self.webhook_url {
+            Url::parse(url).context("Invalid WEBHOOK_URL")?;
+        }
+        self.webhook_listen
+            .parse::<SocketAddr>()
+            .context("Invalid WEBHOOK_LISTEN")?;
+        match (&self.webhook_tls_cert, &self.webhook_tls_key) {
+            (Some(_), None) => return Err(anyhow!("WEBHOOK_TLS_CERT needs WEBHOOK_TLS_KEY")),
+            (None, Some(_)) => return Err(anyhow!("WEBHOOK_TLS_KEY needs WEBHOOK_TLS_CERT")),
+            _ => {}
         }
+        if self.webhook_self_signed && self.webhook_tls_cert.is_none() {
+            return Err(anyhow!("WEBHOOK_SELF_SIGNED needs WEBHOOK_TLS_CERT"));
+        }
+        if let Some(listen) = &self.file_server_listen {
+            listen
+                .parse::<SocketAddr>()
+                .context("Invalid FILE_SERVER_LISTEN")?;
+            let url = self
+                .file_server_url
+                .as_deref()
+                .context("FILE_SERVER_LISTEN needs FILE_SERVER_URL")?;
+            Url::parse(url).context("Invalid FILE_SERVER_URL")?;
+        }
+        utils::parse_size(&self.file_server_max_size).context("Invalid FILE_SERVER_MAX_SIZE")?;
+        if self.file_link_ttl == 0 {
+            return Err(anyhow!("FILE_LINK_TTL must be at least 1"));
+        }
+        if let Some(listen) = &self.api_listen {
+            listen.parse::<SocketAddr>().context("Invalid API_LISTEN")?;
+            if self.api_tokens.iter().all(|t| t.is_empty()) {
+                return Err(anyhow!("API_LISTEN needs API_TOKENS"));
+            }
+        }
+        if let Some(listen) = &self.metrics_listen {
+            listen
+                .parse::<SocketAddr>()
+                .context("Invalid METRICS_LISTEN")?;
+        }
+        Ok(())
     }
 }
 
-pub fn load_config() -> Result<Config> {
-    let config_path = Path::new("config.toml");
-    if !config_path.exists() {
-        println!("Config file not found, please check config.toml");
-        let default_config = Config::default();
-        let toml_string =
-            toml::to_string_pretty(&default_config).expect("Create default config error");
-        fs::write(config_path, toml_string)?;
-        exit(1)
+/// Loads the config from `path` (`--config`) or `config.toml` and makes it current.
+pub fn init(path: Option<PathBuf>) -> Result<Arc<Config>> {
+    let explicit = path.is_some();
+    let path = path.unwrap_or_else(|| PathBuf::from(DEFAULT_PATH));
+    if explicit && !path.exists() {
+        return Err(anyhow!("Config file {} not found", path.display()));
     }
-    let contents = fs::read_to_string(config_path)?;
-    let config: Config = toml::from_str(&contents).expect("Parse config file error");
+    let _ = PATH.set(path);
+    let config = load()?;
     if std::env::var("RUST_LOG").is_err() {
         unsafe {
             std::env::set_var("RUST_LOG", &config.rust_log);
         }
     }
+    set(config);
+    let _ = STARTED.set(get());
+    Ok(get())
+}
+
+pub fn path() -> &'static Path {
+    PATH.get().map_or(Path::new(DEFAULT_PATH), PathBuf::as_path)
+}
+
+/// Reads and validates the config file with `PEB_*` overrides applied.
+///
+/// A missing file leaves every key at its default, so the bot can be
+/// configured through the environment alone.
+pub fn load() -> Result<Config> {
+    let path = path();
+    let mut table = match fs::read_to_string(path) {
+        Ok(contents) => toml::from_str::<Table>(&contents)
+            .with_context(|| format!("Failed to parse {}", path.display()))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Table::new(),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    apply_env(&mut table, std::env::vars());
+    parse(table)
+}
+
+fn parse(table: Table) -> Result<Config> {
+    let config: Config = serde_path_to_error::deserialize(Value::Table(table)).map_err(|e| {
+        let message = e.inner().message();
+        let path = e.path().to_string();
+        match message
+            .strip_prefix("unknown field `")
+            .and_then(|m| m.split_once('`'))
+        {
+            // The path ends at the key, except inside tagged tables such as ROM_INDEXES.
+            Some((key, _)) if path.ends_with(key) => anyhow!("Unknown key {path}"),
+            Some((key, _)) => anyhow!("Unknown key {path}.{key}"),
+            None => anyhow!("Invalid {path}: {message}"),
+        }
+    })?;
+    config.validate()?;
     Ok(config)
 }
+
+/// Keys whose value in `config` differs from the config the process started
+/// with, and only takes effect after a restart.
+pub fn restart_needed(config: &Config) -> Vec<String> {
+    let Some(started) = STARTED.get() else {
+        return Vec::new();
+    };
+    let (Ok(old), Ok(new)) = (Table::try_from(&**started), Table::try_from(config)) else {
+        return Vec::new();
+    };
+    let mut keys: Vec<String> = old
+        .keys()
+        .chain(new.keys())
+        .filter(|key| {
+            RESTART_KEYS.iter().any(|k| match k.strip_suffix('_') {
+                Some(_) => key.starts_with(k),
+                None => *key == k,
+            })
+        })
+        .filter(|key| old.get(*key) != new.get(*key))
+        .cloned()
+        .collect();
+    keys.sort();
+    keys.dedup();
+    keys
+}
+
+/// Overrides keys with `PEB_<KEY>` variables.
+///
+/// Values of string keys are taken as is, others are parsed as TOML values,
+/// for example `PEB_ADMINS=[1, 2]`. Unknown keys fail in [`load`] like
+/// unknown keys in the file.
+fn apply_env(table: &mut Table, vars: impl Iterator<Item = (String, String)>) {
+    let defaults = Table::try_from(Config::default()).unwrap_or_default();
+    for (name, raw) in vars {
+        let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        // Unset optional keys are all strings or paths.
+        let value = match defaults.get(key) {
+            None | Some(Value::String(_)) => Value::String(raw),
+            Some(_) => toml::from_str::<Table>(&format!("value = {raw}"))
+                .ok()
+                .and_then(|mut t| t.remove("value"))
+                .unwrap_or(Value::String(raw)),
+        };
+        table.insert(key.to_string(), value);
+    }
+}
+
+/// Config for the current job, or the current one outside of jobs.
+pub fn get() -> Arc<Config> {
+    SNAPSHOT
+        .try_with(Arc::clone)
+        .unwrap_or_else(|_| CURRENT.read().unwrap().clone())
+}
+
+/// Makes a config current that lets tests fetch from servers on `localhost`.
+#[cfg(test)]
+pub fn set_for_tests() {
+    set(Config {
+        url_allowlist: vec!["localhost".to_string()],
+        url_allowed_ports: Vec::new(),
+        ..Config::default()
+    });
+}
+
+/// Makes `config` current, jobs already running keep theirs.
+pub fn set(config: Config) {
+    *CURRENT.write().unwrap() = Arc::new(config);
+}
+
+/// Runs `fut` with the config current at this point, whatever is reloaded meanwhile.
+pub async fn scope<F: Future>(fut: F) -> F::Output {
+    SNAPSHOT.scope(get(), fut).await
+}
+
+/// Calls `reload` whenever the config file changes.
+///
+/// The file is polled, so the watcher works the same for edits in place,
+/// replaced files and mounted config maps.
+pub fn watch<F, Fut>(reload: F)
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<()>> + Send,
+{
+    let modified = || fs::metadata(path()).and_then(|m| m.modified()).ok();
+    tokio::spawn(async move {
+        let mut last = modified();
+        loop {
+            tokio::time::sleep(WATCH_INTERVAL).await;
+            let current = modified();
+            if current == last {
+                continue;
+            }
+            last = current;
+            info!("{} changed, reloading", path().display());
+            if let Err(e) = reload().await {
+                error!("Failed to reload config, keeping the previous one: {e:#}");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_str(contents: &str, vars: &[(&str, &str)]) -> Result<Config> {
+        let mut table: Table = toml::from_str(contents).unwrap();
+        let vars = vars.iter().map(|(k, v)| (k.to_string(), v.to_string()));
+        apply_env(&mut table, vars);
+        parse(table)
+    }
+
+    #[test]
+    fn applies_env_overrides() {
+        let config = parse_str(
+            "TOKEN = \"file\"\nMAX_JOBS = 2",
+            &[
+                ("PEB_TOKEN", "env"),
+                ("PEB_ADMINS", "[1, 2]"),
+                ("HOME", "/root"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(config.token, "env");
+        assert_eq!(config.max_jobs, 2);
+        assert_eq!(config.admins, [1, 2]);
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        let err = parse_str("MAX_JOB = 2", &[]).err().unwrap().to_string();
+        assert_eq!(err, "Unknown key MAX_JOB");
+        let err = parse_str("", &[("PEB_ADMIN", "[1]")])
+            .err()
+            .unwrap()
+            .to_string();
+        assert_eq!(err, "Unknown key ADMIN");
+        let err = parse_str(
+            "[[ROM_INDEXES]]\nTYPE = \"file\"\nPATH = \"a\"\nURL = \"b\"",
+            &[],
+        )
+        .err()
+        .unwrap()
+        .to_string();
+        assert_eq!(err, "Unknown key ROM_INDEXES[0].URL");
+    }
+
+    #[test]
+    fn reports_changed_restart_keys() {
+        let _ = STARTED.set(Arc::new(Config::default()));
+        let mut config = Config {
+            max_jobs: 7,
+            admins: vec![1],
+            ..Config::default()
+        };
+        assert!(restart_needed(&config).is_empty());
+        config.proxy = Some("socks5h://127.0.0.1:1080".to_string());
+        assert!(restart_needed(&config).is_empty());
+        config.api_result_ttl = 60;
+        config.webhook_listen = "127.0.0.1:9443".to_string();
+        assert_eq!(
+            restart_needed(&config),
+            ["API_RESULT_TTL", "WEBHOOK_LISTEN"]
+        );
+    }
+
+    #[test]
+    fn example_config_is_valid() {
+        let example = include_str!("../config.toml.example");
+        let config = parse(toml::from_str(example).unwrap());
+        assert!(config.is_ok(), "{}", config.err().unwrap());
+    }
+}
@@ -0,0 +1,135 @@
+use crate::jobs;
+use crate::patch_boot::get_kmi;
+use crate::payload::{Partition, RomInfo, dump_partition, get_build_fingerprint, get_rom_info};
+use crate::tool::{Tool, ToolManager};
+use anyhow::Result;
+use log::{info, warn};
+use payload_dumper::utils::format_size;
+use std::collections::BTreeMap;
+
+struct RomSide {
+    info: RomInfo,
+    fingerprint: Option<String>,
+    kernel_version: Option<String>,
+}
+
+pub async fn diff_image(old_url: String, new_url: String) -> Result<String> {
+    info!("Comparing images: {old_url} -> {new_url}");
+    let (old, new) = tokio::try_join!(get_side(old_url), get_side(new_url))?;
+
+    let old_parts = partition_map(&old.info);
+    let new_parts = partition_map(&new.info);
+
+    let mut added = Vec::new();
+    let mut removed = Vec::new();
+    let mut resized = Vec::new();
+    let mut changed = Vec::new();
+    let mut unchanged = 0;
+
+    for (name, p) in &new_parts {
+        match old_parts.get(name) {
+            None => added.push(format!("  + {name}: {}", p.size_readable)),
+            Some(o) => {
+                let (old_size, new_size) = (o.size_bytes, p.size_bytes);
+                if old_size != new_size {
+                    resized.push(format!(
+                        "  ~ {name}: {} -> {}",
+                        format_size(old_size),
+                        format_size(new_size)
+                    ));
+                } else if o.hash != p.hash {
+                    changed.push(format!("  * {name}"));
+                } else {
+                    unchanged += 1;
+                }
+            }
+        }
+    }
+    for (name, p) in &old_parts {
+        if !new_parts.contains_key(name) {
+            removed.push(format!("  - {name}: {}", p.size_readable));
+        }
+    }
+
+    let mut ret = String::new();
+    ret.push_str(&compare(
+        "Security patch level",
+        old.info.security_patch_level.as_deref(),
+        new.info.security_patch_level.as_deref(),
+    ));
+    ret.push_str(&compare(
+        "Fingerprint",
+        old.fingerprint.as_deref(),
+        new.fingerprint.as_deref(),
+    ));
+    ret.push_str(&compare(
+        "Kernel version",
+        old.kernel_version.as_deref(),
+        new.kernel_version.as_deref(),
+    ));
+    for (title, lines) in [
+        ("Added", added),
+        ("Removed", removed),
+        ("Resized", resized),
+        ("Changed hash", changed),
+    ] {
+        if !lines.is_empty() {
+            ret.push_str(&format!(
+                "{title} ({}):\n{}\n",
+                lines.len(),
+                lines.join("\n")
+            ));
+        }
+    }
+    ret.push_str(&format!("Unchanged partitions: {unchanged}"));
+    Ok(ret)
+}
+
+async fn get_side(url: String) -> Result<RomSide> {
+    let info = get_rom_info(url.clone()).await?;
+    let fingerprint = get_build_fingerprint(url.clone())
+        .await
+        .unwrap_or_else(|e| {
+            warn!("Failed to get fingerprint of {url}: {e}");
+            None
+        });
+    let kernel_version = get_kernel_version(url.clone(), &info)
+        .await
+        .unwrap_or_else(|e| {
+            warn!("Failed to get kernel version of {url}: {e}");
+            None
+        });
+    jobs::check_cancelled()?;
+    Ok(RomSide {
+        info,
+        fingerprint,
+        kernel_version,
+    })
+}
+
+async fn get_kernel_version(url: String, info: &RomInfo) -> Result<Option<String>> {
+    let has = |name: &str| info.partitions.iter().any(|p| p.name == name);
+    let Some(image) = ["boot", "init_boot"].into_iter().find(|p| has(p)) else {
+        return Ok(None);
+    };
+    let dumped = dump_partition(url, image.to_string()).await?;
+    let magiskboot = ToolManager::new()?.get_magiskboot().get();
+    let kernel = get_kmi(&magiskboot, dumped.dir.path(), &format!("{image}.img")).await?;
+    Ok(Some(kernel.version))
+}
+
+fn partition_map(info: &RomInfo) -> BTreeMap<&str, &Partition> {
+    info.partitions
+        .iter()
+        .map(|p| (p.name.as_str(), p))
+        .collect()
+}
+
+fn compare(title: &str, old: Option<&str>, new: Option<&str>) -> String {
+    let (old, new) = (old.unwrap_or("N/A"), new.unwrap_or("N/A"));
+    if old == new {
+        format!("{title}: {old} (unchanged)\n")
+    } else {
+        format!("{title}:\n  {old}\n  -> {new}\n")
+    }
+}
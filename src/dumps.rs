@@ -0,0 +1,86 @@
+use crate::jobs::{self, Dedup, Joined, Lead, Stage};
+use crate::payload::Dumped;
+use anyhow::Result;
+use reqwest::Url;
+use std::future::Future;
+use std::sync::{Arc, LazyLock};
+
+type SharedDump = Result<Arc<Dumped>, String>;
+
+/// Dumps in flight, see [`dump_key`].
+static DUMPS: LazyLock<Dedup<SharedDump>> = LazyLock::new(Dedup::default);
+
+/// Identifies identical dumps by the resolved URL and the set of partitions.
+fn dump_key(url: &str, partition: &str) -> String {
+    let url = match Url::parse(url.trim()) {
+        Ok(mut url) => {
+            url.set_fragment(None);
+            url.to_string()
+        }
+        Err(_) => url.trim().to_string(),
+    };
+    let mut partitions = partition
+        .split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .collect::<Vec<_>>();
+    partitions.sort_unstable();
+    partitions.dedup();
+    format!("{url} {}", partitions.join(","))
+}
+
+/// Dumps `partition` from `url` through `lead`, unless another job already dumps the same.
+///
+/// `url` must be resolved already, so links resolving to the same file share a dump.
+/// In that case the current job attaches to it, `attached` is told its id and the
+/// result is shared; if it ends without one, the current job tries again. `None`
+/// means `lead` ended the job before dumping. The returned lead must be held while
+/// the files are in use, so later requests still attach.
+pub async fn shared<E, L, A>(
+    url: &str,
+    partition: &str,
+    lead: impl FnOnce() -> L,
+    mut attached: impl FnMut(u64) -> A,
+) -> Result<Option<(Result<Arc<Dumped>>, Option<Lead<SharedDump>>)>, E>
+where
+    L: Future<Output = Result<Option<Result<Arc<Dumped>>>, E>>,
+    A: Future<Output = Result<(), E>>,
+{
+    let mut lead = Some(lead);
+    let token = jobs::token();
+    loop {
+        match DUMPS.join(dump_key(url, partition)) {
+            Joined::Leader(held) => {
+                let Some(lead) = lead.take() else {
+                    return Ok(None);
+                };
+                let Some(dumped) = lead().await? else {
+                    return Ok(None);
+                };
+                match &dumped {
+                    // Attached jobs try again on their own rather than share the cancellation.
+                    Err(e) if e.is::<jobs::Cancelled>() => {}
+                    Ok(dumped) => held.publish(Ok(dumped.clone())),
+                    Err(e) => held.publish(Err(e.to_string())),
+                }
+                return Ok(Some((dumped, Some(held))));
+            }
+            Joined::Follower(leader, result) => {
+                jobs::attach(leader);
+                jobs::set_stage(Stage::Extracting);
+                attached(leader).await?;
+                let followed = tokio::select! {
+                    followed = jobs::follow(result) => followed,
+                    _ = token.cancelled() => None,
+                };
+                if token.is_cancelled() {
+                    return Ok(Some((Err(jobs::Cancelled.into()), None)));
+                }
+                match followed {
+                    Some(result) => return Ok(Some((result.map_err(anyhow::Error::msg), None))),
+                    None => jobs::detach(),
+                }
+            }
+        }
+    }
+}
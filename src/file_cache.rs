@@ -0,0 +1,76 @@
+use crate::config::Config;
+use crate::stats;
+use anyhow::Result;
+use log::{debug, error};
+use rusqlite::{Connection, OptionalExtension, params};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Telegram file_ids of uploaded partitions, keyed by their payload hash.
+static DB: OnceLock<Mutex<Connection>> = OnceLock::new();
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS files (
+    hash TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    file_id TEXT NOT NULL,
+    created INTEGER NOT NULL
+);
+";
+
+pub fn init(config: &Config) -> Result<()> {
+    let conn = Connection::open(&config.history_db)?;
+    conn.execute_batch(SCHEMA)?;
+    let _ = DB.set(Mutex::new(conn));
+    Ok(())
+}
+
+/// Remembers an uploaded partition, errors are only logged.
+pub fn save(hash: &str, name: &str, size: u64, file_id: &str) {
+    let Some(db) = DB.get() else {
+        return;
+    };
+    let created = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default();
+    debug!("Caching {name} ({hash}) as {file_id}");
+    if let Err(e) = db.lock().unwrap().execute(
+        "INSERT OR REPLACE INTO files (hash, name, size, file_id, created) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![hash, name, size as i64, file_id, created],
+    ) {
+        error!("Failed to cache file_id of {name}: {e}");
+    }
+}
+
+/// file_id of a partition uploaded before with the same payload hash.
+pub fn get(hash: &str) -> Option<String> {
+    let db = DB.get()?;
+    let file_id = db
+        .lock()
+        .unwrap()
+        .query_row(
+            "SELECT file_id FROM files WHERE hash = ?1",
+            params![hash],
+            |row| row.get(0),
+        )
+        .optional()
+        .unwrap_or_else(|e| {
+            error!("Failed to look up cached file {hash}: {e}");
+            None
+        });
+    stats::cache_lookup(file_id.is_some());
+    file_id
+}
+
+/// Number of cached uploads.
+pub fn count() -> Result<u64> {
+    let db = DB
+        .get()
+        .ok_or_else(|| anyhow::anyhow!("File cache is not available"))?
+        .lock()
+        .unwrap();
+    let count: i64 = db.query_row("SELECT COUNT(*) FROM files", [], |row| row.get(0))?;
+    Ok(count as u64)
+}
@@ -0,0 +1,319 @@
+use crate::config::Config;
+use crate::utils;
+use anyhow::{Context, Result, anyhow};
+use axum::Router;
+use axum::body::Body;
+use axum::extract::Path as UrlPath;
+use axum::http::{HeaderMap, Method, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use log::{debug, error, info};
+use std::collections::HashMap;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio_util::io::ReaderStream;
+
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+/// Length of link tokens, which are also the names of the served files.
+const TOKEN_LEN: usize = 32;
+/// User agents fetching links for previews rather than downloads.
+const PREVIEW_AGENTS: &[&str] = &[
+    "TelegramBot",
+    "Twitterbot",
+    "facebookexternalhit",
+    "Slackbot",
+    "Discordbot",
+    "WhatsApp",
+];
+
+/// Settings of the running server, unset while it is disabled.
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+/// Published files by their token.
+static LINKS: LazyLock<Mutex<HashMap<String, Link>>> = LazyLock::new(Default::default);
+
+struct Settings {
+    dir: PathBuf,
+    url: String,
+    ttl: Duration,
+    single_use: bool,
+    max_size: u64,
+}
+
+struct Link {
+    name: String,
+    size: u64,
+    path: PathBuf,
+    created: Instant,
+}
+
+/// A file published by [`publish`].
+pub struct PublicFile {
+    pub name: String,
+    pub size: u64,
+    pub url: String,
+}
+
+/// Starts serving published files if `FILE_SERVER_LISTEN` is set.
+///
+/// Links live in memory only, so files left over from a previous run are
+/// removed on startup. Other files in the directory are left alone.
+pub fn init(config: &Config) -> Result<()> {
+    let Some(listen) = &config.file_server_listen else {
+        return Ok(());
+    };
+    let address: SocketAddr = listen.parse().context("Invalid FILE_SERVER_LISTEN")?;
+    let settings = Settings {
+        dir: config.file_server_dir.clone(),
+        url: config
+            .file_server_url
+            .as_deref()
+            .context("FILE_SERVER_LISTEN needs FILE_SERVER_URL")?
+            .trim_end_matches('/')
+            .to_string(),
+        ttl: Duration::from_secs(config.file_link_ttl),
+        single_use: config.file_link_single_use,
+        max_size: utils::parse_size(&config.file_server_max_size)
+            .context("Invalid FILE_SERVER_MAX_SIZE")?,
+    };
+    fs::create_dir_all(&settings.dir)?;
+    remove_stale(&settings.dir)?;
+    let _ = SETTINGS.set(settings);
+
+    info!("Serving files on {address}");
+    let app = Router::new().route("/files/{token}/{name}", get(serve));
+    tokio::spawn(async move {
+        if let Err(e) = axum_server::bind(address)
+            .serve(app.into_make_service())
+            .await
+        {
+            error!("File server error: {e}");
+        }
+    });
+    tokio::spawn(async {
+        loop {
+            tokio::time::sleep(CLEANUP_INTERVAL).await;
+            prune(0);
+        }
+    });
+    Ok(())
+}
+
+pub fn enabled() -> bool {
+    SETTINGS.get().is_some()
+}
+
+/// How long links stay valid and whether they work only once.
+pub fn ttl() -> Option<(Duration, bool)> {
+    SETTINGS.get().map(|s| (s.ttl, s.single_use))
+}
+
+/// Makes `path` downloadable under an unguessable link.
+///
+/// The file is hard linked (or copied) into `FILE_SERVER_DIR`, so the caller
+/// may remove its own copy right away.
+pub fn publish(path: &Path) -> Result<PublicFile> {
+    let settings = SETTINGS
+        .get()
+        .ok_or_else(|| anyhow!("The file server is disabled"))?;
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let size = path.metadata()?.len();
+    if size > settings.max_size {
+        return Err(anyhow!("{name} is larger than FILE_SERVER_MAX_SIZE"));
+    }
+    prune(size);
+
+    let token = new_token()?;
+    let served = settings.dir.join(&token);
+    if fs::hard_link(path, &served).is_err() {
+        fs::copy(path, &served)?;
+    }
+    debug!("Publishing {} as {token}", path.display());
+    LINKS.lock().unwrap().insert(
+        token.clone(),
+        Link {
+            name: name.clone(),
+            size,
+            path: served,
+            created: Instant::now(),
+        },
+    );
+    Ok(PublicFile {
+        url: format!("{}/files/{token}/{name}", settings.url),
+        name,
+        size,
+    })
+}
+
+/// Removes expired links, then the oldest ones until `extra` more bytes fit.
+fn prune(extra: u64) {
+    let Some(settings) = SETTINGS.get() else {
+        return;
+    };
+    let mut links = LINKS.lock().unwrap();
+    let expired = links
+        .iter()
+        .filter(|(_, l)| l.created.elapsed() >= settings.ttl)
+        .map(|(token, _)| token.clone())
+        .collect::<Vec<_>>();
+    for token in expired {
+        remove(&mut links, &token);
+    }
+    let mut total: u64 = links.values().map(|l| l.size).sum();
+    while total + extra > settings.max_size {
+        let Some(oldest) = links
+            .iter()
+            .min_by_key(|(_, l)| l.created)
+            .map(|(token, _)| token.clone())
+        else {
+            break;
+        };
+        total -= remove(&mut links, &oldest);
+    }
+}
+
+/// Drops link `token` and its file, returns the freed size.
+fn remove(links: &mut HashMap<String, Link>, token: &str) -> u64 {
+    let Some(link) = links.remove(token) else {
+        return 0;
+    };
+    debug!("Removing published file {} ({token})", link.name);
+    if let Err(e) = fs::remove_file(&link.path) {
+        error!("Failed to remove {}: {e}", link.path.display());
+    }
+    link.size
+}
+
+/// Removes files named like link tokens, left over from a previous run.
+fn remove_stale(dir: &Path) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() && is_token(&entry.file_name().to_string_lossy()) {
+            debug!("Removing stale published file {}", entry.path().display());
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+fn is_token(name: &str) -> bool {
+    name.len() == TOKEN_LEN
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+fn new_token() -> Result<String> {
+    let mut bytes = [0u8; TOKEN_LEN / 2];
+    getrandom::fill(&mut bytes).map_err(|e| anyhow!("Failed to generate a link token: {e}"))?;
+    Ok(bytes.iter().map(|b| format!("{b:02x}")).collect())
+}
+
+/// Whether the request only looks at the link, without downloading it.
+fn is_peek(method: &Method, headers: &HeaderMap) -> bool {
+    let agent = headers
+        .get(header::USER_AGENT)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    method == Method::HEAD || PREVIEW_AGENTS.iter().any(|bot| agent.contains(bot))
+}
+
+async fn serve(
+    method: Method,
+    headers: HeaderMap,
+    UrlPath((token, name)): UrlPath<(String, String)>,
+) -> Response {
+    let Some(settings) = SETTINGS.get() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let peek = is_peek(&method, &headers);
+    let (path, size) = {
+        let mut links = LINKS.lock().unwrap();
+        match links.get(&token) {
+            Some(link) if link.name == name && link.created.elapsed() < settings.ttl => {
+                let found = (link.path.clone(), link.size);
+                if settings.single_use && !peek {
+                    links.remove(&token);
+                }
+                found
+            }
+            _ => return StatusCode::NOT_FOUND.into_response(),
+        }
+    };
+    match method {
+        Method::HEAD => return (download_headers(&name, size), ()).into_response(),
+        _ if peek => return StatusCode::NO_CONTENT.into_response(),
+        _ => {}
+    }
+    let file = match tokio::fs::File::open(&path).await {
+        Ok(file) => file,
+        Err(e) => {
+            error!("Failed to open {}: {e}", path.display());
+            return StatusCode::NOT_FOUND.into_response();
+        }
+    };
+    if settings.single_use {
+        // The open handle keeps the data readable until the download ends.
+        fs::remove_file(&path).ok();
+    }
+    info!("Serving {name} ({token})");
+    download(file, &name, size)
+}
+
+/// Response streaming `file` as a download named `name`.
+pub fn download(file: tokio::fs::File, name: &str, size: u64) -> Response {
+    (
+        download_headers(name, size),
+        Body::from_stream(ReaderStream::new(file)),
+    )
+        .into_response()
+}
+
+fn download_headers(name: &str, size: u64) -> [(header::HeaderName, String); 3] {
+    [
+        (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+        (header::CONTENT_LENGTH, size.to_string()),
+        (
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{name}\""),
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn removes_only_stale_links() {
+        let dir = utils::TempDir::new().unwrap();
+        let token = new_token().unwrap();
+        for name in [token.as_str(), "notes.txt", &"g".repeat(TOKEN_LEN)] {
+            fs::write(dir.path().join(name), b"data").unwrap();
+        }
+        remove_stale(dir.path()).unwrap();
+        assert!(!dir.path().join(&token).exists());
+        assert!(dir.path().join("notes.txt").exists());
+        assert!(dir.path().join("g".repeat(TOKEN_LEN)).exists());
+    }
+
+    #[test]
+    fn previews_and_head_requests_are_peeks() {
+        let mut headers = HeaderMap::new();
+        assert!(is_peek(&Method::HEAD, &headers));
+        assert!(!is_peek(&Method::GET, &headers));
+        headers.insert(
+            header::USER_AGENT,
+            HeaderValue::from_static("TelegramBot (like TwitterBot)"),
+        );
+        assert!(is_peek(&Method::GET, &headers));
+        headers.insert(header::USER_AGENT, HeaderValue::from_static("curl/8.5.0"));
+        assert!(!is_peek(&Method::GET, &headers));
+    }
+}
@@ -0,0 +1,177 @@
+use crate::config::Config;
+use crate::jobs::JobInfo;
+use anyhow::Result;
+use log::error;
+use rusqlite::{Connection, params};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+static DB: OnceLock<Mutex<Connection>> = OnceLock::new();
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS jobs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user INTEGER NOT NULL,
+    chat INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    url TEXT,
+    partitions TEXT,
+    method TEXT,
+    kmi TEXT,
+    hashes TEXT,
+    created INTEGER NOT NULL,
+    duration REAL NOT NULL,
+    outcome TEXT NOT NULL,
+    error TEXT
+);
+CREATE INDEX IF NOT EXISTS jobs_user ON jobs (user, id);
+";
+
+#[derive(Clone, Debug)]
+pub enum Outcome {
+    Succeeded,
+    Failed(String),
+    Cancelled,
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Succeeded => "succeeded",
+            Self::Failed(_) => "failed",
+            Self::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// Details of a job collected while it runs, saved when it ends.
+#[derive(Clone, Default)]
+pub struct Entry {
+    pub url: Option<String>,
+    pub partitions: Option<String>,
+    pub method: Option<String>,
+    pub kmi: Option<String>,
+    pub hashes: Option<String>,
+    pub outcome: Option<Outcome>,
+    /// When the outcome was known, the job may linger a bit to clean up.
+    pub finished: Option<Instant>,
+}
+
+impl Entry {
+    pub fn finish(&mut self, outcome: Outcome) {
+        self.outcome = Some(outcome);
+        self.finished = Some(Instant::now());
+    }
+}
+
+/// A saved job, as shown by `/history`.
+pub struct Record {
+    pub id: i64,
+    pub user: u64,
+    pub kind: String,
+    pub url: Option<String>,
+    pub partitions: Option<String>,
+    pub method: Option<String>,
+    pub kmi: Option<String>,
+    pub hashes: Option<String>,
+    /// UTC time the job was submitted, `YYYY-MM-DD HH:MM:SS`.
+    pub created: String,
+    pub duration: f64,
+    pub outcome: String,
+    pub error: Option<String>,
+}
+
+pub fn init(config: &Config) -> Result<()> {
+    let conn = Connection::open(&config.history_db)?;
+    conn.execute_batch(SCHEMA)?;
+    let _ = DB.set(Mutex::new(conn));
+    Ok(())
+}
+
+/// Saves a finished job, errors are only logged.
+pub fn save(job: &JobInfo, outcome: &Outcome) {
+    let Some(db) = DB.get() else {
+        return;
+    };
+    let created = SystemTime::now()
+        .checked_sub(job.age())
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default();
+    let start = job.started.unwrap_or(job.created);
+    let duration = job
+        .entry
+        .finished
+        .unwrap_or_else(Instant::now)
+        .saturating_duration_since(start)
+        .as_secs_f64();
+    let error = match outcome {
+        Outcome::Failed(e) => Some(e.as_str()),
+        _ => None,
+    };
+    let entry = &job.entry;
+    if let Err(e) = db.lock().unwrap().execute(
+        "INSERT INTO jobs (user, chat, kind, url, partitions, method, kmi, hashes, created, duration, outcome, error)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+        params![
+            job.user as i64,
+            job.chat,
+            job.kind,
+            entry.url,
+            entry.partitions,
+            entry.method,
+            entry.kmi,
+            entry.hashes,
+            created,
+            duration,
+            outcome.as_str(),
+            error,
+        ],
+    ) {
+        error!("Failed to save job #{} to history: {e}", job.id);
+    }
+}
+
+/// Number of recorded jobs per outcome.
+pub fn outcome_counts() -> Result<Vec<(String, u64)>> {
+    let db = DB
+        .get()
+        .ok_or_else(|| anyhow::anyhow!("Job history is not available"))?
+        .lock()
+        .unwrap();
+    let mut stmt =
+        db.prepare("SELECT outcome, COUNT(*) FROM jobs GROUP BY outcome ORDER BY outcome")?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get::<_, i64>(1)? as u64)))?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// Latest `limit` jobs, of `user` only if given.
+pub fn recent(user: Option<u64>, limit: usize) -> Result<Vec<Record>> {
+    let db = DB
+        .get()
+        .ok_or_else(|| anyhow::anyhow!("Job history is not available"))?
+        .lock()
+        .unwrap();
+    let mut stmt = db.prepare(
+        "SELECT id, user, kind, url, partitions, method, kmi, hashes,
+                datetime(created, 'unixepoch'), duration, outcome, error
+         FROM jobs WHERE ?1 IS NULL OR user = ?1 ORDER BY id DESC LIMIT ?2",
+    )?;
+    let rows = stmt.query_map(params![user.map(|u| u as i64), limit as i64], |row| {
+        Ok(Record {
+            id: row.get(0)?,
+            user: row.get::<_, i64>(1)? as u64,
+            kind: row.get(2)?,
+            url: row.get(3)?,
+            partitions: row.get(4)?,
+            method: row.get(5)?,
+            kmi: row.get(6)?,
+            hashes: row.get(7)?,
+            created: row.get(8)?,
+            duration: row.get(9)?,
+            outcome: row.get(10)?,
+            error: row.get(11)?,
+        })
+    })?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
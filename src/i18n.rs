@@ -0,0 +1,160 @@
+use crate::config::Config;
+use anyhow::Result;
+use fluent_bundle::concurrent::FluentBundle;
+use fluent_bundle::{FluentArgs, FluentResource};
+use log::{error, warn};
+use rusqlite::{Connection, params};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{LazyLock, Mutex, OnceLock};
+use unic_langid::LanguageIdentifier;
+
+/// Locale used when a message is missing from the chosen catalog.
+pub const FALLBACK: &str = "en";
+
+/// Catalogs built into the binary, as (locale, Fluent source).
+const CATALOGS: &[(&str, &str)] = &[
+    ("en", include_str!("../locales/en.ftl")),
+    ("ru", include_str!("../locales/ru.ftl")),
+    ("zh", include_str!("../locales/zh.ftl")),
+];
+
+static BUNDLES: LazyLock<HashMap<&'static str, FluentBundle<FluentResource>>> =
+    LazyLock::new(|| {
+        CATALOGS
+            .iter()
+            .map(|(locale, source)| {
+                let lang: LanguageIdentifier = locale.parse().expect("Invalid catalog locale");
+                let mut bundle = FluentBundle::new_concurrent(vec![lang]);
+                bundle.set_use_isolating(false);
+                let resource =
+                    FluentResource::try_new(source.to_string()).unwrap_or_else(|(r, e)| {
+                        error!("Errors in catalog {locale}: {e:?}");
+                        r
+                    });
+                if let Err(e) = bundle.add_resource(resource) {
+                    error!("Duplicate messages in catalog {locale}: {e:?}");
+                }
+                (*locale, bundle)
+            })
+            .collect()
+    });
+
+/// Per-chat `/lang` settings, kept next to the job history.
+static CHAT_LOCALES: OnceLock<Mutex<(Connection, HashMap<i64, &'static str>)>> = OnceLock::new();
+
+tokio::task_local! {
+    static LOCALE: &'static str;
+}
+
+pub fn init(config: &Config) -> Result<()> {
+    let conn = Connection::open(&config.history_db)?;
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS chat_locales (chat INTEGER PRIMARY KEY, locale TEXT NOT NULL);",
+    )?;
+    let mut locales = HashMap::new();
+    {
+        let mut stmt = conn.prepare("SELECT chat, locale FROM chat_locales")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+        })?;
+        for row in rows {
+            let (chat, locale) = row?;
+            match find(&locale) {
+                Some(locale) => {
+                    locales.insert(chat, locale);
+                }
+                None => warn!("Ignoring unknown locale {locale} of chat {chat}"),
+            }
+        }
+    }
+    let _ = CHAT_LOCALES.set(Mutex::new((conn, locales)));
+    Ok(())
+}
+
+pub fn available() -> Vec<&'static str> {
+    CATALOGS.iter().map(|(locale, _)| *locale).collect()
+}
+
+/// Matches a Telegram `language_code` such as `zh-hans` or `ru` to a catalog.
+pub fn find(code: &str) -> Option<&'static str> {
+    let lang = code.split(['-', '_']).next()?.to_ascii_lowercase();
+    available().into_iter().find(|l| *l == lang)
+}
+
+/// Locale for a chat: its `/lang` setting, then the user's Telegram language.
+pub fn locale_for(chat: i64, language_code: Option<&str>) -> &'static str {
+    let chat_locale = CHAT_LOCALES
+        .get()
+        .and_then(|c| c.lock().unwrap().1.get(&chat).copied());
+    chat_locale
+        .or_else(|| language_code.and_then(find))
+        .unwrap_or(FALLBACK)
+}
+
+/// Sets the locale of `chat`, `None` goes back to each user's own language.
+pub fn set_chat_locale(chat: i64, locale: Option<&'static str>) -> Result<()> {
+    let Some(chat_locales) = CHAT_LOCALES.get() else {
+        return Err(anyhow::anyhow!("Chat settings are not available"));
+    };
+    let mut guard = chat_locales.lock().unwrap();
+    let (conn, locales) = &mut *guard;
+    match locale {
+        Some(locale) => {
+            conn.execute(
+                "INSERT OR REPLACE INTO chat_locales (chat, locale) VALUES (?1, ?2)",
+                params![chat, locale],
+            )?;
+            locales.insert(chat, locale);
+        }
+        None => {
+            conn.execute("DELETE FROM chat_locales WHERE chat = ?1", params![chat])?;
+            locales.remove(&chat);
+        }
+    }
+    Ok(())
+}
+
+/// Runs `fut` with `locale` as the language of every message it builds.
+pub async fn scope<F: Future>(locale: &'static str, fut: F) -> F::Output {
+    LOCALE.scope(locale, fut).await
+}
+
+pub fn current() -> &'static str {
+    LOCALE.try_with(|l| *l).unwrap_or(FALLBACK)
+}
+
+/// Formats message `key` in the current locale, falling back to English.
+pub fn translate(key: &str, args: Option<&FluentArgs>) -> Option<String> {
+    [current(), FALLBACK].into_iter().find_map(|locale| {
+        let bundle = BUNDLES.get(locale)?;
+        let pattern = bundle.get_message(key)?.value()?;
+        let mut errors = Vec::new();
+        let ret = bundle.format_pattern(pattern, args, &mut errors);
+        if !errors.is_empty() {
+            warn!("Errors formatting {key} in {locale}: {errors:?}");
+        }
+        Some(ret.into_owned())
+    })
+}
+
+/// Like [`translate`], showing the key itself if no catalog has it.
+pub fn message(key: &str, args: Option<&FluentArgs>) -> String {
+    translate(key, args).unwrap_or_else(|| {
+        warn!("Missing message {key}");
+        key.to_string()
+    })
+}
+
+/// `tr!("key", name = value, ..)` formats a catalog message in the current locale.
+macro_rules! tr {
+    ($key:expr) => {
+        $crate::i18n::message($key, None)
+    };
+    ($key:expr, $($name:ident = $value:expr),+ $(,)?) => {{
+        let mut args = fluent_bundle::FluentArgs::new();
+        $(args.set(stringify!($name), $value);)+
+        $crate::i18n::message($key, Some(&args))
+    }};
+}
+pub(crate) use tr;
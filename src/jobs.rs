@@ -0,0 +1,399 @@
+use crate::config::{self, Config};
+use crate::history::{self, Entry, Outcome};
+use crate::{metrics, task};
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::{LazyLock, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::futures::Notified;
+use tokio::sync::{Notify, watch};
+use tokio_util::sync::CancellationToken;
+
+pub use task::{Cancelled, Stage, check_cancelled, set_stage, token};
+
+static MANAGER: LazyLock<JobManager> = LazyLock::new(JobManager::default);
+/// Message of `/maintenance on`, new jobs are refused while set.
+static MAINTENANCE: RwLock<Option<String>> = RwLock::new(None);
+static LIMITS: LazyLock<RwLock<Limits>> =
+    LazyLock::new(|| RwLock::new(Limits::from(&Config::default())));
+
+tokio::task_local! {
+    static CURRENT_JOB: u64;
+}
+
+#[derive(Clone)]
+pub struct JobInfo {
+    pub id: u64,
+    pub user: u64,
+    pub chat: i64,
+    pub kind: String,
+    pub description: String,
+    pub stage: Stage,
+    pub created: Instant,
+    pub started: Option<Instant>,
+    /// Job doing the work this one waits for, see [`Dedup`].
+    pub attached_to: Option<u64>,
+    /// Details recorded in the job history.
+    pub entry: Entry,
+    token: CancellationToken,
+    /// Set once the job asks for its turn, commands rejected before that never queue.
+    enqueued: bool,
+}
+
+impl JobInfo {
+    pub fn is_running(&self) -> bool {
+        self.started.is_some()
+    }
+
+    /// Attached jobs run without taking a slot from the limits.
+    fn holds_slot(&self) -> bool {
+        self.is_running() && self.attached_to.is_none()
+    }
+
+    pub fn age(&self) -> Duration {
+        self.created.elapsed()
+    }
+}
+
+struct Limits {
+    global: usize,
+    per_user: usize,
+}
+
+#[derive(Default)]
+struct JobManager {
+    jobs: Mutex<BTreeMap<u64, JobInfo>>,
+    next_id: Mutex<u64>,
+    changed: Notify,
+}
+
+impl JobManager {
+    /// Picks the queued job allowed to start next, in submission order.
+    fn next_runnable(jobs: &BTreeMap<u64, JobInfo>) -> Option<u64> {
+        let limits = LIMITS.read().unwrap();
+        let running = jobs.values().filter(|j| j.holds_slot()).count();
+        if running >= limits.global {
+            return None;
+        }
+        jobs.values()
+            .filter(|j| j.enqueued && !j.is_running())
+            .find(|j| {
+                jobs.values()
+                    .filter(|r| r.holds_slot() && r.user == j.user)
+                    .count()
+                    < limits.per_user
+            })
+            .map(|j| j.id)
+    }
+}
+
+impl Limits {
+    fn from(config: &Config) -> Self {
+        Self {
+            global: config.max_jobs.max(1),
+            per_user: config.max_jobs_per_user.max(1),
+        }
+    }
+}
+
+pub fn init(config: &Config) {
+    *LIMITS.write().unwrap() = Limits::from(config);
+    // Raised limits may let queued jobs start.
+    MANAGER.changed.notify_waiters();
+}
+
+/// Pauses new jobs while `message` is set, an empty message shows a generic notice.
+pub fn set_maintenance(message: Option<String>) {
+    *MAINTENANCE.write().unwrap() = message;
+}
+
+pub fn maintenance() -> Option<String> {
+    MAINTENANCE.read().unwrap().clone()
+}
+
+/// Registration of a job, removed from the queue and saved to the history when dropped.
+pub struct JobHandle {
+    pub id: u64,
+}
+
+impl Drop for JobHandle {
+    fn drop(&mut self) {
+        let job = MANAGER.jobs.lock().unwrap().remove(&self.id);
+        MANAGER.changed.notify_waiters();
+        if let Some(job) = job.filter(|j| j.enqueued) {
+            let outcome = if job.token.is_cancelled() {
+                Outcome::Cancelled
+            } else {
+                job.entry
+                    .outcome
+                    .clone()
+                    .unwrap_or_else(|| Outcome::Failed("Interrupted".to_string()))
+            };
+            metrics::job_finished(&job.kind, outcome.as_str());
+            history::save(&job, &outcome);
+        }
+    }
+}
+
+pub fn submit(user: u64, chat: i64, kind: &str, description: &str) -> JobHandle {
+    let id = {
+        let mut next_id = MANAGER.next_id.lock().unwrap();
+        *next_id += 1;
+        *next_id
+    };
+    MANAGER.jobs.lock().unwrap().insert(
+        id,
+        JobInfo {
+            id,
+            user,
+            chat,
+            kind: kind.to_string(),
+            description: description.to_string(),
+            stage: Stage::Queued,
+            created: Instant::now(),
+            started: None,
+            attached_to: None,
+            entry: Entry::default(),
+            token: CancellationToken::new(),
+            enqueued: false,
+        },
+    );
+    JobHandle { id }
+}
+
+/// Runs `fut` as job `handle`, so stage updates inside it land on the job.
+///
+/// The job keeps the config current at this point until it finishes.
+pub async fn scope<F: Future>(handle: JobHandle, fut: F) -> F::Output {
+    let id = handle.id;
+    let token = MANAGER
+        .jobs
+        .lock()
+        .unwrap()
+        .get(&id)
+        .map(|j| j.token.clone())
+        .unwrap_or_default();
+    let on_stage = move |stage| {
+        if let Some(job) = MANAGER.jobs.lock().unwrap().get_mut(&id) {
+            job.stage = stage;
+        }
+    };
+    let fut = task::scope(token, on_stage, CURRENT_JOB.scope(id, fut));
+    let ret = config::scope(fut).await;
+    drop(handle);
+    ret
+}
+
+pub fn current() -> Option<u64> {
+    CURRENT_JOB.try_with(|id| *id).ok()
+}
+
+/// Resolves when any job is added, started or finished.
+///
+/// Create it before checking the queue, so no change is missed in between.
+pub fn changed() -> Notified<'static> {
+    MANAGER.changed.notified()
+}
+
+/// Number of running and queued jobs.
+pub fn counts() -> (usize, usize) {
+    let jobs = MANAGER.jobs.lock().unwrap();
+    let running = jobs.values().filter(|j| j.is_running()).count();
+    let queued = jobs
+        .values()
+        .filter(|j| j.enqueued && !j.is_running())
+        .count();
+    (running, queued)
+}
+
+/// Whether `user` already runs as many jobs as `MAX_JOBS_PER_USER` allows.
+pub fn user_busy(user: u64) -> bool {
+    let jobs = MANAGER.jobs.lock().unwrap();
+    let running = jobs.values().filter(|j| j.holds_slot() && j.user == user);
+    running.count() >= LIMITS.read().unwrap().per_user
+}
+
+/// Starts the current job if its turn has come, otherwise returns its queue position.
+pub fn try_start() -> Option<usize> {
+    let id = current()?;
+    let mut jobs = MANAGER.jobs.lock().unwrap();
+    let job = jobs.get_mut(&id)?;
+    if job.is_running() {
+        return None;
+    }
+    job.enqueued = true;
+    if JobManager::next_runnable(&jobs) == Some(id) {
+        if let Some(job) = jobs.get_mut(&id) {
+            job.started = Some(Instant::now());
+        }
+        drop(jobs);
+        MANAGER.changed.notify_waiters();
+        return None;
+    }
+    Some(queue_position(&jobs, id))
+}
+
+/// Waits until the current job may start, false if it is cancelled first.
+pub async fn wait_for_turn() -> bool {
+    let token = token();
+    loop {
+        let changed = changed();
+        if token.is_cancelled() {
+            return false;
+        }
+        if try_start().is_none() {
+            return true;
+        }
+        tokio::select! {
+            _ = changed => {}
+            _ = token.cancelled() => {}
+        }
+    }
+}
+
+/// Marks the current job as running on the work of job `leader`.
+pub fn attach(leader: u64) {
+    update_current(|job| {
+        job.enqueued = true;
+        job.started = Some(Instant::now());
+        job.attached_to = Some(leader);
+    });
+}
+
+/// Puts the current job back in the queue after its leader ended without a result.
+pub fn detach() {
+    update_current(|job| {
+        job.started = None;
+        job.attached_to = None;
+    });
+}
+
+fn update_current(f: impl FnOnce(&mut JobInfo)) {
+    if let Some(id) = current()
+        && let Some(job) = MANAGER.jobs.lock().unwrap().get_mut(&id)
+    {
+        f(job);
+    }
+    MANAGER.changed.notify_waiters();
+}
+
+/// Cancels job `id`, returns false if there is no such job.
+pub fn cancel(id: u64) -> bool {
+    let jobs = MANAGER.jobs.lock().unwrap();
+    let Some(job) = jobs.get(&id) else {
+        return false;
+    };
+    job.token.cancel();
+    true
+}
+
+/// Updates the history entry of the current job.
+pub fn record(f: impl FnOnce(&mut Entry)) {
+    if let Some(id) = current()
+        && let Some(job) = MANAGER.jobs.lock().unwrap().get_mut(&id)
+    {
+        f(&mut job.entry);
+    }
+}
+
+pub fn get(id: u64) -> Option<JobInfo> {
+    MANAGER
+        .jobs
+        .lock()
+        .unwrap()
+        .get(&id)
+        .filter(|j| j.enqueued)
+        .cloned()
+}
+
+pub fn list() -> Vec<JobInfo> {
+    MANAGER
+        .jobs
+        .lock()
+        .unwrap()
+        .values()
+        .filter(|j| j.enqueued)
+        .cloned()
+        .collect()
+}
+
+/// Number of queued jobs up to and including `id`.
+pub fn position(id: u64) -> Option<usize> {
+    let jobs = MANAGER.jobs.lock().unwrap();
+    let job = jobs.get(&id)?;
+    if !job.enqueued || job.is_running() {
+        return None;
+    }
+    Some(queue_position(&jobs, id))
+}
+
+fn queue_position(jobs: &BTreeMap<u64, JobInfo>, id: u64) -> usize {
+    jobs.values()
+        .filter(|j| j.enqueued && !j.is_running() && j.id <= id)
+        .count()
+}
+
+/// Result of a leader, `None` until published.
+pub type Published<T> = watch::Receiver<Option<T>>;
+
+/// Identical jobs in flight, later ones attach to the first instead of repeating its work.
+pub struct Dedup<T> {
+    leaders: Mutex<HashMap<String, (u64, Published<T>)>>,
+}
+
+impl<T> Default for Dedup<T> {
+    fn default() -> Self {
+        Self {
+            leaders: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+pub enum Joined<T: 'static> {
+    /// The current job does the work and publishes the result.
+    Leader(Lead<T>),
+    /// Job doing the work and its result, see [`follow`].
+    Follower(u64, Published<T>),
+}
+
+impl<T> Dedup<T> {
+    /// Makes the current job the one doing the work for `key`, unless another job already is.
+    pub fn join(&'static self, key: String) -> Joined<T> {
+        let mut leaders = self.leaders.lock().unwrap();
+        if let Some((leader, result)) = leaders.get(&key) {
+            return Joined::Follower(*leader, result.clone());
+        }
+        let (tx, rx) = watch::channel(None);
+        leaders.insert(key.clone(), (current().unwrap_or_default(), rx));
+        Joined::Leader(Lead {
+            dedup: self,
+            key,
+            tx,
+        })
+    }
+}
+
+/// Work for a key taken by [`Dedup::join`], released when dropped.
+pub struct Lead<T: 'static> {
+    dedup: &'static Dedup<T>,
+    key: String,
+    tx: watch::Sender<Option<T>>,
+}
+
+impl<T> Lead<T> {
+    /// Hands `result` to the attached jobs, and to jobs attaching until the lead is dropped.
+    pub fn publish(&self, result: T) {
+        self.tx.send_replace(Some(result));
+    }
+}
+
+impl<T> Drop for Lead<T> {
+    fn drop(&mut self) {
+        self.dedup.leaders.lock().unwrap().remove(&self.key);
+    }
+}
+
+/// Waits for the result of a leader, `None` if it ended without publishing one.
+pub async fn follow<T: Clone>(mut result: Published<T>) -> Option<T> {
+    result.wait_for(Option::is_some).await.ok()?.clone()
+}
@@ -0,0 +1,41 @@
+//! Core of the payload extract bot: listing and extracting partitions of
+//! Android OTA payloads over HTTP(S), patching boot images and managing the
+//! tools patching needs. The Telegram bot, its REST API and the command line
+//! are consumers of this API.
+//!
+//! ```no_run
+//! use payload_extract_bot::patch_boot::{self, PatchMethod, PatchPartition};
+//! use payload_extract_bot::payload::{self, ListOptions};
+//! use payload_extract_bot::tool::ToolManager;
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let url = "https://example.com/ota.zip".to_string();
+//! let listing = payload::list_image(url.clone(), &ListOptions::default()).await?;
+//! println!("{}", listing.text);
+//!
+//! let dumped = payload::dump_partition(url.clone(), "boot,vendor_boot".to_string()).await?;
+//! payload::verify_partitions(&dumped.files).await?;
+//!
+//! ToolManager::new()?.init().await?;
+//! let patched =
+//!     patch_boot::patch_boot(url, PatchPartition::Boot, PatchMethod::KernelSU).await?;
+//! println!("{} ({})", patched.path.display(), patched.kernel.kmi);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Settings such as the URL policy and proxy come from [`config`], which falls
+//! back to defaults unless the caller loads or sets one. Calls run inside a
+//! [`task::scope`] can be cancelled and report their progress.
+
+pub mod config;
+pub mod metrics;
+pub mod patch_boot;
+pub mod payload;
+pub mod proxy;
+pub mod remote;
+pub mod stats;
+pub mod task;
+pub mod tool;
+pub mod url_policy;
+pub mod utils;
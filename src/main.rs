@@ -1,38 +1,107 @@
+mod access;
+mod api;
+mod args;
+mod cli;
 mod commands;
-mod config;
-mod patch_boot;
-mod payload;
-mod tool;
-mod utils;
+mod diff;
+mod dumps;
+mod file_cache;
+mod file_server;
+mod history;
+mod i18n;
+mod jobs;
+mod markup;
+mod picker;
+mod resolver;
+mod rom_index;
+mod webhook;
 
-use crate::commands::{Command, answer};
+use crate::commands::{Command, answer, authorized, callback, inline, refuse};
+use crate::config::Config;
 use anyhow::Result;
-use log::info;
+use log::{info, warn};
+use payload_extract_bot::{
+    config, metrics, patch_boot, payload, proxy, stats, task, tool, url_policy, utils,
+};
+use std::sync::Arc;
 use std::time::Duration;
 use teloxide::dispatching::{Dispatcher, HandlerExt, UpdateFilterExt};
+use teloxide::error_handlers::LoggingErrorHandler;
 use teloxide::prelude::{Bot, Update};
 use teloxide::{dptree, net};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let config = config::load_config()?;
+    let cli = cli::parse()?;
+    if let cli::Command::Help = cli.command {
+        print!("{}", cli::help());
+        return Ok(());
+    }
+    let config = config::init(cli.config)?;
     pretty_env_logger::init();
+    proxy::init(&config)?;
+    url_policy::validate(&config)?;
+    stats::init();
+    match cli.command {
+        cli::Command::Run(spec, args) => cli::run(spec, args).await,
+        _ => bot(config).await,
+    }
+}
+
+/// Runs the Telegram bot until it is stopped.
+async fn bot(config: Arc<Config>) -> Result<()> {
+    config.check_token()?;
+    jobs::init(&config);
+    access::init(&config)?;
+    history::init(&config)?;
+    i18n::init(&config)?;
+    file_cache::init(&config)?;
+    file_server::init(&config)?;
+    api::init(&config)?;
+    metrics::init(&config, jobs::counts)?;
     info!("Initializing tools");
-    let tm = tool::ToolManager::default();
+    let tm = tool::ToolManager::new()?;
     tm.init().await?;
     info!("Cleaning temp files");
-    std::fs::remove_dir_all("tmp").ok();
+    std::fs::remove_dir_all(utils::TEMP_ROOT).ok();
     info!("Starting command bot...");
-    let client = net::default_reqwest_settings().timeout(Duration::from_secs(120));
-    let bot = Bot::with_client(config.token, client.build()?).set_api_url(config.api_url.parse()?);
+    let client = proxy::apply(net::default_reqwest_settings().timeout(Duration::from_secs(120)));
+    let bot = Bot::with_client(&config.token, client.build()?).set_api_url(config.api_url.parse()?);
+
+    if let Err(e) = commands::register_commands(&bot).await {
+        warn!("Failed to register commands: {e}");
+    }
+    config::watch({
+        let bot = bot.clone();
+        move || {
+            let bot = bot.clone();
+            async move { commands::reload_config(&bot).await.map(|_| ()) }
+        }
+    });
 
-    let handler = Update::filter_message()
-        .branch(dptree::entry().filter_command::<Command>().endpoint(answer));
+    let handler = dptree::entry()
+        .branch(
+            Update::filter_message().branch(
+                dptree::entry()
+                    .filter_command::<Command>()
+                    .branch(dptree::filter(authorized).endpoint(answer))
+                    .branch(dptree::endpoint(refuse)),
+            ),
+        )
+        .branch(Update::filter_callback_query().endpoint(callback))
+        .branch(Update::filter_inline_query().endpoint(inline));
 
-    Dispatcher::builder(bot, handler)
+    let mut dispatcher = Dispatcher::builder(bot.clone(), handler)
         .enable_ctrlc_handler()
-        .build()
-        .dispatch()
-        .await;
+        .build();
+    if config.webhook_url.is_some() {
+        let listener = webhook::listener(bot, &config).await?;
+        let error_handler = LoggingErrorHandler::with_custom_text("Webhook listener error");
+        dispatcher
+            .dispatch_with_listener(listener, error_handler)
+            .await;
+    } else {
+        dispatcher.dispatch().await;
+    }
     Ok(())
 }
@@ -0,0 +1,28 @@
+pub fn to_tg_md(s: String) -> String {
+    s.replace("-", "\\-")
+        .replace(".", "\\.")
+        .replace("(", "\\(")
+        .replace(")", "\\)")
+        .replace("+", "\\+")
+        .replace("#", "\\#")
+}
+
+/// Escapes every character with a meaning in Telegram MarkdownV2.
+pub fn escape_md(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+    for c in s.chars() {
+        if "_*[]()~`>#+-=|{}.!\\".contains(c) {
+            ret.push('\\');
+        }
+        ret.push(c);
+    }
+    ret
+}
+
+pub fn to_html_pre(s: &str) -> String {
+    let escaped = s
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    format!("<pre>{escaped}</pre>")
+}
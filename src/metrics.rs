@@ -0,0 +1,213 @@
+//! Prometheus metrics, served under `/metrics` on `METRICS_LISTEN`.
+//!
+//! Metrics are collected whether or not they are served.
+
+use crate::config::Config;
+use crate::utils;
+use anyhow::{Context, Result};
+use axum::Router;
+use axum::http::{StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use log::{error, info};
+use prometheus::core::Collector;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder, exponential_buckets,
+};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+
+const MIB: f64 = 1024.0 * 1024.0;
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+struct Metrics {
+    registry: Registry,
+    commands: IntCounterVec,
+    jobs: IntCounterVec,
+    jobs_current: IntGaugeVec,
+    extracted_bytes: IntCounterVec,
+    extraction_seconds: HistogramVec,
+    manifest_seconds: Histogram,
+    telegram_errors: IntCounterVec,
+    upload_bytes: Histogram,
+    tool_failures: IntCounterVec,
+    temp_bytes: IntGauge,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("peb".to_string()), None).unwrap();
+        let counter = |name: &str, help: &str, labels: &[&str]| {
+            register(&registry, IntCounterVec::new(Opts::new(name, help), labels))
+        };
+        let histogram = |name: &str, help: &str, buckets: Vec<f64>| {
+            let opts = HistogramOpts::new(name, help).buckets(buckets);
+            register(&registry, Histogram::with_opts(opts))
+        };
+        Self {
+            commands: counter(
+                "commands_received_total",
+                "Bot commands received",
+                &["command"],
+            ),
+            jobs: counter(
+                "jobs_total",
+                "Finished jobs by kind and outcome",
+                &["kind", "outcome"],
+            ),
+            jobs_current: register(
+                &registry,
+                IntGaugeVec::new(
+                    Opts::new("jobs", "Jobs currently running or queued"),
+                    &["state"],
+                ),
+            ),
+            extracted_bytes: counter(
+                "extracted_bytes_total",
+                "Bytes of extracted partition images",
+                &["partition"],
+            ),
+            extraction_seconds: register(
+                &registry,
+                HistogramVec::new(
+                    HistogramOpts::new(
+                        "extraction_duration_seconds",
+                        "Time to extract a partition",
+                    )
+                    .buckets(exponential_buckets(1.0, 2.0, 12).unwrap()),
+                    &["partition"],
+                ),
+            ),
+            manifest_seconds: histogram(
+                "manifest_fetch_duration_seconds",
+                "Time to fetch and parse a payload manifest",
+                exponential_buckets(0.1, 2.0, 10).unwrap(),
+            ),
+            telegram_errors: counter(
+                "telegram_errors_total",
+                "Failed Telegram API requests by error kind",
+                &["kind"],
+            ),
+            upload_bytes: histogram(
+                "upload_size_bytes",
+                "Size of files sent to Telegram",
+                exponential_buckets(MIB, 4.0, 8).unwrap(),
+            ),
+            tool_failures: counter(
+                "tool_failures_total",
+                "Failed runs of magiskboot and ksud",
+                &["tool"],
+            ),
+            temp_bytes: register(
+                &registry,
+                IntGauge::new("temp_disk_bytes", "Disk used by temporary files"),
+            ),
+            registry,
+        }
+    }
+}
+
+/// Adds `metric` to `registry`, names are fixed so this only fails on a typo.
+fn register<T: Collector + Clone + 'static>(
+    registry: &Registry,
+    metric: prometheus::Result<T>,
+) -> T {
+    let metric = metric.unwrap();
+    registry.register(Box::new(metric.clone())).unwrap();
+    metric
+}
+
+/// Starts serving `/metrics` if `METRICS_LISTEN` is set.
+///
+/// `jobs` returns the number of running and queued jobs when scraped.
+pub fn init(
+    config: &Config,
+    jobs: impl Fn() -> (usize, usize) + Send + Sync + 'static,
+) -> Result<()> {
+    let Some(listen) = &config.metrics_listen else {
+        return Ok(());
+    };
+    let address: SocketAddr = listen.parse().context("Invalid METRICS_LISTEN")?;
+    let jobs = Arc::new(jobs);
+
+    info!("Serving metrics on {address}");
+    let app = Router::new().route("/metrics", get(move || serve(jobs.clone())));
+    tokio::spawn(async move {
+        if let Err(e) = axum_server::bind(address)
+            .serve(app.into_make_service())
+            .await
+        {
+            error!("Metrics server error: {e}");
+        }
+    });
+    Ok(())
+}
+
+async fn serve(jobs: Arc<impl Fn() -> (usize, usize)>) -> Response {
+    let (running, queued) = jobs();
+    METRICS
+        .jobs_current
+        .with_label_values(&["running"])
+        .set(running as i64);
+    METRICS
+        .jobs_current
+        .with_label_values(&["queued"])
+        .set(queued as i64);
+    match tokio::task::spawn_blocking(|| utils::dir_size(Path::new(utils::TEMP_ROOT))).await {
+        Ok(size) => METRICS.temp_bytes.set(size as i64),
+        Err(e) => error!("Failed to measure the temp dir: {e}"),
+    }
+
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    if let Err(e) = encoder.encode(&METRICS.registry.gather(), &mut body) {
+        error!("Failed to encode metrics: {e}");
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    (
+        [(header::CONTENT_TYPE, encoder.format_type().to_string())],
+        body,
+    )
+        .into_response()
+}
+
+pub fn command(name: &str) {
+    METRICS.commands.with_label_values(&[name]).inc();
+}
+
+pub fn job_finished(kind: &str, outcome: &str) {
+    METRICS.jobs.with_label_values(&[kind, outcome]).inc();
+}
+
+/// A partition image of `bytes` extracted in `duration`.
+pub fn extracted(partition: &str, bytes: u64, duration: Duration) {
+    METRICS
+        .extracted_bytes
+        .with_label_values(&[partition])
+        .inc_by(bytes);
+    METRICS
+        .extraction_seconds
+        .with_label_values(&[partition])
+        .observe(duration.as_secs_f64());
+}
+
+pub fn manifest_fetched(duration: Duration) {
+    METRICS.manifest_seconds.observe(duration.as_secs_f64());
+}
+
+pub fn telegram_error(kind: &str) {
+    METRICS.telegram_errors.with_label_values(&[kind]).inc();
+}
+
+/// A file of `bytes` sent to Telegram.
+pub fn uploaded(bytes: u64) {
+    METRICS.upload_bytes.observe(bytes as f64);
+}
+
+pub fn tool_failed(tool: &str) {
+    METRICS.tool_failures.with_label_values(&[tool]).inc();
+}
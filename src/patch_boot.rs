@@ -1,42 +1,53 @@
-use crate::payload::dump_partition;
+//! Patching boot images for root, from a payload or from local images.
+
+use crate::metrics;
+use crate::payload::{Dumped, dump_partition};
+use crate::task::{self, Stage};
 use crate::tool::*;
-use anyhow::Result;
+use crate::utils::TempDir;
+use anyhow::{Context, Result, anyhow};
 use log::info;
 use regex::Regex;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{BufReader, Read};
-use std::path::PathBuf;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::process::Command;
 
-enum PatchMethod {
+#[derive(Clone, Copy)]
+pub enum PatchMethod {
     KernelSU,
     Magisk,
 }
 
 impl PatchMethod {
-    fn from(s: &str) -> Result<Self> {
+    pub fn from(s: &str) -> Result<Self> {
         match s {
             "kernelsu" | "ksu" | "k" => Ok(Self::KernelSU),
             "magisk" | "m" => Ok(Self::Magisk),
             _ => Err(anyhow::anyhow!("Invalid patch method: {}", s)),
         }
     }
-    fn to_string(&self) -> String {
+}
+
+impl std::fmt::Display for PatchMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::KernelSU => "kernelsu".to_string(),
-            Self::Magisk => "magisk".to_string(),
+            Self::KernelSU => write!(f, "kernelsu"),
+            Self::Magisk => write!(f, "magisk"),
         }
     }
 }
 
-enum PatchPartition {
+#[derive(Clone, Copy)]
+pub enum PatchPartition {
     Boot,
     InitBoot,
     VendorBoot,
 }
 
 impl PatchPartition {
-    fn from(s: &str) -> Result<Self> {
+    pub fn from(s: &str) -> Result<Self> {
         match s {
             "boot" | "b" => Ok(Self::Boot),
             "init_boot" | "ib" => Ok(Self::InitBoot),
@@ -45,7 +56,7 @@ impl PatchPartition {
         }
     }
 
-    fn get_partition_name(&self) -> String {
+    pub fn get_partition_name(&self) -> String {
         match self {
             Self::Boot => "boot".to_string(),
             Self::InitBoot => "init_boot".to_string(),
@@ -59,18 +70,29 @@ struct Patch {
     partition: PatchPartition,
 }
 
+/// Kernel of a boot image, see [`get_kmi`].
+#[derive(Clone)]
+pub struct KernelInfo {
+    /// Kernel module interface, e.g. `android14-6.1`.
+    pub kmi: String,
+    /// `Linux version` banner of the kernel.
+    pub version: String,
+}
+
+/// A patched image, removed with its directory when dropped.
 pub struct PatchedFile {
-    pub(crate) path: PathBuf,
-    pub(crate) kmi: String,
-    pub(crate) kernel_version: String,
+    pub path: PathBuf,
+    pub kernel: KernelInfo,
+    pub dir: TempDir,
 }
 
 impl Patch {
-    fn patch(&self, dir: PathBuf) -> Result<PatchedFile> {
-        let tm = ToolManager::default();
+    /// Patches `{partition}.img` in `dir`, next to `boot.img` for KernelSU.
+    async fn patch(&self, dir: &Path) -> Result<(PathBuf, KernelInfo)> {
+        let tm = ToolManager::new()?;
         let mut patched_name = format!(
             "{}_patched_{}",
-            self.method.to_string(),
+            self.method,
             self.partition.get_partition_name()
         );
 
@@ -78,74 +100,144 @@ impl Patch {
             PatchMethod::KernelSU => {
                 let ksud = tm.get_ksud().get();
                 let magiskboot = tm.get_magiskboot().get();
-                let (kmi, kernel_version) = get_kmi(magiskboot.clone(), dir.clone())?;
+                let kernel = get_kmi(&magiskboot, dir, "boot.img").await?;
 
-                patched_name = format!("{patched_name}-{kmi}.img");
+                patched_name = format!("{patched_name}-{}.img", kernel.kmi);
 
                 info!(
                     "patching {} with kmi: {}, tool: {}",
                     self.partition.get_partition_name(),
-                    kmi,
+                    kernel.kmi,
                     tm.get_ksud().get().display()
                 );
 
-                let _ = Command::new(ksud)
-                    .current_dir(dir.clone())
-                    .args(&[
+                run(Command::new(ksud)
+                    .current_dir(dir)
+                    .args([
                         "boot-patch",
                         "-b",
                         format!("{}.img", self.partition.get_partition_name()).as_str(),
                         "--magiskboot",
-                        magiskboot.as_path().to_str().unwrap(),
+                    ])
+                    .arg(&magiskboot)
+                    .args([
                         "--kmi",
-                        kmi.as_str(),
+                        kernel.kmi.as_str(),
                         "--out-name",
                         patched_name.as_str(),
-                    ])
-                    .output()?;
-                let mut file = PathBuf::from(dir);
-                file.push(&patched_name);
-                Ok(PatchedFile {
-                    path: file,
-                    kmi,
-                    kernel_version,
-                })
+                    ]))
+                .await?;
+                Ok((dir.join(&patched_name), kernel))
             }
             PatchMethod::Magisk => Err(anyhow::anyhow!("Magisk patch hasn't implemented!")),
         }
     }
 }
 
+/// Dumps `partition` from the payload at `url` and patches it with `method`.
 pub async fn patch_boot(
     url: String,
-    patch_partition: String,
-    patch_method: String,
+    partition: PatchPartition,
+    method: PatchMethod,
+) -> Result<PatchedFile> {
+    let name = partition.get_partition_name();
+    info!("Patching boot: {url} {name} {method}");
+    let patch = Patch { method, partition };
+    let mut images = vec![name];
+    if let PatchMethod::KernelSU = method {
+        images.push("boot".to_string())
+    }
+    let Dumped { dir, .. } = dump_partition(url, images.join(",")).await?;
+    task::set_stage(Stage::Patching);
+    let ret = patch.patch(dir.path()).await;
+    task::check_cancelled()?;
+    let (path, kernel) = ret?;
+    Ok(PatchedFile { path, kernel, dir })
+}
+
+/// Patches `image`, a local copy of `partition`.
+///
+/// KernelSU reads the KMI from the boot image, which is `image` itself when
+/// patching boot and `boot` otherwise.
+pub async fn patch_image(
+    image: &Path,
+    boot: Option<&Path>,
+    partition: PatchPartition,
+    method: PatchMethod,
 ) -> Result<PatchedFile> {
-    info!("Patching boot: {url} {patch_partition} {patch_method}");
-    let patch = Patch {
-        method: PatchMethod::from(&patch_method)?,
-        partition: PatchPartition::from(&patch_partition)?,
+    let name = partition.get_partition_name();
+    info!("Patching image: {} {name} {method}", image.display());
+    let dir = TempDir::new()?;
+    fs::copy(image, dir.path().join(format!("{name}.img")))
+        .with_context(|| format!("Failed to read {}", image.display()))?;
+    if let PatchMethod::KernelSU = method
+        && !matches!(partition, PatchPartition::Boot)
+    {
+        let boot = boot.with_context(|| {
+            format!("Patching {name} with KernelSU needs the boot image for its KMI")
+        })?;
+        fs::copy(boot, dir.path().join("boot.img"))
+            .with_context(|| format!("Failed to read {}", boot.display()))?;
+    }
+    task::set_stage(Stage::Patching);
+    let (path, kernel) = Patch { method, partition }.patch(dir.path()).await?;
+    Ok(PatchedFile { path, kernel, dir })
+}
+
+/// KMI and kernel version of a local boot image.
+pub async fn read_kmi(image: &Path) -> Result<KernelInfo> {
+    let dir = TempDir::new()?;
+    fs::copy(image, dir.path().join("boot.img"))
+        .with_context(|| format!("Failed to read {}", image.display()))?;
+    let magiskboot = ToolManager::new()?.get_magiskboot().get();
+    get_kmi(&magiskboot, dir.path(), "boot.img").await
+}
+
+/// Runs `cmd` to completion, killing it once the current job is cancelled.
+///
+/// Fails with the last line the tool printed to stderr if it exits unsuccessfully.
+async fn run(cmd: &mut Command) -> Result<()> {
+    let tool = Path::new(cmd.as_std().get_program())
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let token = task::token();
+    let child = cmd
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .inspect_err(|_| metrics::tool_failed(&tool))?;
+    let output = tokio::select! {
+        output = child.wait_with_output() => output?,
+        _ = token.cancelled() => {
+            info!("Killing {tool}");
+            return Err(task::Cancelled.into());
+        }
     };
-    let mut images = Vec::new();
-    images.push(patch.partition.get_partition_name());
-    match patch.method {
-        PatchMethod::KernelSU => images.push("boot".to_string()),
-        _ => {}
+    if !output.status.success() {
+        metrics::tool_failed(&tool);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr.lines().rev().find(|l| !l.trim().is_empty());
+        return Err(match reason {
+            Some(reason) => anyhow!("{tool} failed ({}): {}", output.status, reason.trim()),
+            None => anyhow!("{tool} failed ({})", output.status),
+        });
     }
-    let (_, dir) = dump_partition(url.clone(), images.join(",")).await?;
-    patch.patch(dir)
+    Ok(())
 }
 
-fn get_kmi(magiskboot: PathBuf, dir: PathBuf) -> Result<(String, String)> {
+/// Unpacks `image` in `dir` with `magiskboot` and reads the KMI and version of its kernel.
+pub async fn get_kmi(magiskboot: &Path, dir: &Path, image: &str) -> Result<KernelInfo> {
     info!(
-        "Getting kmi from boot.img in {}, tool: {}",
-        std::env::current_dir()?.display(),
+        "Getting kmi from {image} in {}, tool: {}",
+        dir.display(),
         magiskboot.display()
     );
-    let _ = Command::new(magiskboot)
-        .current_dir(&dir)
-        .args(&["unpack", "-n", "boot.img"])
-        .output()?;
+    run(Command::new(magiskboot)
+        .current_dir(dir)
+        .args(["unpack", "-n", image]))
+    .await?;
 
     let file = File::open(dir.join("kernel"))?;
     let mut reader = BufReader::new(file);
@@ -195,11 +287,59 @@ fn get_kmi(magiskboot: PathBuf, dir: PathBuf) -> Result<(String, String)> {
     }
 
     match (kmi, kernel_version) {
-        (Some(k), Some(v)) => Ok((k, v)),
+        (Some(kmi), Some(version)) => Ok(KernelInfo { kmi, version }),
         (Some(_), None) => Err(anyhow::anyhow!("Can't parse kernel version from kernel")),
-        (None, Some(_)) => Err(anyhow::anyhow!("Can't parse kmi from boot.img")),
+        (None, Some(_)) => Err(anyhow::anyhow!("Can't parse kmi from {image}")),
         (None, None) => Err(anyhow::anyhow!(
-            "Can't parse kmi and kernel version from boot.img"
+            "Can't parse kmi and kernel version from {image}"
         )),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio_util::sync::CancellationToken;
+
+    #[test]
+    fn parses_methods_and_partitions() {
+        assert!(matches!(
+            PatchMethod::from("ksu"),
+            Ok(PatchMethod::KernelSU)
+        ));
+        assert!(matches!(PatchMethod::from("m"), Ok(PatchMethod::Magisk)));
+        assert!(PatchMethod::from("apatch").is_err());
+        assert_eq!(PatchMethod::KernelSU.to_string(), "kernelsu");
+
+        let partition = PatchPartition::from("ib").unwrap();
+        assert_eq!(partition.get_partition_name(), "init_boot");
+        assert!(PatchPartition::from("system").is_err());
+    }
+
+    #[tokio::test]
+    async fn run_fails_on_unsuccessful_exit() {
+        run(Command::new("sh").args(["-c", "exit 0"]))
+            .await
+            .unwrap();
+        let err = run(Command::new("sh").args(["-c", "echo bad image >&2; exit 3"]))
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("bad image"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn run_stops_when_cancelled() {
+        let token = CancellationToken::new();
+        let started = std::time::Instant::now();
+        let cancel = token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            cancel.cancel();
+        });
+        let ret = task::scope(token, |_| {}, run(Command::new("sleep").arg("10"))).await;
+        assert!(ret.unwrap_err().is::<task::Cancelled>());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
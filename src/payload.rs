@@ -1,13 +1,84 @@
-use crate::utils;
+//! Listing and extracting partitions of OTA payloads, fetched over HTTP(S)
+//! without downloading the whole package.
+
+use crate::remote::{self, ZipPayload};
+use crate::task::{self, Stage};
+use crate::utils::{self, TempDir};
+use crate::{metrics, stats, url_policy};
 use anyhow::Result;
-use log::{debug, info};
-use payload_dumper::extractor::remote::{extract_partition_remote_zip, list_partitions_remote_zip};
-use serde_json::Value;
-use std::path::PathBuf;
-use std::time::{SystemTime, UNIX_EPOCH};
-use std::{fs, thread};
+use log::{debug, info, warn};
+use payload_dumper::extractor::local::RUNTIME;
+use payload_dumper::metadata::get_metadata;
+use payload_dumper::payload::payload_dumper::{
+    ProgressReporter, dump_partition as dump_partition_update,
+};
+use payload_dumper::structs::DeltaArchiveManifest;
+use payload_dumper::utils::format_size;
+use payload_dumper::zip::core_parser::ZipParser;
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
 use tokio::sync::oneshot;
+use tokio_util::sync::CancellationToken;
+
+/// Partitions and metadata of a payload, see [`get_rom_info`].
+#[derive(Clone, Serialize)]
+pub struct RomInfo {
+    pub partitions: Vec<Partition>,
+    pub total_partitions: usize,
+    pub total_operations: usize,
+    pub total_size_bytes: u64,
+    pub total_size_readable: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub security_patch_level: Option<String>,
+    pub dynamic_partition_metadata: Option<DynamicPartitions>,
+}
+
+/// A partition in the payload manifest.
+#[derive(Clone, Serialize)]
+pub struct Partition {
+    pub name: String,
+    pub size_bytes: u64,
+    pub size_readable: String,
+    pub operations_count: usize,
+    pub compression_type: String,
+    /// Hex encoded SHA-256 of the image.
+    pub hash: Option<String>,
+    /// Bytes of the payload fetched to extract the partition.
+    pub data_bytes: u64,
+}
 
+/// Dynamic partition groups and Virtual A/B settings.
+#[derive(Clone, Serialize)]
+pub struct DynamicPartitions {
+    pub groups: Vec<PartitionGroup>,
+    pub snapshot_enabled: Option<bool>,
+    pub vabc_enabled: Option<bool>,
+    pub vabc_compression_param: Option<String>,
+    pub cow_version: Option<u32>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct PartitionGroup {
+    pub name: String,
+    /// Maximum size of the group.
+    pub size: Option<u64>,
+    pub size_readable: Option<String>,
+    pub partition_names: Vec<String>,
+}
+
+/// Listing text of a payload, see [`summarize`].
+pub struct Listing {
+    pub text: String,
+    /// Names of the partitions the text shows.
+    pub partitions: Vec<String>,
+}
+
+/// An image written by [`dump_partition`].
 pub struct PartitionInfo {
     pub name: String,
     pub size: u64,
@@ -15,95 +86,580 @@ pub struct PartitionInfo {
     pub path: PathBuf,
 }
 
-pub async fn dump_partition(
-    url: String,
-    partition: String,
-) -> Result<(Vec<PartitionInfo>, PathBuf)> {
+/// Partitions written by [`dump_partition`], removed with their directory when dropped.
+pub struct Dumped {
+    pub files: Vec<PartitionInfo>,
+    pub dir: TempDir,
+}
+
+/// Extracts the comma separated `partition` list from the payload at `url`.
+///
+/// Partitions missing from the payload are skipped.
+pub async fn dump_partition(url: String, partition: String) -> Result<Dumped> {
     let mut partitions: Vec<String> = partition.split(',').map(|s| s.to_string()).collect();
     partitions.sort();
     partitions.dedup();
-    let ts = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
-    let temp_dir = PathBuf::from("tmp").join(ts.to_string());
-    fs::create_dir_all(&temp_dir)?;
-    info!("Dumping partitions to {}", temp_dir.display());
+    let dir = TempDir::new()?;
+    info!("Dumping partitions to {}", dir.path().display());
+    let ret = extract_partitions(url, partitions, dir.path()).await;
+    if task::is_cancelled() {
+        return Err(task::Cancelled.into());
+    }
+    Ok(Dumped { files: ret?, dir })
+}
 
-    let rom_info = get_rom_info(url.clone()).await?;
-    let all_partitions_info = rom_info["partitions"].as_array().unwrap();
+async fn extract_partitions(
+    url: String,
+    partitions: Vec<String>,
+    temp_dir: &Path,
+) -> Result<Vec<PartitionInfo>> {
+    task::set_stage(Stage::Manifest);
+    let token = task::token();
+    let opened = tokio::select! {
+        opened = open_payload(url) => opened?,
+        _ = token.cancelled() => return Err(task::Cancelled.into()),
+    };
+    let rom_info = rom_info(&opened).await?;
+    task::set_stage(Stage::Extracting);
+    let block_size = opened.manifest.block_size.unwrap_or(4096) as u64;
 
     let mut files = Vec::new();
     let mut receivers = Vec::new();
+    let mut data_bytes = 0;
 
     for p_name in partitions {
         let out_put = temp_dir.join(format!("{p_name}.img"));
-        if let Some(part_info) = all_partitions_info.iter().find(|p| p["name"] == p_name) {
-            let info = PartitionInfo {
-                name: p_name.clone(),
-                size: part_info["size_bytes"].as_u64().unwrap_or(0),
-                hash: part_info["hash"].as_str().map(|s| s.to_string()),
-                path: out_put.clone(),
-            };
-            files.push(info);
-
-            let url_clone = url.clone();
-            let (tx, rx) = oneshot::channel();
-            thread::spawn(move || {
-                let result = extract_partition_remote_zip(
-                    url_clone,
-                    &p_name,
-                    out_put,
-                    Option::from(utils::USER_AGENT),
-                    None,
-                    None,
-                    None::<PathBuf>,
-                );
-                let _ = tx.send(result);
-            });
-            receivers.push(rx);
-        }
+        let Some(part_info) = rom_info.partitions.iter().find(|p| p.name == p_name) else {
+            continue;
+        };
+        let Some(update) = opened
+            .manifest
+            .partitions
+            .iter()
+            .find(|p| p.partition_name == p_name)
+            .cloned()
+        else {
+            continue;
+        };
+        files.push(PartitionInfo {
+            name: p_name.clone(),
+            size: part_info.size_bytes,
+            hash: part_info.hash.clone(),
+            path: out_put.clone(),
+        });
+        data_bytes += part_info.data_bytes;
+
+        let payload = opened.payload.clone();
+        let data_offset = opened.data_offset;
+        let reporter = Cancellable(token.clone());
+        let size = part_info.size_bytes;
+        let (tx, rx) = oneshot::channel();
+        thread::spawn(move || {
+            let started = Instant::now();
+            let result = RUNTIME.block_on(dump_partition_update(
+                &update,
+                data_offset,
+                block_size,
+                out_put,
+                &payload,
+                &reporter,
+                None,
+            ));
+            if result.is_ok() {
+                metrics::extracted(&p_name, size, started.elapsed());
+            }
+            let _ = tx.send(result);
+        });
+        receivers.push(rx);
     }
 
+    // Workers notice a cancellation on their next operation, wait for them so
+    // nothing writes to the temp dir after it is removed.
+    let mut ret = Ok(());
     for rx in receivers {
-        rx.await??;
+        let result = rx.await?;
+        if ret.is_ok() {
+            ret = result;
+        }
     }
+    ret?;
+    stats::add_downloaded(data_bytes);
+
+    Ok(files)
+}
 
-    Ok((files, temp_dir))
+/// Reports extraction progress nowhere, stops the extraction once the token is cancelled.
+struct Cancellable(CancellationToken);
+
+impl ProgressReporter for Cancellable {
+    fn on_start(&self, _: &str, _: u64) {}
+    fn on_progress(&self, _: &str, _: u64, _: u64) {}
+    fn on_complete(&self, _: &str, _: u64) {}
+    fn on_warning(&self, partition: &str, operation: usize, message: String) {
+        warn!("{partition}: operation {operation}: {message}");
+    }
+    fn is_cancelled(&self) -> bool {
+        self.0.is_cancelled()
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+pub enum ListSort {
+    #[default]
+    Payload,
+    Name,
+    Size,
+}
+
+impl ListSort {
+    pub fn from(s: &str) -> Result<Self> {
+        match s {
+            "name" | "n" => Ok(Self::Name),
+            "size" | "s" => Ok(Self::Size),
+            "payload" | "p" => Ok(Self::Payload),
+            _ => Err(anyhow::anyhow!("Invalid sort key: {}", s)),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ListOptions {
+    pub sort: ListSort,
+    pub filter: Option<Regex>,
+    pub min_size: u64,
+}
+
+impl ListOptions {
+    fn apply<'a>(&self, partitions: &'a [Partition]) -> Vec<&'a Partition> {
+        let mut ret = partitions
+            .iter()
+            .filter(|p| p.size_bytes >= self.min_size)
+            .filter(|p| match &self.filter {
+                Some(re) => re.is_match(&p.name),
+                None => true,
+            })
+            .collect::<Vec<_>>();
+        match self.sort {
+            ListSort::Payload => {}
+            ListSort::Name => ret.sort_by(|a, b| a.name.cmp(&b.name)),
+            ListSort::Size => ret.sort_by_key(|p| std::cmp::Reverse(p.size_bytes)),
+        }
+        ret
+    }
+
+    fn is_filtered(&self) -> bool {
+        self.filter.is_some() || self.min_size > 0
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+}
+
+impl ExportFormat {
+    pub fn from(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            _ => Err(anyhow::anyhow!("Invalid export format: {}", s)),
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Csv => "csv",
+        }
+    }
+}
+
+/// Checks dumped images against the SHA-256 hashes from the payload manifest.
+pub async fn verify_partitions(files: &[PartitionInfo]) -> Result<()> {
+    let files = files
+        .iter()
+        .map(|f| (f.name.clone(), f.path.clone(), f.hash.clone()))
+        .collect::<Vec<_>>();
+    tokio::task::spawn_blocking(move || {
+        let mut mismatched = Vec::new();
+        for (name, path, hash) in files {
+            let Some(expected) = hash else {
+                return Err(anyhow::anyhow!("No hash for {name} in the payload"));
+            };
+            let actual = utils::sha256_file(&path)?;
+            debug!("Verifying {name}: expected {expected}, got {actual}");
+            if !actual.eq_ignore_ascii_case(expected.trim_matches('"')) {
+                mismatched.push(name);
+            }
+        }
+        if !mismatched.is_empty() {
+            return Err(anyhow::anyhow!("Hash mismatch: {}", mismatched.join(", ")));
+        }
+        Ok(())
+    })
+    .await?
 }
 
-pub async fn list_image(url: String) -> Result<String> {
+/// Fetches the manifest of the payload at `url` and summarizes it.
+pub async fn list_image(url: String, options: &ListOptions) -> Result<Listing> {
     info!("Listing image: {url}");
     let info = get_rom_info(url).await?;
-    let partitions = info["partitions"].as_array().unwrap();
-    let partitions_str = partitions
+    Ok(summarize(&info, options))
+}
+
+/// Human readable summary of [`get_rom_info`], with the names of the shown partitions.
+pub fn summarize(info: &RomInfo, options: &ListOptions) -> Listing {
+    let partitions = options.apply(&info.partitions);
+    let format_partition = |p: &Partition| format!("  - {}: {}", p.name, p.size_readable);
+    let security_patch = info.security_patch_level.as_deref().unwrap_or("N/A");
+    let mut ret = format!(
+        "Total size: {}\nSecurity patch level: {security_patch}\nTotal partitions: {}",
+        info.total_size_readable, info.total_partitions
+    );
+    if options.is_filtered() {
+        ret.push_str(&format!("\nShown partitions: {}", partitions.len()));
+    }
+
+    let groups = match &info.dynamic_partition_metadata {
+        Some(dynamic) => dynamic.groups.as_slice(),
+        None => &[],
+    };
+    let mut dynamic_names = HashSet::new();
+    if let Some(dynamic) = &info.dynamic_partition_metadata {
+        ret.push_str(&format!(
+            "\nVirtual A/B snapshot: {}\nVABC: {}",
+            yes_no(dynamic.snapshot_enabled),
+            yes_no(dynamic.vabc_enabled),
+        ));
+        if let Some(compression) = &dynamic.vabc_compression_param {
+            ret.push_str(&format!("\nVABC compression: {compression}"));
+        }
+        if let Some(cow_version) = dynamic.cow_version {
+            ret.push_str(&format!("\nCOW version: {cow_version}"));
+        }
+    }
+    for group in groups {
+        let members = partitions
+            .iter()
+            .filter(|p| group.partition_names.contains(&p.name))
+            .collect::<Vec<_>>();
+        let budget = group.size_readable.as_deref().unwrap_or("N/A");
+        let used: u64 = members.iter().map(|p| p.size_bytes).sum();
+        ret.push_str(&format!(
+            "\nDynamic group {} (used {} / max {budget}):",
+            group.name,
+            format_size(used),
+        ));
+        for p in members {
+            dynamic_names.insert(p.name.as_str());
+            ret.push('\n');
+            ret.push_str(&format_partition(p));
+        }
+    }
+
+    let physical = partitions
         .iter()
-        .map(|p| {
-            format!(
-                "  - {}: {}",
-                p["name"].as_str().unwrap(),
-                p["size_readable"].as_str().unwrap()
-            )
-        })
+        .filter(|p| !dynamic_names.contains(p.name.as_str()))
+        .map(|p| format_partition(p))
         .collect::<Vec<_>>()
         .join("\n");
-    let total = info["total_partitions"].as_u64().unwrap();
-    let size = info["total_size_readable"].as_str().unwrap();
-    let security_patch = info["security_patch_level"].as_str().unwrap();
-    let ret = format!(
-        "Total size: {size}\nSecurity patch level: {security_patch}\nTotal partitions: {total}\nPartitions:\n{partitions_str}"
-    );
+    if groups.is_empty() {
+        ret.push_str(&format!("\nPartitions:\n{physical}"));
+    } else if !physical.is_empty() {
+        ret.push_str(&format!("\nPhysical partitions:\n{physical}"));
+    }
     debug!("{ret}");
-    Ok(ret)
+    Listing {
+        text: ret,
+        partitions: partitions.iter().map(|p| p.name.clone()).collect(),
+    }
+}
+
+/// Partition info of the payload at `url` as a JSON or CSV file.
+pub async fn export_image(url: String, format: ExportFormat) -> Result<Vec<u8>> {
+    info!("Exporting image info: {url}");
+    let info = get_rom_info(url).await?;
+    match format {
+        ExportFormat::Json => Ok(serde_json::to_vec_pretty(&info)?),
+        ExportFormat::Csv => {
+            let mut group_of = HashMap::new();
+            for group in info
+                .dynamic_partition_metadata
+                .iter()
+                .flat_map(|d| &d.groups)
+            {
+                for name in &group.partition_names {
+                    group_of.insert(name.as_str(), group.name.as_str());
+                }
+            }
+            let mut csv = String::from(
+                "name,size_bytes,size_readable,operations_count,compression_type,hash,group\n",
+            );
+            for p in &info.partitions {
+                let row = [
+                    p.name.clone(),
+                    p.size_bytes.to_string(),
+                    p.size_readable.clone(),
+                    p.operations_count.to_string(),
+                    p.compression_type.clone(),
+                    p.hash.clone().unwrap_or_default(),
+                    group_of
+                        .get(p.name.as_str())
+                        .copied()
+                        .unwrap_or_default()
+                        .to_string(),
+                ];
+                csv.push_str(&row.map(|f| utils::csv_field(&f)).join(","));
+                csv.push('\n');
+            }
+            Ok(csv.into_bytes())
+        }
+    }
+}
+
+fn yes_no(v: Option<bool>) -> &'static str {
+    match v {
+        Some(true) => "enabled",
+        Some(false) => "disabled",
+        None => "unknown",
+    }
+}
+
+/// Reads `post-build` from the OTA's `META-INF/com/android/metadata`, if it is stored uncompressed.
+pub async fn get_build_fingerprint(url: String) -> Result<Option<String>> {
+    info!("Getting build fingerprint: {url}");
+    url_policy::check_str(&url).await?;
+
+    let (tx, rx) = oneshot::channel();
+    thread::spawn(move || {
+        let result = RUNTIME.block_on(async {
+            let reader = remote::open(url).await?;
+            let (mut offset, num_entries) = ZipParser::get_central_directory_info(&reader).await?;
+            for _ in 0..num_entries {
+                let (entry, next_offset) =
+                    ZipParser::read_central_directory_entry(&reader, offset).await?;
+                offset = next_offset;
+                if entry.name != "META-INF/com/android/metadata" {
+                    continue;
+                }
+                if entry.compression_method != 0 {
+                    debug!("OTA metadata is compressed, skipping fingerprint");
+                    return Ok(None);
+                }
+                let data_offset = ZipParser::get_data_offset(&reader, &entry).await?;
+                let mut buf = vec![0u8; entry.uncompressed_size as usize];
+                reader.read_at(data_offset, &mut buf).await?;
+                return Ok(String::from_utf8_lossy(&buf)
+                    .lines()
+                    .find_map(|l| l.strip_prefix("post-build="))
+                    .map(|s| s.trim().to_string()));
+            }
+            anyhow::Ok(None)
+        });
+        let _ = tx.send(result);
+    });
+
+    rx.await?
 }
 
-async fn get_rom_info(url: String) -> Result<Value> {
+/// Fetches and parses the manifest of the payload at `url`.
+pub async fn get_rom_info(url: String) -> Result<RomInfo> {
     info!("Getting rom info: {url}");
+    rom_info(&open_payload(url).await?).await
+}
 
+/// A remote payload with its manifest.
+struct Opened {
+    payload: Arc<ZipPayload>,
+    manifest: DeltaArchiveManifest,
+    data_offset: u64,
+}
+
+/// Opens the payload at `url` and reads its manifest.
+async fn open_payload(url: String) -> Result<Opened> {
+    url_policy::check_str(&url).await?;
+
+    // On payload_dumper's runtime, where the extraction reuses its connections.
     let (tx, rx) = oneshot::channel();
     thread::spawn(move || {
-        let result = list_partitions_remote_zip(url, Option::from(utils::USER_AGENT), None);
+        let result = RUNTIME.block_on(async {
+            let started = Instant::now();
+            let payload = ZipPayload::open(url).await?;
+            let (manifest, data_offset) = payload.manifest().await?;
+            metrics::manifest_fetched(started.elapsed());
+            anyhow::Ok(Opened {
+                payload: Arc::new(payload),
+                manifest,
+                data_offset,
+            })
+        });
         let _ = tx.send(result);
     });
+    rx.await?
+}
 
-    let partition_list = rx.await??;
+async fn rom_info(opened: &Opened) -> Result<RomInfo> {
+    let data_bytes: HashMap<&str, u64> = opened
+        .manifest
+        .partitions
+        .iter()
+        .map(|p| {
+            let bytes = p.operations.iter().filter_map(|op| op.data_length).sum();
+            (p.partition_name.as_str(), bytes)
+        })
+        .collect();
+    let metadata = get_metadata(&opened.manifest, opened.data_offset, false, None).await?;
+    let partitions: Vec<Partition> = metadata
+        .partitions
+        .iter()
+        .map(|p| Partition {
+            name: p.partition_name.clone(),
+            size_bytes: p.size_in_bytes,
+            size_readable: p.size_readable.clone(),
+            operations_count: p.operations_count,
+            compression_type: p.compression_type.clone(),
+            hash: p.hash.clone(),
+            data_bytes: data_bytes
+                .get(p.partition_name.as_str())
+                .copied()
+                .unwrap_or_default(),
+        })
+        .collect();
+    let total_size = partitions.iter().map(|p| p.size_bytes).sum();
+    let dynamic_partition_metadata =
+        metadata
+            .dynamic_partition_metadata
+            .as_ref()
+            .map(|dynamic| DynamicPartitions {
+                groups: dynamic
+                    .groups
+                    .iter()
+                    .map(|g| PartitionGroup {
+                        name: g.name.clone(),
+                        size: g.size,
+                        size_readable: g.size_readable.clone(),
+                        partition_names: g.partition_names.clone(),
+                    })
+                    .collect(),
+                snapshot_enabled: dynamic.snapshot_enabled,
+                vabc_enabled: dynamic.vabc_enabled,
+                vabc_compression_param: dynamic.vabc_compression_param.clone(),
+                cow_version: dynamic.cow_version,
+            });
+    Ok(RomInfo {
+        total_partitions: partitions.len(),
+        total_operations: metadata.total_operations_count,
+        total_size_bytes: total_size,
+        total_size_readable: format_size(total_size),
+        partitions,
+        security_patch_level: metadata.security_patch_level.clone(),
+        dynamic_partition_metadata,
+    })
+}
 
-    Ok(serde_json::from_str(partition_list.json.as_str())?)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use axum::Router;
+    use axum::extract::State;
+    use axum::http::{HeaderMap, StatusCode, header};
+    use axum::response::{IntoResponse, Response};
+    use axum::routing::get;
+    use payload_dumper::structs::{
+        Extent, InstallOperation, PartitionInfo as UpdateInfo, PartitionUpdate,
+    };
+    use prost::Message;
+    use sha2::{Digest, Sha256};
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    const BLOCK_SIZE: usize = 4096;
+
+    /// An OTA zip with a `boot` partition of two blocks stored as one REPLACE operation.
+    fn ota_zip(boot: &[u8]) -> Vec<u8> {
+        let manifest = DeltaArchiveManifest {
+            block_size: Some(BLOCK_SIZE as u32),
+            partitions: vec![PartitionUpdate {
+                partition_name: "boot".to_string(),
+                new_partition_info: Some(UpdateInfo {
+                    size: Some(boot.len() as u64),
+                    hash: Some(Sha256::digest(boot).to_vec()),
+                }),
+                operations: vec![InstallOperation {
+                    data_offset: Some(0),
+                    data_length: Some(boot.len() as u64),
+                    dst_extents: vec![Extent {
+                        start_block: Some(0),
+                        num_blocks: Some((boot.len() / BLOCK_SIZE) as u64),
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+        .encode_to_vec();
+
+        let mut payload = b"CrAU".to_vec();
+        payload.extend(2u64.to_be_bytes());
+        payload.extend((manifest.len() as u64).to_be_bytes());
+        payload.extend(0u32.to_be_bytes());
+        payload.extend(manifest);
+        payload.extend(boot);
+
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let options =
+            SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        zip.start_file("payload.bin", options).unwrap();
+        zip.write_all(&payload).unwrap();
+        zip.finish().unwrap().into_inner()
+    }
+
+    async fn serve_range(State(body): State<Arc<Vec<u8>>>, headers: HeaderMap) -> Response {
+        let Some(range) = headers
+            .get(header::RANGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("bytes="))
+            .and_then(|v| v.split_once('-'))
+        else {
+            return body.to_vec().into_response();
+        };
+        let start: usize = range.0.parse().unwrap();
+        let end = range
+            .1
+            .parse::<usize>()
+            .map_or(body.len() - 1, |end| end.min(body.len() - 1));
+        (StatusCode::PARTIAL_CONTENT, body[start..=end].to_vec()).into_response()
+    }
+
+    async fn serve(body: Vec<u8>) -> String {
+        config::set_for_tests();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let app = Router::new()
+            .route("/ota.zip", get(serve_range))
+            .with_state(Arc::new(body));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://localhost:{port}/ota.zip")
+    }
+
+    #[tokio::test]
+    async fn dumps_partitions_from_a_remote_zip() {
+        let boot: Vec<u8> = (0..2 * BLOCK_SIZE).map(|i| (i % 251) as u8).collect();
+        let url = serve(ota_zip(&boot)).await;
+
+        let info = get_rom_info(url.clone()).await.unwrap();
+        assert_eq!(info.total_partitions, 1);
+        assert_eq!(info.partitions[0].name, "boot");
+        assert_eq!(info.partitions[0].size_bytes, boot.len() as u64);
+
+        let dumped = dump_partition(url, "boot,missing".to_string())
+            .await
+            .unwrap();
+        assert_eq!(dumped.files.len(), 1);
+        assert_eq!(std::fs::read(&dumped.files[0].path).unwrap(), boot);
+        verify_partitions(&dumped.files).await.unwrap();
+    }
 }
@@ -0,0 +1,168 @@
+use crate::i18n::tr;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+/// Partitions shown per keyboard page.
+const PAGE_SIZE: usize = 10;
+/// Sessions older than this are dropped, their buttons stop working.
+const SESSION_TTL: Duration = Duration::from_secs(60 * 60);
+const PREFIX: &str = "pk";
+
+static SESSIONS: LazyLock<Mutex<HashMap<u64, Session>>> = LazyLock::new(Default::default);
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Partition picker state behind a `/list` reply.
+///
+/// Only the session id travels in callback data, the url stays here.
+#[derive(Clone)]
+pub struct Session {
+    pub url: String,
+    pub partitions: Vec<String>,
+    pub selected: BTreeSet<usize>,
+    page: usize,
+    created: Instant,
+}
+
+impl Session {
+    pub fn selected_names(&self) -> Vec<String> {
+        self.selected
+            .iter()
+            .map(|&i| self.partitions[i].clone())
+            .collect()
+    }
+
+    /// Partition the KernelSU button patches, `init_boot` on GKI 2.0 devices.
+    pub fn ksu_partition(&self) -> Option<&'static str> {
+        ["init_boot", "boot"]
+            .into_iter()
+            .find(|p| self.partitions.iter().any(|n| n == p))
+    }
+
+    fn pages(&self) -> usize {
+        self.partitions.len().div_ceil(PAGE_SIZE).max(1)
+    }
+}
+
+pub enum Action {
+    Toggle(usize),
+    Page(usize),
+    Dump,
+    PatchKsu,
+    Noop,
+}
+
+pub fn create(url: String, partitions: Vec<String>) -> InlineKeyboardMarkup {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let session = Session {
+        url,
+        partitions,
+        selected: BTreeSet::new(),
+        page: 0,
+        created: Instant::now(),
+    };
+    let keyboard = keyboard(id, &session);
+    let mut sessions = SESSIONS.lock().unwrap();
+    sessions.retain(|_, s| s.created.elapsed() < SESSION_TTL);
+    sessions.insert(id, session);
+    keyboard
+}
+
+pub fn parse(data: &str) -> Option<(u64, Action)> {
+    let mut parts = data.split(':');
+    if parts.next()? != PREFIX {
+        return None;
+    }
+    let id = parts.next()?.parse().ok()?;
+    let action = match (parts.next()?, parts.next()) {
+        ("t", Some(idx)) => Action::Toggle(idx.parse().ok()?),
+        ("p", Some(page)) => Action::Page(page.parse().ok()?),
+        ("dump", None) => Action::Dump,
+        ("ksu", None) => Action::PatchKsu,
+        ("noop", None) => Action::Noop,
+        _ => return None,
+    };
+    Some((id, action))
+}
+
+pub fn get(id: u64) -> Option<Session> {
+    SESSIONS.lock().unwrap().get(&id).cloned()
+}
+
+/// Applies a toggle or page change and returns the updated keyboard.
+pub fn update(id: u64, action: &Action) -> Option<InlineKeyboardMarkup> {
+    let mut sessions = SESSIONS.lock().unwrap();
+    let session = sessions.get_mut(&id)?;
+    match *action {
+        Action::Toggle(idx) if idx < session.partitions.len() => {
+            if !session.selected.remove(&idx) {
+                session.selected.insert(idx);
+            }
+        }
+        Action::Page(page) if page < session.pages() => session.page = page,
+        _ => return None,
+    }
+    Some(keyboard(id, session))
+}
+
+fn keyboard(id: u64, session: &Session) -> InlineKeyboardMarkup {
+    let start = session.page * PAGE_SIZE;
+    let mut rows: Vec<Vec<InlineKeyboardButton>> = session
+        .partitions
+        .iter()
+        .enumerate()
+        .skip(start)
+        .take(PAGE_SIZE)
+        .map(|(idx, name)| {
+            let mark = if session.selected.contains(&idx) {
+                "✅ "
+            } else {
+                ""
+            };
+            InlineKeyboardButton::callback(
+                format!("{mark}{name}"),
+                format!("{PREFIX}:{id}:t:{idx}"),
+            )
+        })
+        .collect::<Vec<_>>()
+        .chunks(2)
+        .map(|c| c.to_vec())
+        .collect();
+
+    let pages = session.pages();
+    if pages > 1 {
+        let mut nav = Vec::new();
+        if session.page > 0 {
+            nav.push(InlineKeyboardButton::callback(
+                tr!("picker-prev"),
+                format!("{PREFIX}:{id}:p:{}", session.page - 1),
+            ));
+        }
+        nav.push(InlineKeyboardButton::callback(
+            format!("{}/{pages}", session.page + 1),
+            format!("{PREFIX}:{id}:noop"),
+        ));
+        if session.page + 1 < pages {
+            nav.push(InlineKeyboardButton::callback(
+                tr!("picker-next"),
+                format!("{PREFIX}:{id}:p:{}", session.page + 1),
+            ));
+        }
+        rows.push(nav);
+    }
+
+    let mut actions = vec![InlineKeyboardButton::callback(
+        tr!("picker-dump-selected", count = session.selected.len()),
+        format!("{PREFIX}:{id}:dump"),
+    )];
+    if let Some(partition) = session.ksu_partition() {
+        actions.push(InlineKeyboardButton::callback(
+            tr!("picker-patch-ksu", partition = partition),
+            format!("{PREFIX}:{id}:ksu"),
+        ));
+    }
+    rows.push(actions);
+    InlineKeyboardMarkup::new(rows)
+}
@@ -0,0 +1,208 @@
+use crate::config::Config;
+use anyhow::{Context, Result};
+use log::info;
+use reqwest::{ClientBuilder, Proxy, Url};
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, RwLock};
+
+/// Override value that bypasses the proxy for a destination.
+const DIRECT: &str = "direct";
+
+/// Current proxy settings, replaced by [`init`] on reload.
+static SETTINGS: LazyLock<RwLock<Arc<ProxySettings>>> = LazyLock::new(Default::default);
+
+fn settings() -> Arc<ProxySettings> {
+    SETTINGS.read().unwrap().clone()
+}
+
+#[derive(Default)]
+struct ProxySettings {
+    proxy: Option<Url>,
+    overrides: HashMap<String, Option<Url>>,
+    system: SystemProxies,
+}
+
+/// `HTTP_PROXY`, `HTTPS_PROXY`, `ALL_PROXY` and `NO_PROXY` of the environment,
+/// used when `PROXY` is unset.
+#[derive(Default)]
+struct SystemProxies {
+    http: Option<Url>,
+    https: Option<Url>,
+    no_proxy: Vec<String>,
+}
+
+impl SystemProxies {
+    fn from(var: impl Fn(&str) -> Option<String>) -> Self {
+        let get = |name: &str| {
+            var(name)
+                .or_else(|| var(&name.to_ascii_lowercase()))
+                .filter(|v| !v.is_empty())
+        };
+        let url = |name: &str| get(name).and_then(|v| Url::parse(&v).ok());
+        let all = url("ALL_PROXY");
+        Self {
+            http: url("HTTP_PROXY").or_else(|| all.clone()),
+            https: url("HTTPS_PROXY").or(all),
+            no_proxy: get("NO_PROXY")
+                .unwrap_or_default()
+                .split(',')
+                .map(|h| h.trim().trim_start_matches('.').to_ascii_lowercase())
+                .filter(|h| !h.is_empty())
+                .collect(),
+        }
+    }
+
+    fn lookup(&self, url: &Url, host: &str) -> Option<Url> {
+        let bypass = self
+            .no_proxy
+            .iter()
+            .any(|h| h == "*" || host == h || host.ends_with(&format!(".{h}")));
+        match url.scheme() {
+            _ if bypass => None,
+            "https" => self.https.clone(),
+            _ => self.http.clone(),
+        }
+    }
+}
+
+impl ProxySettings {
+    fn from(config: &Config) -> Result<Self> {
+        let proxy = match config.proxy.as_deref() {
+            Some(p) if !p.is_empty() => Some(Url::parse(p).context("Invalid PROXY")?),
+            _ => None,
+        };
+        let mut overrides = HashMap::new();
+        for (host, target) in &config.proxy_overrides {
+            let target = if target == DIRECT {
+                None
+            } else {
+                Some(
+                    Url::parse(target)
+                        .with_context(|| format!("Invalid PROXY_OVERRIDES.{host}"))?,
+                )
+            };
+            overrides.insert(host.to_ascii_lowercase(), target);
+        }
+        let system = SystemProxies::from(|name| std::env::var(name).ok());
+        Ok(Self {
+            proxy,
+            overrides,
+            system,
+        })
+    }
+
+    fn lookup(&self, url: &Url) -> Option<Url> {
+        let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+        let host_port = url.port().map(|port| format!("{host}:{port}"));
+        let found = host_port
+            .iter()
+            .chain(std::iter::once(&host))
+            .find_map(|h| {
+                self.overrides
+                    .iter()
+                    .find(|(k, _)| *h == **k || h.ends_with(&format!(".{k}")))
+            });
+        match found {
+            Some((_, target)) => target.clone(),
+            None => self
+                .proxy
+                .clone()
+                .or_else(|| self.system.lookup(url, &host)),
+        }
+    }
+}
+
+/// Stores the proxy settings, requests look them up when they connect.
+pub fn init(config: &Config) -> Result<()> {
+    let settings = ProxySettings::from(config)?;
+    if let Some(proxy) = &settings.proxy {
+        info!("Using proxy {proxy}");
+    }
+    *SETTINGS.write().unwrap() = Arc::new(settings);
+    Ok(())
+}
+
+/// Routes every request of `builder` through the proxy current at connect time,
+/// so proxies changed on reload apply to clients built before.
+pub fn apply(builder: ClientBuilder) -> ClientBuilder {
+    builder.proxy(Proxy::custom(|url| settings().lookup(url)))
+}
+
+/// Whether `host` is one of the configured proxies, which may well be local.
+pub fn is_proxy_host(host: &str) -> bool {
+    let settings = settings();
+    settings
+        .proxy
+        .iter()
+        .chain(settings.overrides.values().flatten())
+        .any(|p| p.host_str().is_some_and(|h| h.eq_ignore_ascii_case(host)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn falls_back_to_the_environment() {
+        let env = HashMap::from([
+            ("https_proxy", "http://10.0.0.1:3128"),
+            ("ALL_PROXY", "socks5h://10.0.0.2:1080"),
+            ("NO_PROXY", "localhost, .lan"),
+        ]);
+        let mut settings = ProxySettings {
+            system: SystemProxies::from(|name| env.get(name).map(|v| v.to_string())),
+            ..Default::default()
+        };
+        let lookup = |settings: &ProxySettings, url: &str| {
+            settings.lookup(&Url::parse(url).unwrap()).map(String::from)
+        };
+        assert_eq!(
+            lookup(&settings, "https://example.com/"),
+            Some("http://10.0.0.1:3128/".to_string())
+        );
+        assert_eq!(
+            lookup(&settings, "http://example.com/"),
+            Some("socks5h://10.0.0.2:1080".to_string())
+        );
+        assert_eq!(lookup(&settings, "http://localhost:8081/"), None);
+        assert_eq!(lookup(&settings, "http://mirror.lan/"), None);
+        settings.proxy = Some(Url::parse("http://10.0.0.3:3128").unwrap());
+        assert_eq!(
+            lookup(&settings, "http://mirror.lan/"),
+            Some("http://10.0.0.3:3128/".to_string())
+        );
+        assert_eq!(
+            ProxySettings::default().lookup(&Url::parse("http://a/").unwrap()),
+            None
+        );
+    }
+
+    #[test]
+    fn overrides_match_hosts_subdomains_and_ports() {
+        let mut config = Config::default();
+        config.proxy = Some("socks5h://127.0.0.1:1080".to_string());
+        config.proxy_overrides = HashMap::from([
+            ("api.local:8081".to_string(), DIRECT.to_string()),
+            (
+                "Example.com".to_string(),
+                "http://10.0.0.1:3128".to_string(),
+            ),
+        ]);
+        let settings = ProxySettings::from(&config).unwrap();
+        let lookup = |url: &str| settings.lookup(&Url::parse(url).unwrap()).map(String::from);
+
+        assert_eq!(lookup("http://api.local:8081/bot"), None);
+        assert_eq!(
+            lookup("http://api.local/"),
+            Some("socks5h://127.0.0.1:1080".to_string())
+        );
+        assert_eq!(
+            lookup("https://dl.example.com/rom.zip"),
+            Some("http://10.0.0.1:3128/".to_string())
+        );
+        assert_eq!(
+            lookup("https://notexample.com/"),
+            Some("socks5h://127.0.0.1:1080".to_string())
+        );
+    }
+}
@@ -0,0 +1,209 @@
+//! Remote payloads read with range requests.
+//!
+//! payload_dumper's own http client follows redirects unchecked and ignores
+//! per-host proxies, so payloads are fetched through [`client`] instead, which
+//! applies the proxy settings and [`url_policy::guard`] on every hop.
+
+use crate::{proxy, url_policy, utils};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use futures_util::TryStreamExt;
+use log::debug;
+use payload_dumper::constants::{PAYLOAD_MAGIC, SUPPORTED_PAYLOAD_VERSION};
+use payload_dumper::http::HttpReader;
+use payload_dumper::payload::payload_dumper::{AsyncPayloadRead, PayloadReader};
+use payload_dumper::structs::DeltaArchiveManifest;
+use payload_dumper::zip::core_parser::ZipParser;
+use prost::Message;
+use reqwest::header::{CONTENT_LENGTH, RANGE};
+use reqwest::{Client, StatusCode};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncRead;
+use tokio_util::io::StreamReader;
+
+const MAX_TRIES: u32 = 3;
+/// Magic, version, manifest size and signature size in front of the manifest.
+const HEADER_SIZE: u64 = 24;
+
+/// Http client for payload downloads.
+pub fn client() -> Result<Client> {
+    let builder = Client::builder()
+        .user_agent(utils::USER_AGENT)
+        .timeout(Duration::from_secs(600))
+        .connect_timeout(Duration::from_secs(30))
+        .pool_max_idle_per_host(32)
+        .tcp_keepalive(Some(Duration::from_secs(30)));
+    Ok(url_policy::guard(proxy::apply(builder)).build()?)
+}
+
+/// Opens `url` for range reads.
+pub async fn open(url: String) -> Result<HttpReader> {
+    let client = client()?;
+    let mut tries = 0;
+    let response = loop {
+        tries += 1;
+        match client.head(&url).send().await {
+            Ok(response) => break response,
+            // A blocked redirect fails the same way every time.
+            Err(e) if e.is_redirect() || tries >= MAX_TRIES => return Err(e.into()),
+            Err(e) => {
+                debug!("HEAD {url} failed, try {tries}: {e}");
+                tokio::time::sleep(Duration::from_secs(2u64.pow(tries))).await;
+            }
+        }
+    };
+    if !response.status().is_success() {
+        return Err(anyhow!("Failed to access URL: {}", response.status()));
+    }
+    let content_length = response
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok())
+        .filter(|len| *len > 0)
+        .ok_or_else(|| anyhow!("Could not determine content length"))?;
+    Ok(HttpReader {
+        client,
+        url,
+        content_length,
+    })
+}
+
+/// The `payload.bin` inside a remote OTA zip.
+pub struct ZipPayload {
+    http: Arc<HttpReader>,
+    offset: u64,
+    size: u64,
+}
+
+impl ZipPayload {
+    pub async fn open(url: String) -> Result<Self> {
+        let http = open(url).await?;
+        let entry = ZipParser::find_payload_entry(&http).await?;
+        let offset = ZipParser::get_data_offset(&http, &entry).await?;
+        ZipParser::verify_payload_magic(&http, offset).await?;
+        Ok(Self {
+            http: Arc::new(http),
+            offset,
+            size: entry.uncompressed_size,
+        })
+    }
+
+    /// Reads the manifest, returns it with the offset of the data blobs in the payload.
+    pub async fn manifest(&self) -> Result<(DeltaArchiveManifest, u64)> {
+        let mut header = [0u8; HEADER_SIZE as usize];
+        self.http.read_at(self.offset, &mut header).await?;
+        if header[..4] != PAYLOAD_MAGIC[..] {
+            return Err(anyhow!("Invalid payload file: magic 'CrAU' not found"));
+        }
+        let version = u64::from_be_bytes(header[4..12].try_into()?);
+        if version != SUPPORTED_PAYLOAD_VERSION {
+            return Err(anyhow!("Unsupported payload version: {version}"));
+        }
+        let manifest_size = u64::from_be_bytes(header[12..20].try_into()?);
+        let signature_size = u32::from_be_bytes(header[20..24].try_into()?) as u64;
+        if HEADER_SIZE + manifest_size + signature_size > self.size {
+            return Err(anyhow!("Manifest size {manifest_size} exceeds the payload"));
+        }
+        let mut manifest = vec![0u8; manifest_size as usize];
+        self.http
+            .read_at(self.offset + HEADER_SIZE, &mut manifest)
+            .await?;
+        let data_offset = HEADER_SIZE + manifest_size + signature_size;
+        Ok((DeltaArchiveManifest::decode(&manifest[..])?, data_offset))
+    }
+}
+
+#[async_trait]
+impl AsyncPayloadRead for ZipPayload {
+    async fn open_reader(&self) -> Result<Box<dyn PayloadReader>> {
+        Ok(Box::new(RangeReader {
+            http: self.http.clone(),
+            offset: self.offset,
+            size: self.size,
+        }))
+    }
+}
+
+struct RangeReader {
+    http: Arc<HttpReader>,
+    offset: u64,
+    size: u64,
+}
+
+#[async_trait]
+impl PayloadReader for RangeReader {
+    async fn read_range(
+        &mut self,
+        offset: u64,
+        length: u64,
+    ) -> Result<Pin<Box<dyn AsyncRead + Send + '_>>> {
+        if length == 0 || offset + length > self.size {
+            return Err(anyhow!(
+                "Read of {length} bytes at {offset} is outside the payload of {} bytes",
+                self.size
+            ));
+        }
+        let start = self.offset + offset;
+        let response = self
+            .http
+            .client
+            .get(&self.http.url)
+            .header(RANGE, format!("bytes={start}-{}", start + length - 1))
+            .send()
+            .await?;
+        // A server ignoring the range answers 200 with the file from byte 0.
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            return Err(anyhow!("Range request failed: {}", response.status()));
+        }
+        let stream = response.bytes_stream().map_err(std::io::Error::other);
+        Ok(Box::pin(StreamReader::new(stream)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use axum::Router;
+    use axum::routing::get;
+    use tokio::io::AsyncReadExt;
+
+    async fn reader(path: &str) -> RangeReader {
+        config::set_for_tests();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let app = Router::new()
+            .route(
+                "/ranged",
+                get(|| async { (StatusCode::PARTIAL_CONTENT, "cd") }),
+            )
+            .route("/ignored", get(|| async { "abcd" }));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let http = HttpReader {
+            client: client().unwrap(),
+            url: format!("http://localhost:{port}{path}"),
+            content_length: 4,
+        };
+        RangeReader {
+            http: Arc::new(http),
+            offset: 0,
+            size: 4,
+        }
+    }
+
+    #[tokio::test]
+    async fn range_reads_need_partial_content() {
+        let mut ranged = reader("/ranged").await;
+        let mut data = String::new();
+        let mut read = ranged.read_range(2, 2).await.unwrap();
+        read.read_to_string(&mut data).await.unwrap();
+        assert_eq!(data, "cd");
+
+        let mut ignored = reader("/ignored").await;
+        let err = ignored.read_range(2, 2).await.err().unwrap();
+        assert!(err.to_string().contains("200"), "{err}");
+    }
+}
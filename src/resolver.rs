@@ -0,0 +1,531 @@
+use crate::url_policy::{self, Blocked};
+use crate::utils;
+use anyhow::Result;
+use async_trait::async_trait;
+use log::{debug, info};
+use regex::Regex;
+use reqwest::header::{CONTENT_TYPE, LOCATION, RANGE, REFERER};
+use reqwest::redirect::Policy;
+use reqwest::{Client, Response, Url};
+use serde_json::Value;
+use std::sync::LazyLock;
+
+/// Upper bound of resolver hops, so two resolvers can't bounce a URL forever.
+const MAX_HOPS: usize = 8;
+/// Upper bound of http redirects followed by a single probe.
+const MAX_REDIRECTS: usize = 10;
+
+static REGISTRY: LazyLock<ResolverRegistry> = LazyLock::new(ResolverRegistry::default);
+/// Links to zip files on landing pages.
+static ZIP_LINK: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"href=["']([^"']+\.zip(?:\?[^"']*)?)["']"#).unwrap());
+/// Target of a meta refresh, used by SourceForge download pages.
+static META_REFRESH: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?i)http-equiv=["']refresh["'][^>]*url=([^"'>]+)"#).unwrap());
+
+/// What a resolver made of a link.
+#[derive(Debug)]
+pub enum Resolved {
+    /// The direct zip URL, or a link nothing more can be done about.
+    Final(Url),
+    /// A link for the next matching resolver to look at.
+    Next(Url),
+}
+
+/// Turns a user supplied link into something closer to a direct zip URL.
+#[async_trait]
+pub trait UrlResolver: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn matches(&self, url: &Url) -> bool;
+    async fn resolve(&self, client: &Client, url: Url) -> Result<Resolved>;
+}
+
+pub struct ResolverRegistry {
+    client: Client,
+    resolvers: Vec<Box<dyn UrlResolver>>,
+}
+
+impl Default for ResolverRegistry {
+    fn default() -> Self {
+        let client = url_policy::guard(crate::proxy::apply(Client::builder()))
+            .user_agent(utils::USER_AGENT)
+            // Redirects are followed by `probe`, so resolvers see every hop.
+            .redirect(Policy::none())
+            .build()
+            .expect("Create resolver http client error");
+        let mut registry = Self::new(client);
+        registry.register(SourceForge::default());
+        registry.register(AndroidFileHost::default());
+        registry.register(GoogleDrive::default());
+        registry.register(Direct);
+        registry
+    }
+}
+
+impl ResolverRegistry {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            resolvers: Vec::new(),
+        }
+    }
+
+    /// Resolvers are tried in registration order, the first match wins.
+    pub fn register(&mut self, resolver: impl UrlResolver + 'static) {
+        self.resolvers.push(Box::new(resolver));
+    }
+
+    pub async fn resolve(&self, url: &str) -> Result<String> {
+        let mut url = Url::parse(url)?;
+        for _ in 0..MAX_HOPS {
+            url_policy::check(&url).await?;
+            let Some(resolver) = self.resolvers.iter().find(|r| r.matches(&url)) else {
+                break;
+            };
+            debug!("Resolving {url} with {}", resolver.name());
+            match resolver.resolve(&self.client, url.clone()).await? {
+                Resolved::Next(next) if next != url => url = next,
+                Resolved::Next(_) => break,
+                Resolved::Final(last) => {
+                    url = last;
+                    break;
+                }
+            }
+        }
+        url_policy::check(&url).await?;
+        Ok(url.to_string())
+    }
+}
+
+pub async fn resolve(url: &str) -> Result<String> {
+    let resolved = REGISTRY.resolve(url).await?;
+    if resolved != url {
+        info!("Resolved {url} to {resolved}");
+    }
+    Ok(resolved)
+}
+
+fn host_matches(url: &Url, host: &str) -> bool {
+    let Some(h) = url.host_str() else {
+        return false;
+    };
+    let h = match url.port() {
+        Some(port) => format!("{h}:{port}"),
+        None => h.to_string(),
+    };
+    h == host || h.ends_with(&format!(".{host}"))
+}
+
+/// Fetches the first byte of `url`, following redirects.
+async fn probe(client: &Client, mut url: Url) -> Result<Response> {
+    let mut redirects = 0;
+    let resp = loop {
+        url_policy::check(&url).await?;
+        let resp = client
+            .get(url.clone())
+            .header(RANGE, "bytes=0-0")
+            .send()
+            .await?;
+        let location = resp.headers().get(LOCATION).and_then(|v| v.to_str().ok());
+        match location {
+            Some(location) if resp.status().is_redirection() => {
+                redirects += 1;
+                if redirects > MAX_REDIRECTS {
+                    return Err(anyhow::anyhow!("Too many redirects from {url}"));
+                }
+                url = url.join(location)?;
+            }
+            _ => break resp,
+        }
+    };
+    if !resp.status().is_success() {
+        return Err(anyhow::anyhow!(
+            "Failed to resolve {}: {}",
+            resp.url(),
+            resp.status()
+        ));
+    }
+    Ok(resp)
+}
+
+fn is_html(resp: &Response) -> bool {
+    resp.headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("text/html"))
+}
+
+/// Picks the first link in `html` matching `re`'s first capture group.
+fn find_link(base: &Url, html: &str, re: &Regex) -> Option<Url> {
+    re.captures_iter(html)
+        .filter_map(|caps| caps.get(1))
+        .find_map(|m| base.join(&m.as_str().replace("&amp;", "&")).ok())
+}
+
+/// Follows redirects, and digs a zip link out of HTML landing pages.
+pub struct Direct;
+
+#[async_trait]
+impl UrlResolver for Direct {
+    fn name(&self) -> &'static str {
+        "direct"
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        matches!(url.scheme(), "http" | "https")
+    }
+
+    async fn resolve(&self, client: &Client, url: Url) -> Result<Resolved> {
+        // Leave links we can't probe to the payload reader, which reports its own errors.
+        let resp = match probe(client, url.clone()).await {
+            Ok(resp) => resp,
+            Err(e) if e.is::<Blocked>() => return Err(e),
+            Err(e) => {
+                debug!("Probing {url} failed: {e}");
+                return Ok(Resolved::Final(url));
+            }
+        };
+        let final_url = resp.url().clone();
+        if !is_html(&resp) {
+            return Ok(Resolved::Final(final_url));
+        }
+        let html = resp.text().await?;
+        find_link(&final_url, &html, &ZIP_LINK)
+            .map(Resolved::Next)
+            .ok_or_else(|| anyhow::anyhow!("No zip link found on page {final_url}"))
+    }
+}
+
+pub struct SourceForge {
+    pub host: String,
+}
+
+impl Default for SourceForge {
+    fn default() -> Self {
+        Self {
+            host: "sourceforge.net".to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl UrlResolver for SourceForge {
+    fn name(&self) -> &'static str {
+        "sourceforge"
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        host_matches(url, &self.host) && url.path().contains("/files/")
+    }
+
+    async fn resolve(&self, client: &Client, mut url: Url) -> Result<Resolved> {
+        if !url.path().ends_with("/download") {
+            let path = format!("{}/download", url.path().trim_end_matches('/'));
+            url.set_path(&path);
+        }
+        let resp = probe(client, url).await?;
+        let final_url = resp.url().clone();
+        if !is_html(&resp) {
+            return Ok(Resolved::Final(final_url));
+        }
+        // The download page hands out the mirror through a meta refresh.
+        let html = resp.text().await?;
+        find_link(&final_url, &html, &META_REFRESH)
+            .map(Resolved::Next)
+            .ok_or_else(|| anyhow::anyhow!("No mirror found on page {final_url}"))
+    }
+}
+
+pub struct AndroidFileHost {
+    pub host: String,
+    pub mirrors_api: String,
+}
+
+impl Default for AndroidFileHost {
+    fn default() -> Self {
+        Self {
+            host: "androidfilehost.com".to_string(),
+            mirrors_api: "https://androidfilehost.com/libs/otf/mirrors.otf.php".to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl UrlResolver for AndroidFileHost {
+    fn name(&self) -> &'static str {
+        "androidfilehost"
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        host_matches(url, &self.host) && url.query_pairs().any(|(k, _)| k == "fid")
+    }
+
+    async fn resolve(&self, client: &Client, url: Url) -> Result<Resolved> {
+        let fid = url
+            .query_pairs()
+            .find(|(k, _)| k == "fid")
+            .map(|(_, v)| v.to_string())
+            .unwrap_or_default();
+        let resp = client
+            .post(&self.mirrors_api)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header(REFERER, url.as_str())
+            .header("X-Requested-With", "XMLHttpRequest")
+            .body(format!("submit=submit&action=getdownloadmirrors&fid={fid}"))
+            .send()
+            .await?;
+        if !resp.status().is_success() {
+            return Err(anyhow::anyhow!(
+                "Failed to fetch mirrors of {fid}: {}",
+                resp.status()
+            ));
+        }
+        let body: Value = serde_json::from_str(&resp.text().await?)?;
+        let mirror = body["MIRRORS"]
+            .as_array()
+            .and_then(|m| m.first())
+            .and_then(|m| m["url"].as_str())
+            .ok_or_else(|| anyhow::anyhow!("No mirror found for fid {fid}"))?;
+        Ok(Resolved::Next(Url::parse(mirror)?))
+    }
+}
+
+pub struct GoogleDrive {
+    pub host: String,
+    pub download_api: String,
+}
+
+impl Default for GoogleDrive {
+    fn default() -> Self {
+        Self {
+            host: "drive.google.com".to_string(),
+            download_api: "https://drive.usercontent.google.com/download".to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl UrlResolver for GoogleDrive {
+    fn name(&self) -> &'static str {
+        "googledrive"
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        host_matches(url, &self.host)
+    }
+
+    async fn resolve(&self, _client: &Client, url: Url) -> Result<Resolved> {
+        let mut segments = url.path_segments().into_iter().flatten();
+        let id = segments
+            .find(|s| *s == "d")
+            .and_then(|_| segments.next())
+            .map(|s| s.to_string())
+            .or_else(|| {
+                url.query_pairs()
+                    .find(|(k, _)| k == "id")
+                    .map(|(_, v)| v.to_string())
+            })
+            .ok_or_else(|| anyhow::anyhow!("No file id in {url}"))?;
+        let mut direct = Url::parse(&self.download_api)?;
+        direct
+            .query_pairs_mut()
+            .append_pair("id", &id)
+            .append_pair("export", "download")
+            .append_pair("confirm", "t");
+        Ok(Resolved::Next(direct))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{self, Config};
+    use axum::Router;
+    use axum::extract::Query;
+    use axum::http::{StatusCode, header};
+    use axum::response::{Html, IntoResponse, Redirect, Response};
+    use axum::routing::{get, post};
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Requests to `/counted.zip`.
+    static COUNTED: AtomicUsize = AtomicUsize::new(0);
+
+    /// First byte of a zip, as answered to a range request.
+    async fn zip() -> Response {
+        (
+            StatusCode::PARTIAL_CONTENT,
+            [(header::CONTENT_TYPE, "application/zip")],
+            "P",
+        )
+            .into_response()
+    }
+
+    /// Serves a small test site on loopback, returns its `localhost:port`.
+    async fn serve() -> String {
+        let mut config = Config::default();
+        config.url_allowlist = vec!["localhost".to_string()];
+        config.url_allowed_ports = Vec::new();
+        config::set(config);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let host = format!("localhost:{}", listener.local_addr().unwrap().port());
+        let base = format!("http://{host}");
+        let app = Router::new()
+            .route("/rom.zip", get(zip))
+            .route(
+                "/old.zip",
+                get(|| async { Redirect::temporary("/rom.zip") }),
+            )
+            .route(
+                "/page",
+                get(|| async {
+                    Html(r#"<a href="/help">help</a> <a href="rom.zip?x=1&amp;y=2">rom</a>"#)
+                }),
+            )
+            .route("/empty", get(|| async { Html("<p>nothing</p>") }))
+            .route(
+                "/moved.zip",
+                get(|| async { Redirect::temporary("/counted.zip") }),
+            )
+            .route(
+                "/counted.zip",
+                get(|| async {
+                    COUNTED.fetch_add(1, Ordering::Relaxed);
+                    zip().await
+                }),
+            )
+            .route(
+                "/projects/rom/files/rom.zip/download",
+                get(|| async {
+                    Html(r#"<meta http-equiv="refresh" content="5; url=/mirror/rom.zip">"#)
+                }),
+            )
+            .route("/mirror/rom.zip", get(zip))
+            .route(
+                "/mirrors",
+                post(move |body: String| async move {
+                    assert!(body.contains("fid=42"), "{body}");
+                    axum::Json(serde_json::json!({
+                        "MIRRORS": [{ "url": format!("{base}/rom.zip") }]
+                    }))
+                }),
+            )
+            .route(
+                "/download",
+                get(|Query(q): Query<HashMap<String, String>>| async move {
+                    match q.get("id").map(String::as_str) {
+                        Some("abc") => zip().await,
+                        _ => StatusCode::NOT_FOUND.into_response(),
+                    }
+                }),
+            )
+            .route(
+                "/private",
+                get(|| async { Redirect::temporary("http://127.0.0.1/rom.zip") }),
+            );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        host
+    }
+
+    fn registry(resolver: Option<Box<dyn UrlResolver>>) -> ResolverRegistry {
+        let client = url_policy::guard(Client::builder())
+            .redirect(Policy::none())
+            .build()
+            .unwrap();
+        let mut registry = ResolverRegistry::new(client);
+        if let Some(resolver) = resolver {
+            registry.resolvers.push(resolver);
+        }
+        registry.register(Direct);
+        registry
+    }
+
+    #[tokio::test]
+    async fn direct_follows_redirects_and_landing_pages() {
+        let host = serve().await;
+        let registry = registry(None);
+        let rom = format!("http://{host}/rom.zip");
+        assert_eq!(registry.resolve(&rom).await.unwrap(), rom);
+        assert_eq!(
+            registry
+                .resolve(&format!("http://{host}/old.zip"))
+                .await
+                .unwrap(),
+            rom
+        );
+        assert_eq!(
+            registry
+                .resolve(&format!("http://{host}/page"))
+                .await
+                .unwrap(),
+            format!("{rom}?x=1&y=2")
+        );
+        assert!(
+            registry
+                .resolve(&format!("http://{host}/empty"))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn direct_probes_a_redirected_link_once() {
+        let host = serve().await;
+        let resolved = registry(None)
+            .resolve(&format!("http://{host}/moved.zip"))
+            .await
+            .unwrap();
+        assert_eq!(resolved, format!("http://{host}/counted.zip"));
+        assert_eq!(COUNTED.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn redirects_to_private_addresses_are_blocked() {
+        let host = serve().await;
+        let err = registry(None)
+            .resolve(&format!("http://{host}/private"))
+            .await
+            .unwrap_err();
+        assert!(err.is::<Blocked>(), "{err}");
+    }
+
+    #[tokio::test]
+    async fn sourceforge_follows_the_mirror_refresh() {
+        let host = serve().await;
+        let registry = registry(Some(Box::new(SourceForge { host: host.clone() })));
+        let resolved = registry
+            .resolve(&format!("http://{host}/projects/rom/files/rom.zip"))
+            .await
+            .unwrap();
+        assert_eq!(resolved, format!("http://{host}/mirror/rom.zip"));
+    }
+
+    #[tokio::test]
+    async fn androidfilehost_asks_for_mirrors() {
+        let host = serve().await;
+        let registry = registry(Some(Box::new(AndroidFileHost {
+            host: host.clone(),
+            mirrors_api: format!("http://{host}/mirrors"),
+        })));
+        let resolved = registry
+            .resolve(&format!("http://{host}/?fid=42"))
+            .await
+            .unwrap();
+        assert_eq!(resolved, format!("http://{host}/rom.zip"));
+    }
+
+    #[tokio::test]
+    async fn googledrive_uses_the_download_endpoint() {
+        let host = serve().await;
+        let registry = registry(Some(Box::new(GoogleDrive {
+            host: host.clone(),
+            download_api: format!("http://{host}/download"),
+        })));
+        let expected = format!("http://{host}/download?id=abc&export=download&confirm=t");
+        for url in ["/file/d/abc/view?usp=sharing", "/open?id=abc"] {
+            let resolved = registry.resolve(&format!("http://{host}{url}")).await;
+            assert_eq!(resolved.unwrap(), expected);
+        }
+    }
+}
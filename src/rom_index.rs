@@ -0,0 +1,333 @@
+use crate::config::{Config, RomIndexConfig};
+use crate::{proxy, url_policy, utils};
+use anyhow::Result;
+use async_trait::async_trait;
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::PathBuf;
+
+/// A downloadable OTA found by a provider.
+#[derive(Clone, Debug, Deserialize)]
+pub struct RomRelease {
+    #[serde(default)]
+    pub region: Option<String>,
+    #[serde(default)]
+    pub version: Option<String>,
+    pub url: String,
+    #[serde(default)]
+    pub security_patch: Option<String>,
+    #[serde(default)]
+    pub timestamp: u64,
+}
+
+/// Looks up the latest OTA of a device by its codename.
+#[async_trait]
+pub trait RomIndexProvider: Send + Sync {
+    fn name(&self) -> String;
+    async fn latest(&self, codename: &str, region: Option<&str>) -> Result<Option<RomRelease>>;
+}
+
+/// Builds the providers configured in `ROM_INDEXES`, in order.
+pub fn providers(config: &Config) -> Vec<Box<dyn RomIndexProvider>> {
+    config
+        .rom_indexes
+        .iter()
+        .map(|c| -> Box<dyn RomIndexProvider> {
+            match c {
+                RomIndexConfig::Json { url } => Box::new(JsonIndex { url: url.clone() }),
+                RomIndexConfig::Updater { url } => Box::new(UpdaterApi { url: url.clone() }),
+                RomIndexConfig::File { path } => Box::new(FileIndex { path: path.clone() }),
+            }
+        })
+        .collect()
+}
+
+/// Asks every provider in turn and returns the first hit.
+pub async fn find_latest(
+    providers: &[Box<dyn RomIndexProvider>],
+    codename: &str,
+    region: Option<&str>,
+) -> Result<Option<(String, RomRelease)>> {
+    if providers.is_empty() {
+        return Err(anyhow::anyhow!("No ROM index configured"));
+    }
+    check_name("codename", codename)?;
+    if let Some(region) = region {
+        check_name("region", region)?;
+    }
+    for provider in providers {
+        info!("Looking up {codename} in {}", provider.name());
+        match provider.latest(codename, region).await {
+            Ok(Some(release)) => return Ok(Some((provider.name(), release))),
+            Ok(None) => {}
+            Err(e) => warn!("ROM index {} failed: {e}", provider.name()),
+        }
+    }
+    Ok(None)
+}
+
+/// Picks the newest release of `codename` from an index shaped like
+/// `{"<codename>": [{"region": .., "version": .., "url": .., "timestamp": ..}]}`.
+fn pick_from_index(
+    index: &Value,
+    codename: &str,
+    region: Option<&str>,
+) -> Result<Option<RomRelease>> {
+    let Some(releases) = index.get(codename) else {
+        return Ok(None);
+    };
+    let releases: Vec<RomRelease> = serde_json::from_value(releases.clone())?;
+    Ok(releases
+        .into_iter()
+        .filter(|r| match region {
+            Some(region) => r
+                .region
+                .as_deref()
+                .is_some_and(|r| r.eq_ignore_ascii_case(region)),
+            None => true,
+        })
+        .max_by_key(|r| r.timestamp))
+}
+
+/// Codenames and regions go into provider URLs, so only `[A-Za-z0-9_-]` is accepted.
+fn check_name(what: &str, name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    match valid {
+        true => Ok(()),
+        false => Err(anyhow::anyhow!("Invalid {what}: {name}")),
+    }
+}
+
+fn fill_template(template: &str, codename: &str, region: Option<&str>) -> Result<String> {
+    check_name("codename", codename)?;
+    if let Some(region) = region {
+        check_name("region", region)?;
+    }
+    Ok(template
+        .replace("{codename}", codename)
+        .replace("{region}", region.unwrap_or_default()))
+}
+
+async fn fetch_json(url: &str) -> Result<Value> {
+    url_policy::check_str(url).await?;
+    let client = url_policy::guard(proxy::apply(reqwest::Client::builder()))
+        .user_agent(utils::USER_AGENT)
+        .build()?;
+    let resp = client.get(url).send().await?;
+    if !resp.status().is_success() {
+        return Err(anyhow::anyhow!("Failed to fetch {url}: {}", resp.status()));
+    }
+    Ok(serde_json::from_str(&resp.text().await?)?)
+}
+
+/// Community maintained JSON index served over http.
+pub struct JsonIndex {
+    pub url: String,
+}
+
+#[async_trait]
+impl RomIndexProvider for JsonIndex {
+    fn name(&self) -> String {
+        format!("json index {}", self.url)
+    }
+
+    async fn latest(&self, codename: &str, region: Option<&str>) -> Result<Option<RomRelease>> {
+        let index = fetch_json(&fill_template(&self.url, codename, region)?).await?;
+        pick_from_index(&index, codename, region)
+    }
+}
+
+/// JSON index read from a local file, same format as [`JsonIndex`].
+pub struct FileIndex {
+    pub path: PathBuf,
+}
+
+#[async_trait]
+impl RomIndexProvider for FileIndex {
+    fn name(&self) -> String {
+        format!("file index {}", self.path.display())
+    }
+
+    async fn latest(&self, codename: &str, region: Option<&str>) -> Result<Option<RomRelease>> {
+        let index: Value = serde_json::from_str(&tokio::fs::read_to_string(&self.path).await?)?;
+        pick_from_index(&index, codename, region)
+    }
+}
+
+/// LineageOS style updater endpoint answering `{"response": [{"datetime", "url", "version", ..}]}`.
+///
+/// `{codename}` and `{region}` in the url are filled in per request.
+pub struct UpdaterApi {
+    pub url: String,
+}
+
+#[async_trait]
+impl RomIndexProvider for UpdaterApi {
+    fn name(&self) -> String {
+        format!("updater {}", self.url)
+    }
+
+    async fn latest(&self, codename: &str, region: Option<&str>) -> Result<Option<RomRelease>> {
+        let body = fetch_json(&fill_template(&self.url, codename, region)?).await?;
+        Ok(pick_from_updater(&body))
+    }
+}
+
+/// Picks the newest release with a url from an updater response.
+fn pick_from_updater(body: &Value) -> Option<RomRelease> {
+    body["response"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|r| {
+            Some(RomRelease {
+                region: r["region"].as_str().map(|s| s.to_string()),
+                version: r["version"].as_str().map(|s| s.to_string()),
+                url: r["url"].as_str()?.to_string(),
+                security_patch: None,
+                timestamp: r["datetime"].as_u64().unwrap_or(0),
+            })
+        })
+        .max_by_key(|r| r.timestamp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use axum::Router;
+    use axum::extract::Path as UrlPath;
+    use axum::routing::get;
+    use serde_json::json;
+
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/rom_index.json");
+
+    #[test]
+    fn picks_the_newest_release_of_a_region() {
+        let index = json!({
+            "alioth": [
+                { "region": "global", "url": "https://a/old.zip", "timestamp": 1 },
+                { "region": "global", "url": "https://a/new.zip", "timestamp": 3 },
+                { "region": "eea", "url": "https://a/eea.zip", "timestamp": 2 },
+                { "url": "https://a/unknown.zip", "timestamp": 4 },
+            ]
+        });
+        let pick = |region| {
+            pick_from_index(&index, "alioth", region)
+                .unwrap()
+                .map(|r| r.url)
+        };
+        assert_eq!(pick(Some("GLOBAL")).as_deref(), Some("https://a/new.zip"));
+        assert_eq!(pick(Some("eea")).as_deref(), Some("https://a/eea.zip"));
+        assert_eq!(pick(None).as_deref(), Some("https://a/unknown.zip"));
+        assert_eq!(pick(Some("cn")), None);
+        assert!(pick_from_index(&index, "marble", None).unwrap().is_none());
+        assert!(pick_from_index(&json!({ "alioth": [{}] }), "alioth", None).is_err());
+    }
+
+    #[tokio::test]
+    async fn reads_file_indexes() {
+        let index = FileIndex {
+            path: PathBuf::from(FIXTURE),
+        };
+        let latest = index
+            .latest("alioth", Some("global"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(latest.version.as_deref(), Some("OS1.0.5.0.TKHMIXM"));
+        assert_eq!(latest.security_patch.as_deref(), Some("2024-04-01"));
+        let latest = index.latest("alioth", None).await.unwrap().unwrap();
+        assert_eq!(latest.url, "https://example.com/alioth_cn_1.0.9.zip");
+        assert!(index.latest("marble", None).await.unwrap().is_none());
+        assert!(index.latest("unknown", None).await.unwrap().is_none());
+    }
+
+    #[test]
+    fn parses_updater_responses() {
+        let body = json!({
+            "response": [
+                { "datetime": 100, "url": "https://a/100.zip", "version": "21.0" },
+                { "datetime": 300, "version": "22.0" },
+                { "datetime": 200, "url": "https://a/200.zip", "version": "21.0" },
+            ]
+        });
+        let latest = pick_from_updater(&body).unwrap();
+        assert_eq!(latest.url, "https://a/200.zip");
+        assert_eq!(latest.timestamp, 200);
+        assert_eq!(latest.region, None);
+        let body = json!({ "response": [{ "url": "https://a/1.zip", "region": "eea" }] });
+        assert_eq!(
+            pick_from_updater(&body).unwrap().region.as_deref(),
+            Some("eea")
+        );
+        assert!(pick_from_updater(&json!({ "response": [] })).is_none());
+        assert!(pick_from_updater(&json!({ "error": "not found" })).is_none());
+    }
+
+    #[test]
+    fn only_plain_names_fill_templates() {
+        let template = "https://a/{codename}/{region}";
+        assert_eq!(
+            fill_template(template, "alioth", Some("eea_global")).unwrap(),
+            "https://a/alioth/eea_global"
+        );
+        assert_eq!(
+            fill_template(template, "garnet-2", None).unwrap(),
+            "https://a/garnet-2/"
+        );
+        for codename in ["", "a/b", "a?x=1", "a#b", "a&b", "../a", "a b"] {
+            assert!(
+                fill_template(template, codename, None).is_err(),
+                "{codename}"
+            );
+        }
+        assert!(fill_template(template, "alioth", Some("cn/../x")).is_err());
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_the_next_provider() {
+        let mut config = Config::default();
+        config.url_allowlist = vec!["localhost".to_string()];
+        config.url_allowed_ports = Vec::new();
+        config::set(config);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let app = Router::new().route(
+            "/api/{codename}",
+            get(|UrlPath(codename): UrlPath<String>| async move {
+                axum::Json(json!({
+                    "response": [{ "datetime": 1, "url": format!("https://a/{codename}.zip") }]
+                }))
+            }),
+        );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let providers: Vec<Box<dyn RomIndexProvider>> = vec![
+            Box::new(FileIndex {
+                path: PathBuf::from(FIXTURE),
+            }),
+            Box::new(UpdaterApi {
+                url: format!("http://localhost:{port}/api/{{codename}}"),
+            }),
+        ];
+        let (provider, release) = find_latest(&providers, "alioth", Some("cn"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(provider.starts_with("file index"));
+        assert_eq!(release.url, "https://example.com/alioth_cn_1.0.9.zip");
+        let (provider, release) = find_latest(&providers, "garnet", None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(provider.starts_with("updater"));
+        assert_eq!(release.url, "https://a/garnet.zip");
+        assert!(find_latest(&[], "garnet", None).await.is_err());
+        assert!(find_latest(&providers, "garnet?x", None).await.is_err());
+    }
+}
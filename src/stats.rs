@@ -0,0 +1,65 @@
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+/// Counters since the bot started.
+#[derive(Default)]
+struct Counters {
+    downloaded: AtomicU64,
+    uploaded: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+}
+
+static COUNTERS: LazyLock<Counters> = LazyLock::new(Counters::default);
+static STARTED: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+/// Copy of the counters, see [`snapshot`].
+pub struct Snapshot {
+    pub uptime: std::time::Duration,
+    pub downloaded: u64,
+    pub uploaded: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+}
+
+impl Snapshot {
+    /// Share of file cache lookups that found an upload, if there were any.
+    pub fn cache_hit_rate(&self) -> Option<f64> {
+        let total = self.cache_hits + self.cache_misses;
+        (total > 0).then(|| self.cache_hits as f64 / total as f64)
+    }
+}
+
+/// Starts the uptime clock.
+pub fn init() {
+    LazyLock::force(&STARTED);
+}
+
+/// Payload bytes fetched to extract partitions.
+pub fn add_downloaded(bytes: u64) {
+    COUNTERS.downloaded.fetch_add(bytes, Ordering::Relaxed);
+}
+
+/// Bytes of files sent to Telegram.
+pub fn add_uploaded(bytes: u64) {
+    COUNTERS.uploaded.fetch_add(bytes, Ordering::Relaxed);
+}
+
+pub fn cache_lookup(hit: bool) {
+    let counter = match hit {
+        true => &COUNTERS.cache_hits,
+        false => &COUNTERS.cache_misses,
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn snapshot() -> Snapshot {
+    Snapshot {
+        uptime: STARTED.elapsed(),
+        downloaded: COUNTERS.downloaded.load(Ordering::Relaxed),
+        uploaded: COUNTERS.uploaded.load(Ordering::Relaxed),
+        cache_hits: COUNTERS.cache_hits.load(Ordering::Relaxed),
+        cache_misses: COUNTERS.cache_misses.load(Ordering::Relaxed),
+    }
+}
@@ -0,0 +1,148 @@
+//! Cancellation and progress of library calls.
+//!
+//! Long running calls such as [`dump_partition`](crate::payload::dump_partition)
+//! report their [`Stage`] and stop with [`Cancelled`] once the token of the
+//! [`scope`] they run in is cancelled. Outside of a scope they run to the end.
+
+use anyhow::Result;
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+
+tokio::task_local! {
+    static CURRENT: Task;
+}
+
+#[derive(Clone)]
+struct Task {
+    token: CancellationToken,
+    on_stage: Arc<dyn Fn(Stage) + Send + Sync>,
+}
+
+/// Error returned by work stopped through the token of its [`scope`].
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Job cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Stage {
+    Queued,
+    Resolving,
+    Manifest,
+    Extracting,
+    Patching,
+    Uploading,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Queued => "queued",
+            Self::Resolving => "resolving",
+            Self::Manifest => "manifest",
+            Self::Extracting => "extracting",
+            Self::Patching => "patching",
+            Self::Uploading => "uploading",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// Runs `fut` so that cancelling `token` stops it and stage changes inside it
+/// are passed to `on_stage`.
+///
+/// ```
+/// use payload_extract_bot::task::{self, Stage};
+/// use tokio_util::sync::CancellationToken;
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let token = CancellationToken::new();
+/// token.cancel();
+/// let result = task::scope(token, |stage| println!("{stage}"), async {
+///     task::set_stage(Stage::Extracting);
+///     task::check_cancelled()
+/// })
+/// .await;
+/// assert!(result.unwrap_err().is::<task::Cancelled>());
+/// # }
+/// ```
+pub async fn scope<F: Future>(
+    token: CancellationToken,
+    on_stage: impl Fn(Stage) + Send + Sync + 'static,
+    fut: F,
+) -> F::Output {
+    let task = Task {
+        token,
+        on_stage: Arc::new(on_stage),
+    };
+    CURRENT.scope(task, fut).await
+}
+
+pub fn set_stage(stage: Stage) {
+    let _ = CURRENT.try_with(|task| (task.on_stage)(stage));
+}
+
+/// Token of the current scope, outside of a scope it is never cancelled.
+pub fn token() -> CancellationToken {
+    CURRENT
+        .try_with(|task| task.token.clone())
+        .unwrap_or_default()
+}
+
+pub fn is_cancelled() -> bool {
+    token().is_cancelled()
+}
+
+/// Fails with [`Cancelled`] once the current scope has been cancelled.
+pub fn check_cancelled() -> Result<()> {
+    if is_cancelled() {
+        return Err(Cancelled.into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[tokio::test]
+    async fn scope_reports_stages_and_cancellation() {
+        let stages = Arc::new(Mutex::new(Vec::new()));
+        let token = CancellationToken::new();
+        let seen = stages.clone();
+        let cancelled = scope(
+            token.clone(),
+            move |stage| seen.lock().unwrap().push(stage),
+            async {
+                set_stage(Stage::Manifest);
+                assert!(!is_cancelled());
+                token.cancel();
+                set_stage(Stage::Extracting);
+                is_cancelled()
+            },
+        )
+        .await;
+        assert!(cancelled);
+        assert_eq!(
+            *stages.lock().unwrap(),
+            [Stage::Manifest, Stage::Extracting]
+        );
+    }
+
+    #[tokio::test]
+    async fn outside_a_scope_nothing_is_cancelled() {
+        set_stage(Stage::Patching);
+        assert!(!is_cancelled());
+        assert!(check_cancelled().is_ok());
+    }
+}
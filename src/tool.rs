@@ -1,12 +1,13 @@
-use anyhow::Result;
+//! Tools patching runs, downloaded from their latest GitHub releases.
+
+use anyhow::{Context, Result, anyhow};
 use bytes::Bytes;
 use log::{debug, error, info};
 use serde_json::Value;
 use std::env::consts::{ARCH, OS};
 use std::fs;
 use std::io::{Cursor, Read};
-use std::path::PathBuf;
-use std::process::exit;
+use std::path::{Path, PathBuf};
 use zip::ZipArchive;
 
 #[derive(Clone)]
@@ -16,8 +17,13 @@ pub struct Basis {
     suffix: &'static str,
 }
 
+/// A binary downloaded from its latest release into `bin/{os}/{arch}`.
+// Callers await the futures in place, none needs them to be `Send`.
+#[allow(async_fn_in_trait)]
 pub trait Tool {
-    fn from(basis: Basis) -> Self;
+    fn from(basis: Basis) -> Result<Self>
+    where
+        Self: Sized;
     fn get_name(&self) -> String;
     fn get(&self) -> PathBuf;
     async fn init(&self) -> Result<()> {
@@ -30,6 +36,32 @@ pub trait Tool {
         }
     }
     async fn get_latest(&self) -> Result<()>;
+    /// Release tag of the installed binary, recorded when it was downloaded.
+    fn version(&self) -> Option<String> {
+        let version = fs::read_to_string(version_path(&self.get())).ok()?;
+        Some(version.trim().to_string())
+    }
+}
+
+fn version_path(bin: &Path) -> PathBuf {
+    bin.with_extension("version")
+}
+
+/// Replaces `path` in one step, so a running patch never sees a half written binary.
+fn install(path: &Path, content: &[u8], version: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("download");
+    fs::write(&tmp, content)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&tmp, fs::Permissions::from_mode(0o755))?;
+    }
+    fs::rename(&tmp, path)?;
+    fs::write(version_path(path), version)?;
+    Ok(())
 }
 
 #[derive(Clone)]
@@ -39,28 +71,32 @@ pub struct BaseTool {
     path: PathBuf,
 }
 
-#[derive(Clone)]
-pub struct KSUD(BaseTool);
+impl BaseTool {
+    /// `name` under `bin/{os}/{arch}` of the working directory.
+    fn new(basis: Basis, name: &str) -> Result<Self> {
+        let dir = std::env::current_dir().context("Failed to get the working directory")?;
+        let path = dir
+            .join("bin")
+            .join(basis.os)
+            .join(basis.arch)
+            .join(format!("{name}{}", basis.suffix));
+        Ok(Self {
+            basis,
+            name: name.to_string(),
+            path,
+        })
+    }
+}
 
 #[derive(Clone)]
-pub struct MAGISKBOOT(BaseTool);
+pub struct Ksud(BaseTool);
 
-impl Tool for KSUD {
-    fn from(basis: Basis) -> Self {
-        let current_dir = std::env::current_dir().unwrap();
+#[derive(Clone)]
+pub struct MagiskBoot(BaseTool);
 
-        let mut bin = PathBuf::from(current_dir)
-            .join("bin")
-            .join(basis.os)
-            .join(basis.arch);
-        bin.push(format!("{}{}", "ksud", basis.suffix));
-        Self {
-            0: BaseTool {
-                basis: basis.clone(),
-                name: "ksud".to_string(),
-                path: bin,
-            },
-        }
+impl Tool for Ksud {
+    fn from(basis: Basis) -> Result<Self> {
+        Ok(Self(BaseTool::new(basis, "ksud")?))
     }
 
     fn get_name(&self) -> String {
@@ -85,45 +121,27 @@ impl Tool for KSUD {
             }
         );
 
-        let assets = get_assets(api_addr).await?;
+        let (tag, assets) = get_release(api_addr).await?;
         let asset = assets
             .iter()
             .find(|asset| asset["name"].as_str() == Some(assert_name.as_str()))
             .ok_or_else(|| anyhow::anyhow!("'assets' not found in release"))?;
-        info!("Downloading {}...", asset["name"].as_str().unwrap());
+        info!(
+            "Downloading {}...",
+            asset["name"].as_str().unwrap_or_default()
+        );
         let body = download_asset(asset).await?;
-        if let Some(parent) = self.get().parent() {
-            fs::create_dir_all(parent)?;
-        }
 
         info!("Writing {}...", self.get().display());
-        fs::write(self.get(), body)?;
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::PermissionsExt;
-            fs::set_permissions(&self.0.path, fs::Permissions::from_mode(0o755))?;
-        }
-        info!("Download latest {} success", self.0.name);
+        install(&self.get(), &body, &tag)?;
+        info!("Download latest {} {tag} success", self.0.name);
         Ok(())
     }
 }
 
-impl Tool for MAGISKBOOT {
-    fn from(basis: Basis) -> Self {
-        let current_dir = std::env::current_dir().unwrap();
-
-        let mut bin = PathBuf::from(current_dir)
-            .join("bin")
-            .join(basis.os)
-            .join(basis.arch);
-        bin.push(format!("{}{}", "magiskboot", basis.suffix));
-        Self {
-            0: BaseTool {
-                basis: basis.clone(),
-                name: "magiskboot".to_string(),
-                path: bin,
-            },
-        }
+impl Tool for MagiskBoot {
+    fn from(basis: Basis) -> Result<Self> {
+        Ok(Self(BaseTool::new(basis, "magiskboot")?))
     }
 
     fn get_name(&self) -> String {
@@ -139,17 +157,21 @@ impl Tool for MAGISKBOOT {
         let api_addr = "https://api.github.com/repos/topjohnwu/Magisk/releases/latest".to_string();
         let assert_name = "Magisk-v";
 
-        let assets = get_assets(api_addr).await?;
+        let (tag, assets) = get_release(api_addr).await?;
         let asset = assets
             .iter()
-            .find(|asset| asset["name"].as_str().unwrap().starts_with(assert_name))
+            .find(|asset| {
+                asset["name"]
+                    .as_str()
+                    .is_some_and(|name| name.starts_with(assert_name))
+            })
             .ok_or_else(|| anyhow::anyhow!("'assets' not found in release"))?;
 
-        info!("Downloading {}...", asset["name"].as_str().unwrap());
+        info!(
+            "Downloading {}...",
+            asset["name"].as_str().unwrap_or_default()
+        );
         let bytes = download_asset(asset).await?;
-        if let Some(parent) = self.get().parent() {
-            fs::create_dir_all(parent)?;
-        }
 
         info!("Successfully downloaded, unzipping...");
         let reader = Cursor::new(bytes);
@@ -164,26 +186,18 @@ impl Tool for MAGISKBOOT {
             }
         );
 
-        for i in 0..archive.len() {
-            let mut file = archive.by_index(i)?;
-            if file.name() == bin_name {
-                let mut content = Vec::new();
-                file.read_to_end(&mut content)?;
-                fs::write(self.get(), content)?;
-            }
-        }
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::PermissionsExt;
-            fs::set_permissions(&self.0.path, fs::Permissions::from_mode(0o755))?;
-        }
-        info!("Download latest {} success", self.0.name);
+        let mut file = archive.by_name(&bin_name)?;
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)?;
+        install(&self.get(), &content, &tag)?;
+        info!("Download latest {} {tag} success", self.0.name);
         Ok(())
     }
 }
 
-async fn get_assets(url: String) -> Result<Vec<Value>> {
-    let client = reqwest::Client::builder()
+/// Tag and assets of the latest GitHub release.
+async fn get_release(url: String) -> Result<(String, Vec<Value>)> {
+    let client = crate::proxy::apply(reqwest::Client::builder())
         .user_agent(crate::utils::USER_AGENT)
         .build()?;
     let resp = client.get(&url).send().await?;
@@ -198,14 +212,15 @@ async fn get_assets(url: String) -> Result<Vec<Value>> {
     let assets = release["assets"]
         .as_array()
         .ok_or_else(|| anyhow::anyhow!("'assets' not found in release"))?;
-    Ok(assets.clone())
+    let tag = release["tag_name"].as_str().unwrap_or("unknown");
+    Ok((tag.to_string(), assets.clone()))
 }
 
 async fn download_asset(asset: &Value) -> Result<Bytes> {
     let download_url = asset["browser_download_url"]
         .as_str()
         .ok_or_else(|| anyhow::anyhow!("'download_url' not found in release"))?;
-    let client = reqwest::Client::builder()
+    let client = crate::proxy::apply(reqwest::Client::builder())
         .user_agent(crate::utils::USER_AGENT)
         .build()?;
     let resp = client.get(download_url).send().await?;
@@ -217,22 +232,30 @@ async fn download_asset(asset: &Value) -> Result<Bytes> {
     Ok(resp.bytes().await?)
 }
 
+/// The tools of the current platform.
 #[derive(Clone)]
 pub struct ToolManager {
-    ksud: KSUD,
-    magiskboot: MAGISKBOOT,
+    ksud: Ksud,
+    magiskboot: MagiskBoot,
 }
 
-impl Default for ToolManager {
-    fn default() -> Self {
-        let basis = Basis::default();
-        let ksud = <KSUD as Tool>::from(basis.clone());
-        let magiskboot = <MAGISKBOOT as Tool>::from(basis.clone());
-        Self { ksud, magiskboot }
+impl ToolManager {
+    /// Fails on platforms the tools aren't released for.
+    pub fn new() -> Result<Self> {
+        let basis = Basis::current()?;
+        Ok(Self {
+            ksud: <Ksud as Tool>::from(basis.clone())?,
+            magiskboot: <MagiskBoot as Tool>::from(basis)?,
+        })
     }
-}
 
-impl ToolManager {
+    /// Directory the tools of this platform are installed in.
+    pub fn dir(&self) -> PathBuf {
+        let ksud = self.ksud.get();
+        ksud.parent().map(Path::to_path_buf).unwrap_or(ksud)
+    }
+
+    /// Downloads the tools that aren't installed yet.
     pub async fn init(&self) -> Result<()> {
         debug!("Initializing tools");
         self.ksud.init().await?;
@@ -240,34 +263,75 @@ impl ToolManager {
         Ok(())
     }
 
-    pub fn get_magiskboot(&self) -> MAGISKBOOT {
+    /// Names and installed versions of the tools.
+    pub fn versions(&self) -> Vec<(String, Option<String>)> {
+        vec![
+            (self.ksud.get_name(), self.ksud.version()),
+            (self.magiskboot.get_name(), self.magiskboot.version()),
+        ]
+    }
+
+    /// Downloads the latest release of the tool called `name`, or of every tool.
+    pub async fn update(&self, name: Option<&str>) -> Result<()> {
+        match name {
+            None => {
+                self.ksud.get_latest().await?;
+                self.magiskboot.get_latest().await
+            }
+            Some("ksud") => self.ksud.get_latest().await,
+            Some("magiskboot") => self.magiskboot.get_latest().await,
+            Some(name) => Err(anyhow::anyhow!("Unknown tool {name}")),
+        }
+    }
+
+    pub fn get_magiskboot(&self) -> MagiskBoot {
         self.magiskboot.clone()
     }
-    pub fn get_ksud(&self) -> KSUD {
+    pub fn get_ksud(&self) -> Ksud {
         self.ksud.clone()
     }
 }
 
-impl Default for Basis {
-    fn default() -> Self {
+impl Basis {
+    /// The platform this binary runs on.
+    pub fn current() -> Result<Self> {
         let os = match OS {
             "linux" => "linux",
             "android" => "android",
-            _ => "Unknown",
+            _ => return Err(anyhow!("Unsupported platform and arch {OS}/{ARCH}")),
         };
         let arch = match ARCH {
             "x86_64" => "x86_64",
             "aarch64" => "aarch64",
-            _ => "Unknown",
+            _ => return Err(anyhow!("Unsupported platform and arch {OS}/{ARCH}")),
         };
-        if os == "Unknown" || arch == "Unknown" {
-            let msg = format!("Unsupported platform and arch {OS}/{ARCH}");
-            println!("{msg}");
-            error!("{msg}");
-            exit(1);
-        }
-        let suffix = if os == "Windows" { ".exe" } else { "" };
+        Ok(Self {
+            os,
+            arch,
+            suffix: "",
+        })
+    }
+}
 
-        Self { os, arch, suffix }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    fn tools_live_under_bin_of_the_platform() {
+        let tm = ToolManager::new().unwrap();
+        let dir = std::env::current_dir()
+            .unwrap()
+            .join("bin")
+            .join(OS)
+            .join(ARCH);
+        assert_eq!(tm.get_ksud().get(), dir.join("ksud"));
+        assert_eq!(tm.get_magiskboot().get(), dir.join("magiskboot"));
+        assert_eq!(version_path(&tm.get_ksud().get()), dir.join("ksud.version"));
+        assert_eq!(tm.dir(), dir);
     }
 }
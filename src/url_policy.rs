@@ -0,0 +1,386 @@
+use crate::config::{self, Config};
+use crate::proxy;
+use anyhow::{Context, Result};
+use log::warn;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::redirect::Policy;
+use reqwest::{ClientBuilder, Url};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use tokio::runtime::{Handle, RuntimeFlavor};
+
+/// Upper bound of redirects followed by a [`guard`]ed client.
+const MAX_REDIRECTS: usize = 10;
+
+/// Error returned for URLs the policy refuses to fetch.
+#[derive(Debug)]
+pub struct Blocked(String);
+
+impl fmt::Display for Blocked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "URL not allowed: {}", self.0)
+    }
+}
+
+impl std::error::Error for Blocked {}
+
+enum AllowEntry {
+    Host(String),
+    Net(IpAddr, u8),
+}
+
+impl AllowEntry {
+    fn from(s: &str) -> Result<Self> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix.parse::<u8>()?)),
+            None => (s, None),
+        };
+        match addr.parse::<IpAddr>() {
+            Ok(ip) => {
+                let max = if ip.is_ipv4() { 32 } else { 128 };
+                let prefix = prefix.unwrap_or(max);
+                if prefix > max {
+                    return Err(anyhow::anyhow!("Invalid prefix length in {s}"));
+                }
+                Ok(Self::Net(ip, prefix))
+            }
+            Err(_) if prefix.is_none() => Ok(Self::Host(s.to_ascii_lowercase())),
+            Err(e) => Err(anyhow::anyhow!("Invalid network {s}: {e}")),
+        }
+    }
+
+    fn matches_host(&self, host: &str) -> bool {
+        match self {
+            Self::Host(h) => host == h || host.ends_with(&format!(".{h}")),
+            Self::Net(..) => false,
+        }
+    }
+
+    fn matches_ip(&self, ip: IpAddr) -> bool {
+        let Self::Net(net, prefix) = self else {
+            return false;
+        };
+        let (net, ip, bits) = match (net, ip) {
+            (IpAddr::V4(n), IpAddr::V4(i)) => (u32::from(*n) as u128, u32::from(i) as u128, 32),
+            (IpAddr::V6(n), IpAddr::V6(i)) => (u128::from(*n), u128::from(i), 128),
+            _ => return false,
+        };
+        let shift = bits - *prefix as u32;
+        shift >= bits || (net >> shift) == (ip >> shift)
+    }
+}
+
+struct UrlPolicy {
+    schemes: Vec<String>,
+    ports: Vec<u16>,
+    allowlist: Vec<AllowEntry>,
+}
+
+impl UrlPolicy {
+    fn from(config: &Config) -> Result<Self> {
+        Ok(Self {
+            schemes: config.url_allowed_schemes.clone(),
+            ports: config.url_allowed_ports.clone(),
+            allowlist: config
+                .url_allowlist
+                .iter()
+                .map(|s| {
+                    AllowEntry::from(s).with_context(|| format!("Invalid URL_ALLOWLIST entry {s}"))
+                })
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// Checks all but the addresses of the host, returns the host and port left to
+    /// resolve, `None` if the URL is allowed as is.
+    fn check_url(&self, url: &Url) -> Result<Option<(String, u16)>, Blocked> {
+        if !self.schemes.iter().any(|s| s == url.scheme()) {
+            return Err(Blocked(format!("scheme {} is not allowed", url.scheme())));
+        }
+        let port = url
+            .port_or_known_default()
+            .ok_or_else(|| Blocked("missing port".to_string()))?;
+        if !self.ports.is_empty() && !self.ports.contains(&port) {
+            return Err(Blocked(format!("port {port} is not allowed")));
+        }
+        let host = url
+            .host_str()
+            .ok_or_else(|| Blocked("missing host".to_string()))?
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_ascii_lowercase();
+        if self.allows_host(&host) {
+            return Ok(None);
+        }
+        match host.parse::<IpAddr>() {
+            Ok(ip) => self.check_addrs(&host, &[ip]).map(|_| None),
+            Err(_) => Ok(Some((host, port))),
+        }
+    }
+
+    fn allows_host(&self, host: &str) -> bool {
+        self.allowlist.iter().any(|e| e.matches_host(host))
+    }
+
+    /// Every address of `host` must be public or allowlisted.
+    fn check_addrs(&self, host: &str, addrs: &[IpAddr]) -> Result<(), Blocked> {
+        if addrs.is_empty() {
+            return Err(Blocked(format!("{host} doesn't resolve")));
+        }
+        for ip in addrs {
+            if !is_public(*ip) && !self.allowlist.iter().any(|e| e.matches_ip(*ip)) {
+                return Err(Blocked(format!(
+                    "{host} resolves to non-public address {ip}"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks a redirect target, resolving its host here: through a proxy the
+    /// client never resolves it, so [`PolicyResolver`] doesn't see it.
+    fn check_redirect(&self, url: &Url) -> Result<(), Blocked> {
+        let Some((host, port)) = self.check_url(url)? else {
+            return Ok(());
+        };
+        let addrs = lookup_blocking(&host, port)
+            .map_err(|e| Blocked(format!("{host} doesn't resolve: {e}")))?;
+        self.check_addrs(&host, &addrs)
+    }
+}
+
+/// Resolves `host` from the synchronous redirect policy, off the async workers where possible.
+fn lookup_blocking(host: &str, port: u16) -> std::io::Result<Vec<IpAddr>> {
+    let lookup = || Ok((host, port).to_socket_addrs()?.map(|a| a.ip()).collect());
+    match Handle::try_current().map(|h| h.runtime_flavor()) {
+        Ok(RuntimeFlavor::MultiThread) => tokio::task::block_in_place(lookup),
+        _ => lookup(),
+    }
+}
+
+pub fn validate(config: &Config) -> Result<()> {
+    UrlPolicy::from(config).map(drop)
+}
+
+/// Checks `url` before it is fetched.
+///
+/// Every address the host resolves to must be public unless the host or
+/// address is allowlisted. Clients passed through [`guard`] check again on
+/// every redirect and connection.
+pub async fn check(url: &Url) -> Result<()> {
+    // Built per check, so running jobs keep the policy of their config.
+    let policy = UrlPolicy::from(&config::get())?;
+    let blocked = |e: Blocked| {
+        warn!("Blocked {url}: {}", e.0);
+        anyhow::Error::new(e)
+    };
+    let Some((host, port)) = policy.check_url(url).map_err(blocked)? else {
+        return Ok(());
+    };
+    let addrs: Vec<IpAddr> = tokio::net::lookup_host((host.as_str(), port))
+        .await?
+        .map(|addr| addr.ip())
+        .collect();
+    policy.check_addrs(&host, &addrs).map_err(blocked)
+}
+
+pub async fn check_str(url: &str) -> Result<()> {
+    check(&Url::parse(url)?).await
+}
+
+/// Makes a client enforce the policy on every redirect and every address it
+/// connects to, so a host can't pass [`check`] and then send the fetch elsewhere.
+///
+/// Redirect targets are resolved and checked by the redirect policy itself, since
+/// through a proxy the proxy resolves them. The resolver only lets the proxy
+/// hosts through, which are never the target of a fetch.
+pub fn guard(builder: ClientBuilder) -> ClientBuilder {
+    builder
+        .redirect(Policy::custom(|attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                return attempt.error(anyhow::anyhow!("Too many redirects"));
+            }
+            let checked = UrlPolicy::from(&config::get())
+                .and_then(|policy| Ok(policy.check_redirect(attempt.url())?));
+            match checked {
+                Ok(()) => attempt.follow(),
+                Err(e) => {
+                    warn!("Blocked redirect to {}: {e}", attempt.url());
+                    attempt.error(e)
+                }
+            }
+        }))
+        .dns_resolver(Arc::new(PolicyResolver))
+}
+
+/// Resolves host names for [`guard`]ed clients, refusing hosts with blocked addresses.
+struct PolicyResolver;
+
+impl Resolve for PolicyResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let host = name.as_str().to_ascii_lowercase();
+            let addrs: Vec<SocketAddr> =
+                tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+            let policy = UrlPolicy::from(&config::get())?;
+            // Only the proxy itself connects here unchecked, targets reached through
+            // it were checked by `check` and the redirect policy.
+            if !policy.allows_host(&host) && !proxy::is_proxy_host(&host) {
+                let ips: Vec<IpAddr> = addrs.iter().map(|a| a.ip()).collect();
+                if let Err(e) = policy.check_addrs(&host, &ips) {
+                    warn!("Blocked connection to {host}: {}", e.0);
+                    return Err(e.into());
+                }
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(v4) => is_public_v4(v4),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // 0.0.0.0/8, 100.64.0.0/10 (CGNAT), 198.18.0.0/15 (benchmarking), 240.0.0.0/4
+        || a == 0
+        || (a == 100 && (b & 0xc0) == 64)
+        || (a == 198 && (b & 0xfe) == 18)
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        || ip.is_unique_local()
+        || ip.is_unicast_link_local()
+        // 2001:db8::/32 documentation
+        || (first == 0x2001 && ip.segments()[1] == 0x0db8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::response::Redirect;
+    use axum::routing::get;
+    use reqwest::Client;
+
+    fn policy(allowlist: &[&str]) -> UrlPolicy {
+        let mut config = Config::default();
+        config.url_allowlist = allowlist.iter().map(|s| s.to_string()).collect();
+        UrlPolicy::from(&config).unwrap()
+    }
+
+    fn check_url(policy: &UrlPolicy, url: &str) -> Result<Option<(String, u16)>, Blocked> {
+        policy.check_url(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn blocks_non_public_addresses() {
+        let policy = policy(&[]);
+        for url in [
+            "http://127.0.0.1/",
+            "http://[::1]/",
+            "http://169.254.169.254/latest/meta-data",
+            "http://10.0.0.1/",
+            "http://100.64.0.1/",
+            "http://[::ffff:192.168.1.1]/",
+            "http://[fd00::1]/",
+        ] {
+            assert!(check_url(&policy, url).is_err(), "{url}");
+        }
+        assert!(matches!(check_url(&policy, "http://1.1.1.1/"), Ok(None)));
+        assert!(matches!(
+            check_url(&policy, "https://example.com/rom.zip"),
+            Ok(Some((host, 443))) if host == "example.com"
+        ));
+    }
+
+    #[test]
+    fn checks_scheme_and_port() {
+        let policy = policy(&[]);
+        assert!(check_url(&policy, "ftp://example.com/").is_err());
+        assert!(check_url(&policy, "file:///etc/passwd").is_err());
+        assert!(check_url(&policy, "http://example.com:8080/").is_err());
+        assert!(check_url(&policy, "http://example.com:80/").is_ok());
+    }
+
+    #[test]
+    fn allowlist_matches_hosts_and_networks() {
+        let policy = policy(&["mirror.lan", "192.168.1.0/24"]);
+        assert!(matches!(check_url(&policy, "http://mirror.lan/"), Ok(None)));
+        assert!(matches!(
+            check_url(&policy, "http://files.mirror.lan/"),
+            Ok(None)
+        ));
+        assert!(matches!(
+            check_url(&policy, "http://192.168.1.7/"),
+            Ok(None)
+        ));
+        assert!(check_url(&policy, "http://192.168.2.7/").is_err());
+        assert!(check_url(&policy, "http://notmirror.lan/").is_ok_and(|r| r.is_some()));
+
+        let private: IpAddr = "10.1.2.3".parse().unwrap();
+        let allowed: IpAddr = "192.168.1.3".parse().unwrap();
+        let public: IpAddr = "8.8.8.8".parse().unwrap();
+        assert!(policy.check_addrs("host", &[public, allowed]).is_ok());
+        assert!(policy.check_addrs("host", &[public, private]).is_err());
+        assert!(policy.check_addrs("host", &[]).is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn redirects_are_resolved_and_checked() {
+        let check =
+            |policy: &UrlPolicy, url: &str| policy.check_redirect(&Url::parse(url).unwrap());
+        assert!(check(&policy(&[]), "http://localhost/").is_err());
+        assert!(check(&policy(&["localhost"]), "http://localhost/").is_ok());
+        assert!(check(&policy(&["127.0.0.0/8", "::1"]), "http://localhost/").is_ok());
+        assert!(check(&policy(&[]), "http://1.1.1.1/").is_ok());
+        assert!(check(&policy(&[]), "http://doesnt-resolve.invalid/").is_err());
+    }
+
+    #[tokio::test]
+    async fn guard_blocks_redirects_to_non_public_addresses() {
+        config::set_for_tests();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let target = format!("http://127.0.0.1:{port}/ok");
+        let app = Router::new().route("/ok", get(|| async { "ok" })).route(
+            "/redirect",
+            get(move || async move { Redirect::temporary(&target) }),
+        );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let client = guard(Client::builder()).build().unwrap();
+        let ok = client
+            .get(format!("http://localhost:{port}/ok"))
+            .send()
+            .await
+            .unwrap();
+        assert!(ok.status().is_success());
+        let err = client
+            .get(format!("http://localhost:{port}/redirect"))
+            .send()
+            .await
+            .unwrap_err();
+        assert!(err.is_redirect(), "{err:?}");
+    }
+}
@@ -1,10 +1,164 @@
+use anyhow::Result;
+use log::{error, info};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
 pub const USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/114.0.0.0 Safari/537.36";
 
-pub fn to_tg_md(s: String) -> String {
-    s.replace("-", "\\-")
-        .replace(".", "\\.")
-        .replace("(", "\\(")
-        .replace(")", "\\)")
-        .replace("+", "\\+")
-        .replace("#", "\\#")
+pub fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// Converts a shell style glob (`*`, `?`) into an anchored regex.
+pub fn glob_to_regex(glob: &str) -> anyhow::Result<regex::Regex> {
+    let pattern = regex::escape(glob).replace("\\*", ".*").replace("\\?", ".");
+    Ok(regex::Regex::new(&format!("^{pattern}$"))?)
+}
+
+/// Parses sizes like `512`, `64K`, `100M` or `2G` into bytes.
+///
+/// ```
+/// use payload_extract_bot::utils::parse_size;
+///
+/// assert_eq!(parse_size("64K").unwrap(), 64 * 1024);
+/// assert_eq!(parse_size("1.5gb").unwrap(), 3 << 29);
+/// assert!(parse_size("10T").is_err());
+/// ```
+pub fn parse_size(s: &str) -> anyhow::Result<u64> {
+    let s = s.trim().to_ascii_uppercase();
+    let s = s.trim_end_matches('B');
+    let (num, unit) = match s.find(|c: char| !c.is_ascii_digit() && c != '.') {
+        Some(idx) => s.split_at(idx),
+        None => (s, ""),
+    };
+    let multiplier: u64 = match unit {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => return Err(anyhow::anyhow!("Invalid size unit: {unit}")),
+    };
+    let num: f64 = num
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid size: {s}"))?;
+    Ok((num * multiplier as f64) as u64)
+}
+
+/// Formats a duration as `1h2m3s`, leaving out leading zero units.
+pub fn format_duration(d: std::time::Duration) -> String {
+    let secs = d.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{s}s"),
+        (0, m, s) => format!("{m}m{s}s"),
+        (h, m, s) => format!("{h}h{m}m{s}s"),
+    }
+}
+
+/// Hex encoded SHA-256 of the file at `path`.
+pub fn sha256_file(path: &std::path::Path) -> anyhow::Result<String> {
+    use sha2::{Digest, Sha256};
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Total size of the files under `path`, unreadable entries count as empty.
+pub fn dir_size(path: &std::path::Path) -> u64 {
+    let Ok(meta) = std::fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !meta.is_dir() {
+        return meta.len();
+    }
+    std::fs::read_dir(path)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| dir_size(&entry.path()))
+        .sum()
+}
+
+/// Where [`TempDir`]s are created, emptied at startup.
+pub const TEMP_ROOT: &str = "tmp";
+
+/// Directory under [`TEMP_ROOT`] for the files of one call, removed with its contents when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Result<Self> {
+        let ts = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+        let dir = Path::new(TEMP_ROOT).join(ts.to_string());
+        fs::create_dir_all(&dir)?;
+        Ok(Self(dir))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        info!("Cleaning up temporary directory: {}", self.0.display());
+        if let Err(e) = fs::remove_dir_all(&self.0) {
+            error!(
+                "Failed to clean up temp directory {}: {e}",
+                self.0.display()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size(" 100M ").unwrap(), 100 << 20);
+        assert_eq!(parse_size("2G").unwrap(), 2 << 30);
+        assert!(parse_size("").is_err());
+        assert!(parse_size("M").is_err());
+    }
+
+    #[test]
+    fn formats_durations() {
+        assert_eq!(format_duration(Duration::from_secs(59)), "59s");
+        assert_eq!(format_duration(Duration::from_secs(61)), "1m1s");
+        assert_eq!(format_duration(Duration::from_secs(86400)), "24h0m0s");
+    }
+
+    #[test]
+    fn globs_are_anchored() {
+        let re = glob_to_regex("*boot?.img").unwrap();
+        assert!(re.is_match("vendor_boot1.img"));
+        assert!(!re.is_match("boot.img"));
+        assert!(!re.is_match("boot1.img.bak"));
+        assert!(glob_to_regex("a.b").unwrap().is_match("a.b"));
+        assert!(!glob_to_regex("a.b").unwrap().is_match("axb"));
+    }
+
+    #[test]
+    fn quotes_csv_fields() {
+        assert_eq!(csv_field("boot"), "boot");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn temp_dirs_are_removed_on_drop() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().to_path_buf();
+        fs::write(path.join("file"), b"data").unwrap();
+        assert_eq!(dir_size(&path), 4);
+        drop(dir);
+        assert!(!path.exists());
+    }
 }
@@ -0,0 +1,76 @@
+use crate::config::Config;
+use anyhow::{Context, Result};
+use axum_server::Handle;
+use axum_server::tls_rustls::RustlsConfig;
+use log::{error, info};
+use std::convert::Infallible;
+use teloxide::Bot;
+use teloxide::types::InputFile;
+use teloxide::update_listeners::UpdateListener;
+use teloxide::update_listeners::webhooks::{self, Options};
+
+/// Registers the webhook and serves updates on `WEBHOOK_LISTEN`.
+///
+/// TLS is terminated here when `WEBHOOK_TLS_CERT` and `WEBHOOK_TLS_KEY` are set,
+/// otherwise plain http is served for a reverse proxy in front.
+pub async fn listener(bot: Bot, config: &Config) -> Result<impl UpdateListener<Err = Infallible>> {
+    let url = config
+        .webhook_url
+        .as_deref()
+        .context("WEBHOOK_URL is not set")?
+        .parse()
+        .context("Invalid WEBHOOK_URL")?;
+    let address = config
+        .webhook_listen
+        .parse()
+        .context("Invalid WEBHOOK_LISTEN")?;
+    let mut options = Options::new(address, url);
+    options.secret_token = config.webhook_secret.clone();
+    options.drop_pending_updates = config.webhook_drop_pending_updates;
+    if config.webhook_self_signed {
+        let cert = config
+            .webhook_tls_cert
+            .clone()
+            .context("WEBHOOK_SELF_SIGNED needs WEBHOOK_TLS_CERT")?;
+        options.certificate = Some(InputFile::file(cert));
+    }
+    info!("Serving webhook {} on {address}", options.url);
+
+    let tls = match (&config.webhook_tls_cert, &config.webhook_tls_key) {
+        (Some(cert), Some(key)) => Some({
+            // Dependencies enable several rustls backends, so none is picked by default.
+            let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+            RustlsConfig::from_pem_file(cert, key)
+                .await
+                .context("Failed to load WEBHOOK_TLS_CERT or WEBHOOK_TLS_KEY")?
+        }),
+        _ => None,
+    };
+    let (mut listener, stop_flag, app) = webhooks::axum_to_router(bot, options).await?;
+    let stop_token = listener.stop_token();
+    let handle = Handle::new();
+    tokio::spawn({
+        let handle = handle.clone();
+        async move {
+            stop_flag.await;
+            handle.graceful_shutdown(None);
+        }
+    });
+    tokio::spawn(async move {
+        let app = app.into_make_service();
+        let served = match tls {
+            Some(tls) => {
+                axum_server::bind_rustls(address, tls)
+                    .handle(handle)
+                    .serve(app)
+                    .await
+            }
+            None => axum_server::bind(address).handle(handle).serve(app).await,
+        };
+        if let Err(e) = served {
+            error!("Webhook server error: {e}");
+            stop_token.stop();
+        }
+    });
+    Ok(listener)
+}